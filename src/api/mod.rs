@@ -47,6 +47,18 @@ pub struct SyncServer {
     pub pfp: Option<String>,
     pub name: Option<String>,
     pub idx: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hide_images: bool,
+}
+
+/// A named group of servers in the sidebar. Servers refer to their folder by name.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Folder {
+    pub name: String,
+    #[serde(default)]
+    pub collapsed: bool,
 }
 
 #[derive(Deserialize)]
@@ -54,6 +66,8 @@ pub struct SyncData {
     pub user_uuid: i64,
     pub uname: String,
     pub pfp: String,
+    #[serde(default)]
+    pub folders: Option<Vec<Folder>>, // not every sync server stores these
 }
 
 #[derive(Deserialize, Clone)]
@@ -106,7 +120,7 @@ pub enum Request {
     #[serde(rename = "list_channels")]    ListChannels,
     #[serde(rename = "history")]          History { num: u32, channel: i64, before_message: Option<i64> },
     #[serde(rename = "pfp")]              Pfp { data: String },
-    #[serde(rename = "sync_set")]         SyncSet { uname: String, pfp: String },
+    #[serde(rename = "sync_set")]         SyncSet { uname: String, pfp: String, #[serde(skip_serializing_if = "Option::is_none")] folders: Option<Vec<Folder>> },
     #[serde(rename = "sync_get")]         SyncGet,
     #[serde(rename = "sync_set_servers")] SyncSetServers { severs: Vec<SyncServer> },
     #[serde(rename = "sync_get_servers")] SyncGetServers,
@@ -133,6 +147,7 @@ pub enum Response {
     #[serde(rename = "get_emoji")]        GetEmoji       { status: Status, data: Option<Emoji> },
     #[serde(rename = "list_emoji")]       ListEmoji      { status: Status, data: Option<Vec<(String, i64)>> },
     #[serde(rename = "sync_get")]         SyncGet        { status: Status, #[serde(flatten)] data: Option<SyncData> },
+    #[serde(rename = "sync_set")]         SyncSet        { status: Status },
     #[serde(rename = "content")]          Content        { status: Status, #[serde(flatten)] message: Message },
     #[serde(rename = "API_version")]      APIVersion     { status: Status, version: [u8; 3] },
     #[serde(rename = "send")]             Send           { status: Status, message: i64, },
@@ -162,6 +177,7 @@ impl Response {
             GetEmoji { status, .. } => status,
             ListEmoji { status, .. } => status,
             SyncGet { status, .. } => status,
+            SyncSet { status } => status,
             Content { status, .. } => status,
             APIVersion { status, .. } => status,
             Send { status, .. } => status,
@@ -188,6 +204,7 @@ impl Response {
             GetEmoji { .. } => "GetEmojiResponse",
             ListEmoji { .. } => "ListEmojiResponse",
             SyncGet { .. } => "SyncGetResponse",
+            SyncSet { .. } => "SyncSetResponse",
             Content { .. } => "ContentResponse",
             APIVersion { .. } => "APIVersion",
             Send { .. } => "SendResponse",
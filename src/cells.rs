@@ -0,0 +1,573 @@
+use crate::unicode;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::rc::Rc;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A colour as set by an SGR escape code
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SgrColour {
+    Basic(u8), // the code itself, like 31 or 91 for foregrounds and 41 or 101 for backgrounds
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// Everything about how a cell looks apart from its character
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+struct Style {
+    fg: Option<SgrColour>,
+    bg: Option<SgrColour>,
+    bold: bool,
+    faint: bool,
+    italic: bool,
+    underline: bool,
+    invert: bool,
+    strikethrough: bool,
+}
+
+impl Style {
+    /// Update the style from the parameters of an `ESC [ ... m` sequence
+    fn apply_sgr(&mut self, params: &str) {
+        let params: Vec<u8> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.faint = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.invert = true,
+                9 => self.strikethrough = true,
+                21 | 22 => {
+                    self.bold = false;
+                    self.faint = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.invert = false,
+                29 => self.strikethrough = false,
+                code @ (30..=37 | 90..=97) => self.fg = Some(SgrColour::Basic(code)),
+                39 => self.fg = None,
+                code @ (40..=47 | 100..=107) => self.bg = Some(SgrColour::Basic(code)),
+                49 => self.bg = None,
+                ground @ (38 | 48) => {
+                    let (colour, len) = match params.get(i + 1) {
+                        Some(5) => (params.get(i + 2).map(|&idx| SgrColour::Indexed(idx)), 3),
+                        Some(2) => match params.get(i + 2..i + 5) {
+                            Some(&[r, g, b]) => (Some(SgrColour::Rgb(r, g, b)), 5),
+                            _ => (None, 5),
+                        },
+                        _ => (None, 1),
+                    };
+                    if ground == 38 {
+                        self.fg = colour;
+                    } else {
+                        self.bg = colour;
+                    }
+                    i += len;
+                    continue;
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+    }
+
+    /// One escape sequence that switches to this style from any other
+    fn to_sgr(self) -> String {
+        let mut out = String::from("\x1b[0");
+        for (on, code) in [
+            (self.bold, 1),
+            (self.faint, 2),
+            (self.italic, 3),
+            (self.underline, 4),
+            (self.invert, 7),
+            (self.strikethrough, 9),
+        ] {
+            if on {
+                out.push_str(&format!(";{}", code));
+            }
+        }
+        for (colour, ground) in [(self.fg, 38), (self.bg, 48)] {
+            match colour {
+                Some(SgrColour::Basic(code)) => out.push_str(&format!(";{}", code)),
+                Some(SgrColour::Indexed(idx)) => out.push_str(&format!(";{};5;{}", ground, idx)),
+                Some(SgrColour::Rgb(r, g, b)) => {
+                    out.push_str(&format!(";{};2;{};{};{}", ground, r, g, b))
+                }
+                None => (),
+            }
+        }
+        out.push('m');
+        out
+    }
+}
+
+/// What's drawn in a cell
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Glyph {
+    Char(char),
+    Cluster(Box<str>), // a grapheme made of more than one char, like 👩‍💻 or é with a combining accent
+    Continuation,      // the right half of a wide grapheme in the cell before
+}
+
+impl Glyph {
+    fn as_str(&self) -> std::borrow::Cow<'_, str> {
+        match self {
+            Glyph::Char(ch) => ch.to_string().into(),
+            Glyph::Cluster(text) => (**text).into(),
+            Glyph::Continuation => "".into(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Cell {
+    glyph: Glyph,
+    style: Style,
+    link: Option<Rc<str>>, // the parameters and URL of the OSC 8 hyperlink it's part of
+}
+
+const BLANK: Cell = Cell {
+    glyph: Glyph::Char(' '),
+    style: Style {
+        fg: None,
+        bg: None,
+        bold: false,
+        faint: false,
+        italic: false,
+        underline: false,
+        invert: false,
+        strikethrough: false,
+    },
+    link: None,
+};
+
+/// A copy of the screen that everything is drawn into first. It understands the escape codes that
+/// the drawing code uses, and remembers what's already on the terminal so that `present` only has
+/// to send the cells that changed.
+pub struct CellBuffer {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    shown: Vec<Option<Cell>>, // what the terminal has on it, None if we don't know
+    cursor: (u16, u16),       // 0-based, unlike termion
+    style: Style,
+    link: Option<Rc<str>>,
+    cursor_visible: bool,
+    shown_cursor_visible: Option<bool>,
+    pending: Vec<u8>, // the start of an escape code or character that was cut off at the end of a write
+    last_put: Option<(u16, u16)>, // where the last grapheme went, in case the next char is part of it
+}
+
+impl CellBuffer {
+    pub fn new(width: u16, height: u16) -> Self {
+        let size = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            cells: vec![BLANK; size],
+            shown: vec![None; size],
+            cursor: (0, 0),
+            style: Style::default(),
+            link: None,
+            cursor_visible: true,
+            shown_cursor_visible: None,
+            pending: Vec::new(),
+            last_put: None,
+        }
+    }
+
+    /// Start again with a blank screen of a new size. Everything gets sent again on the next `present`.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        if (width, height) != (self.width, self.height) {
+            *self = Self::new(width, height);
+        }
+    }
+
+    /// Send everything that's changed since the last time to the terminal, in one write
+    pub fn present<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let mut buffer = String::new();
+        if self.shown.iter().all(Option::is_none) {
+            // nothing is known about the terminal, so get rid of whatever else is on it
+            write!(buffer, "{}", termion::clear::All).unwrap();
+        }
+        let mut style = None; // we don't know what the terminal's style is until we set it
+        let mut link = None;
+        let mut pos = None;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let idx = self.index(x, y);
+                let cell = &self.cells[idx];
+                if self.shown[idx].as_ref() == Some(cell) {
+                    continue;
+                }
+                self.shown[idx] = Some(cell.clone());
+                if cell.glyph == Glyph::Continuation {
+                    continue; // already drawn by the wide grapheme before it
+                }
+                if pos != Some((x, y)) {
+                    write!(buffer, "{}", termion::cursor::Goto(x + 1, y + 1)).unwrap();
+                }
+                if style != Some(cell.style) {
+                    buffer.push_str(&cell.style.to_sgr());
+                    style = Some(cell.style);
+                }
+                if link != cell.link {
+                    buffer.push_str(&hyperlink(cell.link.as_deref()));
+                    link = cell.link.clone();
+                }
+                let text = cell.glyph.as_str();
+                buffer.push_str(&text);
+                pos = Some((x + unicode::grapheme_width(&text) as u16, y));
+            }
+        }
+        if style.is_some() {
+            write!(buffer, "{}", termion::style::Reset).unwrap();
+        }
+        if link.is_some() {
+            buffer.push_str(&hyperlink(None));
+        }
+
+        if self.shown_cursor_visible != Some(self.cursor_visible) {
+            if self.cursor_visible {
+                write!(buffer, "{}", termion::cursor::Show).unwrap();
+            } else {
+                write!(buffer, "{}", termion::cursor::Hide).unwrap();
+            }
+            self.shown_cursor_visible = Some(self.cursor_visible);
+        }
+        let (x, y) = self.cursor;
+        let cursor = termion::cursor::Goto(x.saturating_add(1), y.saturating_add(1));
+        write!(buffer, "{}", cursor).unwrap();
+        out.write_all(buffer.as_bytes())?;
+        out.flush()
+    }
+
+    fn index(&self, x: u16, y: u16) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    /// Replace one cell, blanking the other half of any wide grapheme that gets cut in two
+    fn set(&mut self, x: u16, y: u16, glyph: Glyph) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let idx = self.index(x, y);
+        if self.cells[idx].glyph == Glyph::Continuation && x > 0 {
+            self.cells[idx - 1].glyph = Glyph::Char(' ');
+        }
+        if x + 1 < self.width && self.cells[idx + 1].glyph == Glyph::Continuation {
+            self.cells[idx + 1].glyph = Glyph::Char(' ');
+        }
+        self.cells[idx] = Cell {
+            glyph,
+            style: self.style,
+            link: self.link.clone(),
+        };
+    }
+
+    fn put(&mut self, ch: char) {
+        if self.extend_last(ch) {
+            return;
+        }
+        let width = unicode::grapheme_width(ch.encode_utf8(&mut [0; 4]));
+        if width == 0 {
+            return; // e.g. a combining accent with nothing before it
+        }
+        let (x, y) = self.cursor;
+        self.cursor.0 = x.saturating_add(width as u16);
+        if width == 2 && x + 1 >= self.width {
+            // doesn't fit, the terminal would wrap it onto the next line
+            self.set(x, y, Glyph::Char(' '));
+            self.last_put = None;
+            return;
+        }
+        self.set(x, y, Glyph::Char(ch));
+        if width == 2 {
+            self.set(x + 1, y, Glyph::Continuation);
+        }
+        self.last_put = Some((x, y));
+    }
+
+    /// Add `ch` onto the grapheme just before the cursor if it's part of it, like the
+    /// second half of a ZWJ sequence. Returns false if `ch` starts a new grapheme.
+    fn extend_last(&mut self, ch: char) -> bool {
+        let Some((x, y)) = self.last_put else {
+            return false;
+        };
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let idx = self.index(x, y);
+        let mut text = self.cells[idx].glyph.as_str().into_owned();
+        let old_width = unicode::grapheme_width(&text) as u16;
+        if self.cursor != (x + old_width, y) {
+            return false;
+        }
+        text.push(ch);
+        if text.graphemes(true).count() != 1 {
+            return false;
+        }
+        let new_width = unicode::grapheme_width(&text) as u16;
+        self.cells[idx].glyph = if new_width == 2 && x + 1 >= self.width {
+            Glyph::Char(' ') // it no longer fits, like in `put`
+        } else {
+            Glyph::Cluster(text.into())
+        };
+        if new_width > old_width {
+            self.set(x + 1, y, Glyph::Continuation);
+        }
+        self.cursor.0 = x + new_width;
+        true
+    }
+
+    /// Act on one `ESC [ ... final` sequence
+    fn csi(&mut self, params: &str, command: char) {
+        let nums: Vec<u16> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        match (params, command) {
+            (_, 'm') => self.style.apply_sgr(params),
+            (_, 'H') | (_, 'f') => {
+                let row = nums.first().copied().unwrap_or(1).max(1);
+                let col = nums.get(1).copied().unwrap_or(1).max(1);
+                self.cursor = (col - 1, row - 1);
+                self.last_put = None;
+            }
+            ("2", 'J') => {
+                self.cells.fill(BLANK);
+                self.last_put = None;
+            }
+            ("?25", 'l') => self.cursor_visible = false,
+            ("?25", 'h') => self.cursor_visible = true,
+            _ => (), // nothing we draw uses anything else
+        }
+    }
+
+    /// Act on one `ESC ] ... ST` sequence. Only hyperlinks are used, everything else is ignored.
+    fn osc(&mut self, payload: &str) {
+        if let Some(link) = payload.strip_prefix("8;") {
+            // "params;url", where an empty url ends the link
+            self.link = match link.split_once(';') {
+                Some((_, "")) | None => None,
+                Some(_) => Some(link.into()),
+            };
+        }
+    }
+
+    /// Handle as much of `text` as possible, returning how many bytes were used.
+    /// Anything left over is an escape code that hasn't been finished yet.
+    fn parse(&mut self, text: &str) -> usize {
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, ch)) = chars.next() {
+            match ch {
+                '\x1b' => {
+                    match chars.next() {
+                        Some((_, '[')) => (),
+                        Some((_, ']')) => {
+                            // ends with either BEL or ESC \
+                            let payload_start = start + 2;
+                            let end = loop {
+                                match chars.next() {
+                                    Some((end, '\x07')) => break end,
+                                    Some((end, '\x1b')) => match chars.peek() {
+                                        Some((_, '\\')) => {
+                                            chars.next();
+                                            break end;
+                                        }
+                                        Some(_) => (), // an ESC on its own doesn't end it
+                                        None => return start,
+                                    },
+                                    Some(_) => (),
+                                    None => return start,
+                                }
+                            };
+                            self.osc(&text[payload_start..end]);
+                            continue;
+                        }
+                        Some(_) => continue, // not a CSI, skip it
+                        None => return start,
+                    }
+                    let params_start = start + 2;
+                    let Some((end, command)) = chars.find(|(_, c)| ('\x40'..='\x7e').contains(c))
+                    else {
+                        return start;
+                    };
+                    self.csi(&text[params_start..end], command);
+                }
+                '\r' => {
+                    self.cursor.0 = 0;
+                    self.last_put = None;
+                }
+                '\n' => {
+                    self.cursor.1 = self.cursor.1.saturating_add(1);
+                    self.last_put = None;
+                }
+                ch if ch.is_control() => (),
+                ch => self.put(ch),
+            }
+        }
+        text.len()
+    }
+}
+
+impl Write for CellBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let pending = std::mem::take(&mut self.pending);
+        // leave any half-written UTF-8 character for the next write
+        let valid = match std::str::from_utf8(&pending) {
+            Ok(text) => text,
+            Err(e) if e.error_len().is_none() => {
+                std::str::from_utf8(&pending[..e.valid_up_to()]).unwrap()
+            }
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let used = self.parse(valid);
+        self.pending = pending[used..].to_vec();
+        Ok(buf.len())
+    }
+
+    /// Nothing is written to the terminal until `present`
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The OSC 8 sequence that starts a hyperlink, or ends one if `link` is None
+fn hyperlink(link: Option<&str>) -> String {
+    format!("\x1b]8;{}\x1b\\", link.unwrap_or(";"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use termion::cursor::Goto;
+
+    fn row(buffer: &CellBuffer, y: u16) -> String {
+        (0..buffer.width)
+            .map(|x| buffer.cells[buffer.index(x, y)].glyph.as_str().into_owned())
+            .collect()
+    }
+
+    fn present(buffer: &mut CellBuffer) -> String {
+        let mut out = Vec::new();
+        buffer.present(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn only_changed_cells_are_sent() {
+        let mut buffer = CellBuffer::new(5, 2);
+        buffer.write_all(b"hello").unwrap();
+        let first = present(&mut buffer);
+        assert!(first.starts_with(&termion::clear::All.to_string()));
+        assert!(first.contains("hello"));
+
+        // nothing changed, so only the cursor is moved
+        assert_eq!(present(&mut buffer), Goto(6, 1).to_string());
+
+        buffer.write_all(b"\x1b[1;3HX").unwrap();
+        assert_eq!(
+            present(&mut buffer),
+            format!(
+                "{}{}X{}{}",
+                Goto(3, 1),
+                Style::default().to_sgr(),
+                termion::style::Reset,
+                Goto(4, 1)
+            )
+        );
+        // writing the same thing again doesn't count as a change
+        buffer.write_all(b"\x1b[1;3HX").unwrap();
+        assert_eq!(present(&mut buffer), Goto(4, 1).to_string());
+    }
+
+    #[test]
+    fn wide_graphemes() {
+        let mut buffer = CellBuffer::new(3, 1);
+        buffer.write_all("ab日".as_bytes()).unwrap();
+        assert_eq!(row(&buffer, 0), "ab ", "doesn't fit at the edge");
+
+        buffer.write_all("\x1b[1;1H日".as_bytes()).unwrap();
+        assert_eq!(buffer.cells[1].glyph, Glyph::Continuation);
+        assert_eq!(row(&buffer, 0), "日 ");
+
+        // overwriting the right half blanks the left half
+        buffer.write_all(b"\x1b[1;2Hx").unwrap();
+        assert_eq!(row(&buffer, 0), " x ");
+
+        // and overwriting the left half blanks the right half
+        buffer
+            .write_all("\x1b[1;1H日\x1b[1;1Hy".as_bytes())
+            .unwrap();
+        assert_eq!(row(&buffer, 0), "y  ");
+        assert_eq!(buffer.cells[1].glyph, Glyph::Char(' '));
+    }
+
+    #[test]
+    fn graphemes_made_of_several_chars() {
+        let mut buffer = CellBuffer::new(4, 1);
+        buffer.write_all("e\u{301}👩‍💻".as_bytes()).unwrap();
+        assert_eq!(buffer.cells[0].glyph, Glyph::Cluster("e\u{301}".into()));
+        assert_eq!(buffer.cells[1].glyph, Glyph::Cluster("👩‍💻".into()));
+        assert_eq!(buffer.cells[2].glyph, Glyph::Continuation);
+        assert_eq!(buffer.cursor, (3, 0));
+    }
+
+    #[test]
+    fn writes_can_be_split_anywhere() {
+        let mut buffer = CellBuffer::new(4, 1);
+        buffer.write_all(b"\x1b[3").unwrap();
+        assert_eq!(row(&buffer, 0), "    ");
+        buffer.write_all(b"1mA").unwrap();
+        assert_eq!(buffer.cells[0].glyph, Glyph::Char('A'));
+        assert_eq!(buffer.cells[0].style.fg, Some(SgrColour::Basic(31)));
+
+        let wide = "日".as_bytes();
+        buffer.write_all(&wide[..1]).unwrap();
+        buffer.write_all(&wide[1..2]).unwrap();
+        assert_eq!(row(&buffer, 0), "A   ");
+        buffer.write_all(&wide[2..]).unwrap();
+        assert_eq!(row(&buffer, 0), "A日 ");
+        assert!(buffer.pending.is_empty());
+
+        buffer.write_all(b"\x1b]8;;http://a\x1b").unwrap();
+        buffer.write_all(b"\\B").unwrap();
+        assert_eq!(buffer.cells[3].link.as_deref(), Some(";http://a"));
+    }
+
+    #[test]
+    fn hyperlinks_end_with_bel_or_st() {
+        for end in ["\x07", "\x1b\\"] {
+            let mut buffer = CellBuffer::new(3, 1);
+            let text = format!("a\x1b]8;;http://a{0}b\x1b]8;;{0}c", end);
+            buffer.write_all(text.as_bytes()).unwrap();
+            assert_eq!(row(&buffer, 0), "abc");
+            let links: Vec<_> = buffer.cells.iter().map(|c| c.link.as_deref()).collect();
+            assert_eq!(links, [None, Some(";http://a"), None]);
+        }
+        // an ESC that isn't followed by \ doesn't end it
+        let mut buffer = CellBuffer::new(2, 1);
+        buffer.write_all(b"\x1b]8;;http://\x1bb\x07ab").unwrap();
+        assert_eq!(row(&buffer, 0), "ab");
+        assert_eq!(buffer.cells[0].link.as_deref(), Some(";http://\x1bb"));
+    }
+
+    #[test]
+    fn resizing_redraws_everything() {
+        let mut buffer = CellBuffer::new(2, 1);
+        buffer.write_all(b"hi").unwrap();
+        present(&mut buffer);
+
+        buffer.resize(2, 1);
+        assert_eq!(row(&buffer, 0), "hi", "same size, nothing happens");
+        assert_eq!(present(&mut buffer), Goto(3, 1).to_string());
+
+        buffer.resize(3, 1);
+        assert_eq!(row(&buffer, 0), "   ");
+        let out = present(&mut buffer);
+        assert!(out.starts_with(&termion::clear::All.to_string()));
+        assert!(out.contains("   "));
+    }
+}
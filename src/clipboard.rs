@@ -0,0 +1,95 @@
+use crate::gui::{CommandError, Gui};
+use crate::reply;
+use base64::prelude::*;
+
+/// What to copy from the selected message
+#[derive(Clone, Copy)]
+pub enum Yank {
+    Content,
+    Author,
+    /// The line a reply to it starts with, which pastes into a message as a reply. This is what's
+    /// copied instead of a permalink: messages only exist on the server they were sent to and
+    /// have no URL of their own, so a reference is the closest there is to a link to one.
+    Reference,
+}
+
+impl Yank {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "content" => Some(Yank::Content),
+            "author" => Some(Yank::Author),
+            "reference" | "ref" => Some(Yank::Reference),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Yank::Content => "message",
+            Yank::Author => "author's name",
+            Yank::Reference => "reference to the message",
+        }
+    }
+}
+
+impl Gui {
+    /// Copy part of the selected message to the clipboard. It's sent to the terminal after the next draw.
+    pub fn yank(&mut self, what: Yank) -> Result<(), CommandError> {
+        if self.selected_message.is_none() {
+            return Err(CommandError("No message selected".into()));
+        }
+        let message = &self.get_selected_message()?.message;
+        let server = &self.servers[self.curr_server.unwrap()]; // unwrap: checked by get_selected_message
+        let text = match what {
+            Yank::Content => message.content.clone(),
+            Yank::Author => match server.network {
+                Ok(ref net) => net
+                    .peers
+                    .get(&message.author_uuid)
+                    .map(|peer| peer.name.clone())
+                    .unwrap_or("Unknown User".into()),
+                Err(_) => unreachable!(), // get_selected_message checks it's online
+            },
+            Yank::Reference => reply::reference(message),
+        };
+        self.clipboard = Some(text);
+        self.send_system(&format!("Copied the {} to the clipboard", what.name()));
+        Ok(())
+    }
+
+    /// Like `yank`, but for key presses, which have nowhere to return an error to
+    pub fn yank_key(&mut self, what: Yank) {
+        if let Err(CommandError(e)) = self.yank(what) {
+            self.send_system(&e);
+        }
+    }
+
+    /// `/copy [content|author|reference]`
+    pub fn handle_copy_command(&mut self, argv: &[&str]) -> Result<(), CommandError> {
+        let what = match argv.get(1) {
+            None => Yank::Content,
+            Some(&"permalink") | Some(&"link") => {
+                return Err(CommandError(
+                    "Messages have no permalinks. /copy reference copies the line a reply to the message starts with instead".into(),
+                ))
+            }
+            Some(name) => Yank::from_name(name).ok_or(CommandError(
+                "Usage: /copy [content|author|reference]".into(),
+            ))?,
+        };
+        self.yank(what)
+    }
+}
+
+/// The escape code that puts `text` on the system clipboard. The terminal does the copying,
+/// so it works over SSH without needing a clipboard program on either end.
+pub fn osc52(text: &str) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", BASE64_STANDARD.encode(text));
+    if std::env::var_os("TMUX").is_some() {
+        // tmux only passes escape codes on to the terminal outside it when they're wrapped up
+        // like this, with every ESC inside doubled
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
@@ -0,0 +1,461 @@
+use fmtstring::{Colour, Ground};
+use once_cell::sync::Lazy;
+use std::borrow::Cow;
+
+/// How many colours the terminal can show
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColourDepth {
+    Ansi16,
+    Ansi256,
+    TrueColour,
+}
+
+impl ColourDepth {
+    /// Work out the colour depth from the environment, assuming the worst if it's not clear
+    pub fn detect() -> Self {
+        Self::from_vars(
+            &std::env::var("COLORTERM").unwrap_or_default(),
+            &std::env::var("TERM").unwrap_or_default(),
+        )
+    }
+
+    /// The colour depth given what `$COLORTERM` and `$TERM` are set to
+    fn from_vars(colorterm: &str, term: &str) -> Self {
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return ColourDepth::TrueColour;
+        }
+        if term.ends_with("-direct") || term.contains("truecolor") {
+            ColourDepth::TrueColour
+        } else if term.contains("256color") {
+            ColourDepth::Ansi256
+        } else {
+            ColourDepth::Ansi16
+        }
+    }
+}
+
+pub static COLOUR_DEPTH: Lazy<ColourDepth> = Lazy::new(ColourDepth::detect);
+
+/// The 16 basic colours, in the order of their ANSI indices
+const NAMED: [Colour; 16] = [
+    Colour::Black,
+    Colour::Red,
+    Colour::Green,
+    Colour::Yellow,
+    Colour::Blue,
+    Colour::Magenta,
+    Colour::Cyan,
+    Colour::White,
+    Colour::LightBlack,
+    Colour::LightRed,
+    Colour::LightGreen,
+    Colour::LightYellow,
+    Colour::LightBlue,
+    Colour::LightMagenta,
+    Colour::LightCyan,
+    Colour::LightWhite,
+];
+
+/// What the 16 basic colours look like in xterm's default palette. Other terminals vary, but not by much.
+const NAMED_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// Levels used by each channel of the 6x6x6 colour cube in the 256 colour palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// A colour as written in a theme. Anything the terminal can't show is downsampled when it's drawn.
+#[derive(Clone, Copy, Debug)]
+pub enum ThemeColour {
+    Named(Colour),
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl ThemeColour {
+    /// Parse a colour name like "light blue", a 256 colour index like "208", or hex like "#ff8800" or "#f80"
+    pub fn parse(inp: &str) -> Option<Self> {
+        if let Some(hex) = inp.strip_prefix('#') {
+            return parse_hex(hex);
+        }
+        if let Ok(idx) = inp.parse::<u8>() {
+            return Some(ThemeColour::Indexed(idx));
+        }
+        let colour = match inp {
+            "black" => Colour::Black,
+            "blue" => Colour::Blue,
+            "cyan" => Colour::Cyan,
+            "green" => Colour::Green,
+            "light black" => Colour::LightBlack,
+            "light blue" => Colour::LightBlue,
+            "light cyan" => Colour::LightCyan,
+            "light green" => Colour::LightGreen,
+            "light magenta" => Colour::LightMagenta,
+            "light red" => Colour::LightRed,
+            "light white" => Colour::LightWhite,
+            "light yellow" => Colour::LightYellow,
+            "magenta" => Colour::Magenta,
+            "red" => Colour::Red,
+            "reset" => Colour::Default,
+            "white" => Colour::White,
+            "yellow" => Colour::Yellow,
+            _ => return None,
+        };
+        Some(ThemeColour::Named(colour))
+    }
+
+    /// The closest colour that can be shown with the given depth
+    pub fn downsample(self, depth: ColourDepth) -> Self {
+        match (self, depth) {
+            (ThemeColour::Named(_), _) | (_, ColourDepth::TrueColour) => self,
+            (ThemeColour::Indexed(_), ColourDepth::Ansi256) => self,
+            (ThemeColour::Indexed(idx), ColourDepth::Ansi16) => {
+                ThemeColour::Named(NAMED[nearest_named(index_to_rgb(idx))])
+            }
+            (ThemeColour::Rgb(r, g, b), ColourDepth::Ansi256) => {
+                ThemeColour::Indexed(nearest_indexed((r, g, b)))
+            }
+            (ThemeColour::Rgb(r, g, b), ColourDepth::Ansi16) => {
+                ThemeColour::Named(NAMED[nearest_named((r, g, b))])
+            }
+        }
+    }
+
+    /// The escape code to switch to this colour, downsampled for the terminal
+    pub fn escape(self, ground: Ground) -> String {
+        self.escape_for(ground, *COLOUR_DEPTH)
+    }
+
+    fn escape_for(self, ground: Ground, depth: ColourDepth) -> String {
+        use termion::color::{AnsiValue, Rgb};
+        match (self.downsample(depth), ground) {
+            (ThemeColour::Named(colour), ground) => colour.to_string(ground),
+            (ThemeColour::Indexed(idx), Ground::Foreground) => AnsiValue(idx).fg_string(),
+            (ThemeColour::Indexed(idx), Ground::Background) => AnsiValue(idx).bg_string(),
+            (ThemeColour::Rgb(r, g, b), Ground::Foreground) => Rgb(r, g, b).fg_string(),
+            (ThemeColour::Rgb(r, g, b), Ground::Background) => Rgb(r, g, b).bg_string(),
+        }
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<ThemeColour> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+    match hex.len() {
+        6 => Some(ThemeColour::Rgb(
+            channel(&hex[0..2])?,
+            channel(&hex[2..4])?,
+            channel(&hex[4..6])?,
+        )),
+        // #rgb is shorthand for #rrggbb
+        3 => Some(ThemeColour::Rgb(
+            channel(&hex[0..1])? * 17,
+            channel(&hex[1..2])? * 17,
+            channel(&hex[2..3])? * 17,
+        )),
+        _ => None,
+    }
+}
+
+fn index_to_rgb(idx: u8) -> (u8, u8, u8) {
+    match idx {
+        0..=15 => NAMED_RGB[idx as usize],
+        16..=231 => {
+            let idx = idx - 16;
+            (
+                CUBE_LEVELS[(idx / 36) as usize],
+                CUBE_LEVELS[(idx / 6 % 6) as usize],
+                CUBE_LEVELS[(idx % 6) as usize],
+            )
+        }
+        _ => {
+            let grey = 8 + (idx - 232) * 10;
+            (grey, grey, grey)
+        }
+    }
+}
+
+/// How different two colours look. Weighted so that it roughly follows what the eye sees,
+/// which matters a lot more when picking from only 16 colours.
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let mean_r = (a.0 as i32 + b.0 as i32) / 2;
+    let dr = a.0 as i32 - b.0 as i32;
+    let dg = a.1 as i32 - b.1 as i32;
+    let db = a.2 as i32 - b.2 as i32;
+    ((((512 + mean_r) * dr * dr) >> 8) + 4 * dg * dg + (((767 - mean_r) * db * db) >> 8)) as u32
+}
+
+fn nearest_named(rgb: (u8, u8, u8)) -> usize {
+    (0..NAMED_RGB.len())
+        .min_by_key(|&idx| distance(rgb, NAMED_RGB[idx]))
+        .unwrap() // unwrap: there are always 16 to pick from
+}
+
+/// The closest colour out of the cube and the greyscale ramp. The first 16 are left out
+/// because terminals often change them.
+fn nearest_indexed(rgb: (u8, u8, u8)) -> u8 {
+    let nearest_level = |c: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&idx| (CUBE_LEVELS[idx] as i32 - c as i32).abs())
+            .unwrap() as u8
+    };
+    let cube = 16 + 36 * nearest_level(rgb.0) + 6 * nearest_level(rgb.1) + nearest_level(rgb.2);
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let grey = 232 + (average.saturating_sub(3) / 10).min(23) as u8;
+    if distance(rgb, index_to_rgb(grey)) < distance(rgb, index_to_rgb(cube)) {
+        grey
+    } else {
+        cube
+    }
+}
+
+/// Rewrite any 256 colour or RGB escape codes in already-formatted text so the terminal can show them.
+/// This is for text coloured by other crates, like the avatars from dct-tiv.
+pub fn downsample_escapes(text: &str) -> Cow<'_, str> {
+    downsample_escapes_for(text, *COLOUR_DEPTH)
+}
+
+fn downsample_escapes_for(text: &str, depth: ColourDepth) -> Cow<'_, str> {
+    if depth == ColourDepth::TrueColour || !text.contains('\x1b') {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("\x1b[") {
+        out.push_str(&rest[..start]);
+        let seq = &rest[start + 2..];
+        match seq.find(|c: char| !(c.is_ascii_digit() || c == ';')) {
+            Some(end) if seq[end..].starts_with('m') => {
+                out.push_str(&downsample_sgr(&seq[..end], depth));
+                rest = &seq[end + 1..];
+            }
+            _ => {
+                // not a colour, leave it alone
+                out.push_str("\x1b[");
+                rest = seq;
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+/// Downsample the colours in the parameters of one `ESC [ ... m` sequence. Extended colours
+/// that don't parse are left out whole, since their parameters mean something else on their own.
+fn downsample_sgr(params: &str, depth: ColourDepth) -> String {
+    let params: Vec<&str> = params.split(';').collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < params.len() {
+        let ground = match params[i] {
+            "38" => Ground::Foreground,
+            "48" => Ground::Background,
+            param => {
+                out.push_str(&format!("\x1b[{}m", param));
+                i += 1;
+                continue;
+            }
+        };
+        let num = |idx: usize| params.get(idx).and_then(|p| p.parse::<u8>().ok());
+        let (colour, len) = match params.get(i + 1) {
+            Some(&"2") => match (num(i + 2), num(i + 3), num(i + 4)) {
+                (Some(r), Some(g), Some(b)) => (Some(ThemeColour::Rgb(r, g, b)), 5),
+                _ => (None, 5),
+            },
+            Some(&"5") => (num(i + 2).map(ThemeColour::Indexed), 3),
+            Some(_) => (None, 2),
+            None => (None, 1),
+        };
+        if let Some(colour) = colour {
+            out.push_str(&colour.escape_for(ground, depth));
+        }
+        i += len;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use termion::color::{AnsiValue, Rgb};
+
+    fn is_named(colour: ThemeColour, expected: Colour) -> bool {
+        matches!(colour, ThemeColour::Named(c) if c == expected)
+    }
+
+    #[test]
+    fn detects_depth() {
+        assert_eq!(
+            ColourDepth::from_vars("truecolor", "xterm"),
+            ColourDepth::TrueColour
+        );
+        assert_eq!(ColourDepth::from_vars("24bit", ""), ColourDepth::TrueColour);
+        assert_eq!(
+            ColourDepth::from_vars("", "xterm-direct"),
+            ColourDepth::TrueColour
+        );
+        assert_eq!(
+            ColourDepth::from_vars("", "xterm-256color"),
+            ColourDepth::Ansi256
+        );
+        assert_eq!(ColourDepth::from_vars("", "xterm"), ColourDepth::Ansi16);
+        assert_eq!(ColourDepth::from_vars("", ""), ColourDepth::Ansi16);
+    }
+
+    #[test]
+    fn parses_colours() {
+        assert!(is_named(
+            ThemeColour::parse("light blue").unwrap(),
+            Colour::LightBlue
+        ));
+        assert!(is_named(
+            ThemeColour::parse("reset").unwrap(),
+            Colour::Default
+        ));
+        assert!(matches!(
+            ThemeColour::parse("208"),
+            Some(ThemeColour::Indexed(208))
+        ));
+        assert!(matches!(
+            ThemeColour::parse("#ff8800"),
+            Some(ThemeColour::Rgb(255, 136, 0))
+        ));
+        assert!(matches!(
+            ThemeColour::parse("#f80"),
+            Some(ThemeColour::Rgb(255, 136, 0))
+        ));
+        for bad in [
+            "256", "-1", "purple", "#ff880", "#gg8800", "#+f+f+f", "", "#",
+        ] {
+            assert!(ThemeColour::parse(bad).is_none(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn parses_hex() {
+        assert!(matches!(
+            parse_hex("000000"),
+            Some(ThemeColour::Rgb(0, 0, 0))
+        ));
+        assert!(matches!(
+            parse_hex("FfFfFf"),
+            Some(ThemeColour::Rgb(255, 255, 255))
+        ));
+        assert!(matches!(
+            parse_hex("1a2"),
+            Some(ThemeColour::Rgb(0x11, 0xaa, 0x22))
+        ));
+        assert!(parse_hex("12345").is_none());
+        assert!(parse_hex("é12").is_none());
+    }
+
+    #[test]
+    fn nearest_indexed_colours() {
+        // exact matches in the cube and the greyscale ramp
+        assert_eq!(nearest_indexed((0, 0, 0)), 16);
+        assert_eq!(nearest_indexed((255, 255, 255)), 231);
+        assert_eq!(nearest_indexed((255, 0, 0)), 196);
+        assert_eq!(nearest_indexed((135, 175, 215)), 110);
+        assert_eq!(nearest_indexed((128, 128, 128)), 244);
+        for idx in 16..=255 {
+            assert_eq!(
+                index_to_rgb(nearest_indexed(index_to_rgb(idx))),
+                index_to_rgb(idx)
+            );
+        }
+    }
+
+    #[test]
+    fn downsamples() {
+        let rgb = ThemeColour::Rgb(255, 0, 0);
+        assert!(matches!(
+            rgb.downsample(ColourDepth::TrueColour),
+            ThemeColour::Rgb(255, 0, 0)
+        ));
+        assert!(matches!(
+            rgb.downsample(ColourDepth::Ansi256),
+            ThemeColour::Indexed(196)
+        ));
+        assert!(is_named(
+            rgb.downsample(ColourDepth::Ansi16),
+            Colour::LightRed
+        ));
+        assert!(is_named(
+            ThemeColour::Indexed(4).downsample(ColourDepth::Ansi16),
+            Colour::Blue
+        ));
+        assert!(matches!(
+            ThemeColour::Indexed(4).downsample(ColourDepth::Ansi256),
+            ThemeColour::Indexed(4)
+        ));
+    }
+
+    #[test]
+    fn downsamples_escapes() {
+        let text = format!(
+            "a{}b{}c",
+            Rgb(255, 0, 0).fg_string(),
+            AnsiValue(200).bg_string()
+        );
+        assert_eq!(downsample_escapes_for(&text, ColourDepth::TrueColour), text);
+        assert_eq!(
+            downsample_escapes_for(&text, ColourDepth::Ansi256),
+            format!(
+                "a{}b{}c",
+                AnsiValue(196).fg_string(),
+                AnsiValue(200).bg_string()
+            )
+        );
+        // anything that isn't a colour is left as it was
+        assert_eq!(
+            downsample_escapes_for("\x1b[1;4mx\x1b[2J", ColourDepth::Ansi256),
+            "\x1b[1m\x1b[4mx\x1b[2J"
+        );
+        assert_eq!(
+            downsample_escapes_for("\x1b[1;38;2;255;0;0m", ColourDepth::Ansi256),
+            format!("\x1b[1m{}", AnsiValue(196).fg_string())
+        );
+    }
+
+    #[test]
+    fn malformed_extended_colours_are_dropped_whole() {
+        // 300 isn't an index, and its 5 mustn't turn into blink
+        assert_eq!(
+            downsample_escapes_for("\x1b[38;5;300mx", ColourDepth::Ansi256),
+            "x"
+        );
+        assert_eq!(
+            downsample_escapes_for("\x1b[48;2;1;2;999;1mx", ColourDepth::Ansi256),
+            "\x1b[1mx"
+        );
+        assert_eq!(
+            downsample_escapes_for("\x1b[38;5mx", ColourDepth::Ansi256),
+            "x"
+        );
+        assert_eq!(
+            downsample_escapes_for("\x1b[38;9;1mx", ColourDepth::Ansi256),
+            "\x1b[1mx"
+        );
+        assert_eq!(
+            downsample_escapes_for("\x1b[38mx", ColourDepth::Ansi256),
+            "x"
+        );
+    }
+}
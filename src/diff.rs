@@ -0,0 +1,108 @@
+/// Whether a piece of text is in both versions, or only one of them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Change {
+    Same,
+    Removed,
+    Added,
+}
+
+/// Past this many comparisons, the two versions are just shown as one replacing the other
+const MAX_WORK: usize = 1_000_000;
+
+/// Split text into words and the whitespace between them, keeping everything, so the pieces join back up
+fn tokens(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut in_space = None;
+    for (idx, ch) in text.char_indices() {
+        let space = ch.is_whitespace();
+        if in_space.is_some_and(|s| s != space) {
+            out.push(&text[start..idx]);
+            start = idx;
+        }
+        in_space = Some(space);
+    }
+    if start < text.len() {
+        out.push(&text[start..]);
+    }
+    out
+}
+
+/// The changes between two versions of a message, word by word, in the order they'd be read
+pub fn diff<'a>(old: &'a str, new: &'a str) -> Vec<(Change, &'a str)> {
+    let (old, new) = (tokens(old), tokens(new));
+    if old.len().saturating_mul(new.len()) > MAX_WORK {
+        let mut out: Vec<_> = old.into_iter().map(|t| (Change::Removed, t)).collect();
+        out.extend(new.into_iter().map(|t| (Change::Added, t)));
+        return out;
+    }
+
+    // longest common subsequence, filled in from the end so it can be walked forwards
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out: Vec<(Change, &str)> = Vec::new();
+    let mut push = |change, token| out.push((change, token));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            push(Change::Same, old[i]);
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            push(Change::Removed, old[i]);
+            i += 1;
+        } else {
+            push(Change::Added, new[j]);
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_common_words() {
+        assert_eq!(
+            diff("the cat sat", "the dog sat"),
+            vec![
+                (Change::Same, "the"),
+                (Change::Same, " "),
+                (Change::Removed, "cat"),
+                (Change::Added, "dog"),
+                (Change::Same, " "),
+                (Change::Same, "sat"),
+            ]
+        );
+    }
+
+    #[test]
+    fn gives_up_past_max_work() {
+        let old = "a ".repeat(1000);
+        let new = "a b ".repeat(1000);
+        let out = diff(&old, &new);
+        assert!(out.iter().all(|(change, _)| *change != Change::Same));
+        let removed: String = out
+            .iter()
+            .filter(|(change, _)| *change == Change::Removed)
+            .map(|(_, t)| *t)
+            .collect();
+        let added: String = out
+            .iter()
+            .filter(|(change, _)| *change == Change::Added)
+            .map(|(_, t)| *t)
+            .collect();
+        assert_eq!((removed, added), (old, new));
+    }
+}
@@ -1,6 +1,16 @@
+use crate::api;
+use crate::colour::{downsample_escapes, ThemeColour};
+use crate::folders::SidebarRow;
 use crate::gui::Gui;
+use crate::highlight::Token;
+use crate::images;
+use crate::layout::{BorderArea, BorderWidths, Layout, Pane, Rect};
+use crate::server::{local_time, LoadedMessage, Peer, Server};
+use crate::styled::StyledLine;
+use crate::unicode::{self, fmt_width};
 use crate::{Focus, Mode};
-use fmtstring::{Colour, FmtChar};
+use chrono::Datelike;
+use fmtstring::FmtString;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::fmt;
@@ -13,50 +23,429 @@ static BUILTIN_THEMES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(||
     m
 });
 
+/// Whether a message is the first of its day, so it has a day separator above it
+fn starts_day(messages: &[LoadedMessage], idx: usize) -> bool {
+    let day = |idx: usize| local_time(messages[idx].message.date).map(|dt| dt.date_naive());
+    day(idx).is_some() && (idx == 0 || day(idx) != day(idx - 1))
+}
+
+/// How many rows a message takes up, including the day separator above it if there is one
+fn message_rows(messages: &[LoadedMessage], idx: usize) -> usize {
+    messages[idx].lines.len() + starts_day(messages, idx) as usize
+}
+
 fn centred(text: &str, width: usize) -> String {
-    format!("{: ^1$}", text, width)
+    let text = truncate(text, width);
+    let padding = width - unicode::width(&text);
+    format!(
+        "{}{}{}",
+        " ".repeat(padding / 2),
+        text,
+        " ".repeat(padding - padding / 2)
+    )
+}
+
+/// Cut `text` down to at most `width` columns, marking the cut with an ellipsis
+pub fn truncate(text: &str, width: usize) -> String {
+    if unicode::width(text) <= width {
+        text.to_owned()
+    } else if width == 0 {
+        String::new()
+    } else {
+        format!("{}…", unicode::take_width(text, width - 1))
+    }
+}
+
+/// Sections a theme can have. "global" is used for anything not set in the other sections.
+const THEME_AREAS: [&str; 6] = [
+    "global", "servers", "channels", "edit", "messages", "status",
+];
+
+const THEME_COLOUR_KEYS: [&str; 33] = [
+    "text-foreground",
+    "text-background",
+    "selected-text-foreground",
+    "selected-text-background",
+    "unfocussed-selected-text-foreground",
+    "unfocussed-selected-text-background",
+    "error-text-foreground",
+    "error-text-background",
+    "system-message-foreground",
+    "system-message-background",
+    "code-foreground",
+    "code-background",
+    "quote-foreground",
+    "quote-background",
+    "link-foreground",
+    "edited-foreground",
+    "edited-background",
+    "reply-foreground",
+    "reply-background",
+    "mention-foreground",
+    "mention-background",
+    "diff-added-foreground",
+    "diff-added-background",
+    "diff-removed-foreground",
+    "diff-removed-background",
+    "date-separator-foreground",
+    "date-separator-background",
+    "syntax-keyword-foreground",
+    "syntax-type-foreground",
+    "syntax-string-foreground",
+    "syntax-number-foreground",
+    "syntax-comment-foreground",
+    "syntax-variable-foreground",
+];
+
+const THEME_ATTRIBUTE_KEYS: [&str; 20] = [
+    "text-attributes",
+    "selected-text-attributes",
+    "unfocussed-selected-text-attributes",
+    "error-text-attributes",
+    "system-message-attributes",
+    "code-attributes",
+    "quote-attributes",
+    "link-attributes",
+    "edited-attributes",
+    "reply-attributes",
+    "mention-attributes",
+    "diff-added-attributes",
+    "diff-removed-attributes",
+    "date-separator-attributes",
+    "syntax-keyword-attributes",
+    "syntax-type-attributes",
+    "syntax-string-attributes",
+    "syntax-number-attributes",
+    "syntax-comment-attributes",
+    "syntax-variable-attributes",
+];
+
+const THEME_BORDER_KEYS: [&str; 13] = [
+    "border-tl",
+    "border-tr",
+    "border-bl",
+    "border-br",
+    "border-top",
+    "border-bottom",
+    "border-left",
+    "border-right",
+    "border-bottom-split",
+    "border-top-split",
+    "border-left-split",
+    "border-right-split",
+    "border-cross",
+];
+
+static DEFAULT_THEME: Lazy<serde_json::Value> = Lazy::new(|| {
+    serde_json::from_str(BUILTIN_THEMES["default"]).expect("The default theme is invalid")
+});
+
+/// Where users can put their own themes, e.g. ~/.config/aster-cli/themes
+pub fn user_theme_dir() -> Option<std::path::PathBuf> {
+    let mut dir = dirs::config_dir()?;
+    dir.push("aster-cli");
+    dir.push("themes");
+    Some(dir)
+}
+
+/// Something wrong with a theme file, and where in the file it is
+#[derive(Debug)]
+pub struct ThemeError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Put a list of theme errors on one line, for the status bar
+pub fn join_theme_errors(errors: &[ThemeError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn parse_colour(val: &serde_json::Value) -> Result<ThemeColour, String> {
+    let name = val
+        .as_str()
+        .ok_or("expected a colour name, 256 colour index or #rrggbb as a string")?;
+    ThemeColour::parse(name).ok_or(format!("unknown colour '{}'", name))
+}
+
+/// Parse text attributes, either one like "bold" or a list like ["bold", "underline"]
+fn parse_attributes(val: &serde_json::Value) -> Result<TextAttributes, String> {
+    let names = match val {
+        serde_json::Value::String(name) => vec![name.as_str()],
+        serde_json::Value::Array(names) => names
+            .iter()
+            .map(|name| name.as_str().ok_or("expected a list of strings"))
+            .collect::<Result<_, _>>()?,
+        _ => return Err("expected an attribute name, or a list of them".into()),
+    };
+    let mut attrs = TextAttributes::default();
+    for name in names {
+        match name {
+            "bold" => attrs.bold = true,
+            "italic" => attrs.italic = true,
+            "underline" => attrs.underline = true,
+            "dim" => attrs.dim = true,
+            "reverse" => attrs.reverse = true,
+            "strikethrough" => attrs.strikethrough = true,
+            _ => {
+                return Err(format!(
+                    "unknown attribute '{}', expected bold, italic, underline, dim, reverse or strikethrough",
+                    name
+                ))
+            }
+        }
+    }
+    Ok(attrs)
+}
+
+/// Parse a border character, `[character, foreground, background]` with optional attributes on the end.
+/// An empty string means no border.
+fn parse_border(val: &serde_json::Value) -> Result<OptionalFmtChar, (Option<usize>, String)> {
+    let parts = match val.as_array() {
+        Some(parts) if parts.len() == 3 || parts.len() == 4 => parts,
+        _ => {
+            return Err((
+                None,
+                "expected [character, foreground, background] or [character, foreground, background, attributes]".into(),
+            ));
+        }
+    };
+    let text = parts[0]
+        .as_str()
+        .ok_or((Some(0), "expected a string".to_owned()))?;
+    if text.chars().count() > 1 {
+        return Err((Some(0), format!("expected one character, got '{}'", text)));
+    }
+    let fg = parse_colour(&parts[1]).map_err(|e| (Some(1), e))?;
+    let bg = parse_colour(&parts[2]).map_err(|e| (Some(2), e))?;
+    let attrs = match parts.get(3) {
+        Some(attrs) => parse_attributes(attrs).map_err(|e| (Some(3), e))?,
+        None => TextAttributes::default(),
+    };
+    Ok(OptionalFmtChar(text.chars().next().map(|ch| ThemedChar {
+        ch,
+        fg,
+        bg,
+        attrs,
+    })))
+}
+
+/// Find every problem with a theme, without loading it
+fn validate_theme(theme: &serde_json::Value) -> Vec<ThemeError> {
+    let mut errors = Vec::new();
+    let Some(areas) = theme.as_object() else {
+        errors.push(ThemeError {
+            path: "(top level)".into(),
+            message: "expected an object".into(),
+        });
+        return errors;
+    };
+    for (area, keys) in areas {
+        if area == "extends" {
+            if !keys.is_string() {
+                errors.push(ThemeError {
+                    path: area.clone(),
+                    message: "expected the name of a theme".into(),
+                });
+            }
+            continue;
+        }
+        if !THEME_AREAS.contains(&area.as_str()) {
+            errors.push(ThemeError {
+                path: area.clone(),
+                message: format!(
+                    "unknown section, expected extends or one of {}",
+                    THEME_AREAS.join(", ")
+                ),
+            });
+            continue;
+        }
+        let Some(keys) = keys.as_object() else {
+            errors.push(ThemeError {
+                path: area.clone(),
+                message: "expected an object".into(),
+            });
+            continue;
+        };
+        for (key, val) in keys {
+            let path = format!("{}.{}", area, key);
+            if THEME_COLOUR_KEYS.contains(&key.as_str()) {
+                if let Err(message) = parse_colour(val) {
+                    errors.push(ThemeError { path, message });
+                }
+            } else if THEME_ATTRIBUTE_KEYS.contains(&key.as_str()) {
+                if let Err(message) = parse_attributes(val) {
+                    errors.push(ThemeError { path, message });
+                }
+            } else if THEME_BORDER_KEYS.contains(&key.as_str()) {
+                if let Err((idx, message)) = parse_border(val) {
+                    let path = match idx {
+                        Some(idx) => format!("{}[{}]", path, idx),
+                        None => path,
+                    };
+                    errors.push(ThemeError { path, message });
+                }
+            } else {
+                errors.push(ThemeError {
+                    path,
+                    message: "unknown key".into(),
+                });
+            }
+        }
+    }
+    errors
+}
+
+/// Look a key up in an area of the theme, falling back to the theme's global section and then to the
+/// default theme. Anything that doesn't parse is skipped; `validate_theme` is what reports it.
+fn theme_value<T, E>(
+    theme: &serde_json::Value,
+    area: &str,
+    key: &str,
+    parse: impl Fn(&serde_json::Value) -> Result<T, E>,
+) -> T {
+    [
+        &theme[area][key],
+        &theme["global"][key],
+        &DEFAULT_THEME[area][key],
+        &DEFAULT_THEME["global"][key],
+    ]
+    .iter()
+    .filter(|val| !val.is_null())
+    .find_map(|val| parse(val).ok())
+    .unwrap_or_else(|| panic!("The default theme doesn't have a valid '{}'", key))
 }
 
-fn fmtchar_from_json_impl(val: &serde_json::Value) -> Option<FmtChar> {
-    Some(FmtChar {
-        ch: val[0].as_str()?.chars().next()?,
-        fg: parse_colour(val[1].as_str()?),
-        bg: parse_colour(val[2].as_str()?),
+/// Merge a theme on top of the one it extends, and that one on top of whatever it extends, and so on.
+/// Problems with the themes it extends are added to `errors`, with the name of the theme they're in.
+fn resolve_extends(
+    theme: serde_json::Value,
+    errors: &mut Vec<ThemeError>,
+    seen: &mut Vec<String>,
+) -> serde_json::Value {
+    let Some(parent_name) = theme["extends"].as_str() else {
+        return theme;
+    };
+    if seen.iter().any(|name| name == parent_name) {
+        errors.push(ThemeError {
+            path: "extends".into(),
+            message: format!("'{}' ends up extending itself", parent_name),
+        });
+        return theme;
+    }
+    seen.push(parent_name.to_owned());
+
+    let parent = match Theme::read(parent_name)
+        .and_then(|(contents, path)| parse_theme_json(&contents, &path))
+    {
+        Ok(parent) => parent,
+        Err(e) => {
+            errors.push(ThemeError {
+                path: "extends".into(),
+                message: format!("couldn't load '{}': {}", parent_name, e),
+            });
+            return theme;
+        }
+    };
+    errors.extend(validate_theme(&parent).into_iter().map(|e| ThemeError {
+        path: format!("{} > {}", parent_name, e.path),
+        message: e.message,
+    }));
+
+    let mut merged = resolve_extends(parent, errors, seen);
+    if !merged.is_object() {
+        merged = serde_json::json!({});
+    }
+    for (area, keys) in theme.as_object().into_iter().flatten() {
+        let Some(keys) = keys.as_object() else {
+            continue;
+        };
+        if !merged[area].is_object() {
+            merged[area] = serde_json::json!({});
+        }
+        for (key, val) in keys {
+            merged[area][key] = val.clone();
+        }
+    }
+    merged
+}
+
+fn parse_theme_json(contents: &str, path: &str) -> Result<serde_json::Value, ThemeError> {
+    serde_json::from_str(contents).map_err(|e| ThemeError {
+        path: path.to_owned(),
+        message: e.to_string(),
     })
 }
 
-pub fn fmtchar_from_json(val: &serde_json::Value) -> OptionalFmtChar {
-    OptionalFmtChar(fmtchar_from_json_impl(val))
+/// Bold, italic and so on, for making things stand out without relying on colour
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TextAttributes {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub dim: bool,
+    pub reverse: bool,
+    pub strikethrough: bool,
 }
 
-fn parse_colour(inp: &str) -> Colour {
-    if inp.starts_with('#') {
-        panic!("RGB colours not currently supported");
+impl fmt::Display for TextAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bold {
+            write!(f, "{}", termion::style::Bold)?;
+        }
+        if self.italic {
+            write!(f, "{}", termion::style::Italic)?;
+        }
+        if self.underline {
+            write!(f, "{}", termion::style::Underline)?;
+        }
+        if self.dim {
+            write!(f, "{}", termion::style::Faint)?;
+        }
+        if self.reverse {
+            write!(f, "{}", termion::style::Invert)?;
+        }
+        if self.strikethrough {
+            write!(f, "{}", termion::style::CrossedOut)?;
+        }
+        Ok(())
     }
+}
+
+/// A character with colours, like a `FmtChar` but able to use any colour a theme can
+#[derive(Clone, Copy, Debug)]
+pub struct ThemedChar {
+    pub ch: char,
+    pub fg: ThemeColour,
+    pub bg: ThemeColour,
+    pub attrs: TextAttributes,
+}
 
-    match inp {
-        "black" => Colour::Black,
-        "blue" => Colour::Blue,
-        "cyan" => Colour::Cyan,
-        "green" => Colour::Green,
-        "light black" => Colour::LightBlack,
-        "light blue" => Colour::LightBlue,
-        "light green" => Colour::LightGreen,
-        "light magenta" => Colour::LightMagenta,
-        "light red" => Colour::LightRed,
-        "light white" => Colour::LightWhite,
-        "light yellow" => Colour::LightYellow,
-        "magenta" => Colour::Magenta,
-        "red" => Colour::Red,
-        "reset" => Colour::Default,
-        "white" => Colour::White,
-        "yellow" => Colour::Yellow,
-        _ => todo!(),
+impl fmt::Display for ThemedChar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}{}{}",
+            self.attrs,
+            self.fg.escape(fmtstring::Ground::Foreground),
+            self.bg.escape(fmtstring::Ground::Background),
+            self.ch,
+            termion::style::Reset
+        )
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct OptionalFmtChar(Option<FmtChar>);
+pub struct OptionalFmtChar(Option<ThemedChar>);
 
 impl OptionalFmtChar {
     pub fn width(&self) -> u16 {
@@ -79,17 +468,37 @@ impl fmt::Display for OptionalFmtChar {
 
 #[derive(Clone, Debug)]
 pub struct Colour2 {
-    pub fg: Colour,
-    pub bg: Colour,
+    pub fg: ThemeColour,
+    pub bg: ThemeColour,
+    pub attrs: TextAttributes,
 }
 
 impl fmt::Display for Colour2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.attrs,
+            self.fg.escape(fmtstring::Ground::Foreground),
+            self.bg.escape(fmtstring::Ground::Background)
+        )
+    }
+}
+
+/// How one kind of token in a code block, or a link, looks. It has no background, so the one behind it shows through.
+#[derive(Clone, Debug)]
+pub struct TokenColour {
+    pub fg: ThemeColour,
+    pub attrs: TextAttributes,
+}
+
+impl fmt::Display for TokenColour {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
             "{}{}",
-            self.fg.to_string(fmtstring::Ground::Foreground),
-            self.bg.to_string(fmtstring::Ground::Background)
+            self.attrs,
+            self.fg.escape(fmtstring::Ground::Foreground)
         )
     }
 }
@@ -108,6 +517,18 @@ pub struct ThemedBorder {
     pub top_split: OptionalFmtChar,
     pub left_split: OptionalFmtChar,
     pub right_split: OptionalFmtChar,
+    pub cross: OptionalFmtChar,
+}
+
+impl ThemedBorder {
+    pub fn widths(&self) -> BorderWidths {
+        BorderWidths {
+            left: self.left.width(),
+            right: self.right.width(),
+            top: self.top.width(),
+            bottom: self.bottom.width(),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -117,6 +538,16 @@ pub struct ThemedArea {
     pub unfocussed_selected_text: Colour2,
     pub error_text: Colour2,
     pub system_message: Colour2,
+    pub code: Colour2,     // inline code and code blocks in messages
+    pub quote: Colour2,    // block quotes in messages
+    pub link: TokenColour, // URLs in messages, on top of whatever text they're in
+    pub edited: Colour2,   // the "(edited)" tag after a message that's been changed
+    pub reply: Colour2,    // the start of the message a reply is replying to, above it
+    pub mention: Colour2,  // the bar beside messages that mention us
+    pub diff_added: Colour2,
+    pub diff_removed: Colour2,
+    pub date_separator: Colour2, // the line between messages from different days
+    pub syntax: [TokenColour; Token::ALL.len()], // in the same order as `Token::ALL`
     pub border: ThemedBorder,
 }
 
@@ -128,203 +559,364 @@ pub struct Theme {
     pub messages: ThemedArea,
     pub status: ThemedArea,
     pub sidebar_width: usize,
-}
-
-fn get_or<'a>(
-    name: &str,
-    main: &'a serde_json::Value,
-    aux: &'a serde_json::Value,
-) -> &'a serde_json::Value {
-    if main[name].is_null() {
-        &aux[name]
-    } else {
-        &main[name]
-    }
+    pub extends: Vec<String>, // every theme this one is built on, so changes to them can reload it
 }
 
 impl ThemedArea {
-    pub fn new(cfg: &serde_json::Value, fallback: &serde_json::Value) -> Self {
+    pub fn new(theme: &serde_json::Value, area: &str) -> Self {
+        let colour = |key: &str| theme_value(theme, area, key, parse_colour);
+        let colour2 = |name: &str| Colour2 {
+            fg: colour(&format!("{}-foreground", name)),
+            bg: colour(&format!("{}-background", name)),
+            attrs: theme_value(
+                theme,
+                area,
+                &format!("{}-attributes", name),
+                parse_attributes,
+            ),
+        };
+        let border = |key: &str| theme_value(theme, area, key, parse_border);
         ThemedArea {
-            text: Colour2 {
-                fg: parse_colour(get_or("text-foreground", cfg, fallback).as_str().unwrap()),
-                bg: parse_colour(get_or("text-background", cfg, fallback).as_str().unwrap()),
-            },
-            selected_text: Colour2 {
-                fg: parse_colour(
-                    get_or("selected-text-foreground", cfg, fallback)
-                        .as_str()
-                        .unwrap(),
-                ),
-                bg: parse_colour(
-                    get_or("selected-text-background", cfg, fallback)
-                        .as_str()
-                        .unwrap(),
-                ),
+            text: colour2("text"),
+            selected_text: colour2("selected-text"),
+            unfocussed_selected_text: colour2("unfocussed-selected-text"),
+            error_text: colour2("error-text"),
+            system_message: colour2("system-message"),
+            code: colour2("code"),
+            quote: colour2("quote"),
+            link: TokenColour {
+                fg: colour("link-foreground"),
+                attrs: theme_value(theme, area, "link-attributes", parse_attributes),
             },
-            unfocussed_selected_text: Colour2 {
-                fg: parse_colour(
-                    get_or("unfocussed-selected-text-foreground", cfg, fallback)
-                        .as_str()
-                        .unwrap(),
+            edited: colour2("edited"),
+            reply: colour2("reply"),
+            mention: colour2("mention"),
+            diff_added: colour2("diff-added"),
+            diff_removed: colour2("diff-removed"),
+            date_separator: colour2("date-separator"),
+            syntax: Token::ALL.map(|token| TokenColour {
+                fg: colour(&format!("syntax-{}-foreground", token.name())),
+                attrs: theme_value(
+                    theme,
+                    area,
+                    &format!("syntax-{}-attributes", token.name()),
+                    parse_attributes,
                 ),
-                bg: parse_colour(
-                    get_or("unfocussed-selected-text-background", cfg, fallback)
-                        .as_str()
-                        .unwrap(),
-                ),
-            },
-            error_text: Colour2 {
-                fg: parse_colour(
-                    get_or("error-text-foreground", cfg, fallback)
-                        .as_str()
-                        .unwrap(),
-                ),
-                bg: parse_colour(
-                    get_or("error-text-background", cfg, fallback)
-                        .as_str()
-                        .unwrap(),
-                ),
-            },
-            system_message: Colour2 {
-                fg: parse_colour(
-                    get_or("system-message-foreground", cfg, fallback)
-                        .as_str()
-                        .unwrap(),
-                ),
-                bg: parse_colour(
-                    get_or("system-message-background", cfg, fallback)
-                        .as_str()
-                        .unwrap(),
-                ),
-            },
+            }),
 
             border: ThemedBorder {
-                tl: fmtchar_from_json(get_or("border-tl", cfg, fallback)),
-                tr: fmtchar_from_json(get_or("border-tr", cfg, fallback)),
-                bl: fmtchar_from_json(get_or("border-bl", cfg, fallback)),
-                br: fmtchar_from_json(get_or("border-br", cfg, fallback)),
-                top: fmtchar_from_json(get_or("border-top", cfg, fallback)),
-                bottom: fmtchar_from_json(get_or("border-bottom", cfg, fallback)),
-                left: fmtchar_from_json(get_or("border-left", cfg, fallback)),
-                right: fmtchar_from_json(get_or("border-right", cfg, fallback)),
-                bottom_split: fmtchar_from_json(get_or("border-bottom-split", cfg, fallback)),
-                top_split: fmtchar_from_json(get_or("border-top-split", cfg, fallback)),
-                left_split: fmtchar_from_json(get_or("border-left-split", cfg, fallback)),
-                right_split: fmtchar_from_json(get_or("border-right-split", cfg, fallback)),
+                tl: border("border-tl"),
+                tr: border("border-tr"),
+                bl: border("border-bl"),
+                br: border("border-br"),
+                top: border("border-top"),
+                bottom: border("border-bottom"),
+                left: border("border-left"),
+                right: border("border-right"),
+                bottom_split: border("border-bottom-split"),
+                top_split: border("border-top-split"),
+                left_split: border("border-left-split"),
+                right_split: border("border-right-split"),
+                cross: border("border-cross"),
             },
         }
     }
 }
 
 impl Theme {
-    pub fn new(name: &str) -> std::result::Result<Self, Box<dyn std::error::Error>> {
-        // TODO when rust 1.79 becomes old enough, we can drop this .to_owned() and borrow the std::fs::read
-        let file_contents = if let Some(c) = BUILTIN_THEMES.get(name) {
-            (*c).to_owned()
-        } else {
-            std::fs::read_to_string(format!("themes/{}.json", name))?
-        };
+    /// Load a theme by name, along with anything wrong with it. Broken or missing parts of the theme
+    /// are taken from the default theme; the only error is if the file can't be read or parsed at all.
+    pub fn new(name: &str, sidebar_width: usize) -> Result<(Self, Vec<ThemeError>), ThemeError> {
+        let (contents, path) = Self::read(name)?;
+        Self::from_str(&contents, &path, sidebar_width)
+    }
 
-        let totalcfg: serde_json::Value = serde_json::from_str(&file_contents)?;
+    /// The contents of a theme, and where it came from
+    fn read(name: &str) -> Result<(String, String), ThemeError> {
+        if let Some(c) = BUILTIN_THEMES.get(name) {
+            return Ok(((*c).to_owned(), name.to_owned()));
+        }
+        let path = Self::path(name)
+            .ok_or(ThemeError {
+                path: name.to_owned(),
+                message: "no such theme".into(),
+            })?
+            .to_string_lossy()
+            .into_owned();
+        let contents = std::fs::read_to_string(&path).map_err(|e| ThemeError {
+            path: path.clone(),
+            message: e.to_string(),
+        })?;
+        Ok((contents, path))
+    }
 
-        let servers = ThemedArea::new(&totalcfg["servers"], &totalcfg["global"]);
-        let channels = ThemedArea::new(&totalcfg["channels"], &totalcfg["global"]);
-        let edit = ThemedArea::new(&totalcfg["edit"], &totalcfg["global"]);
-        let messages = ThemedArea::new(&totalcfg["messages"], &totalcfg["global"]);
-        let status = ThemedArea::new(&totalcfg["status"], &totalcfg["global"]);
+    /// Where a user's theme lives on disk, or None for the builtin ones
+    pub fn path(name: &str) -> Option<std::path::PathBuf> {
+        if BUILTIN_THEMES.contains_key(name) {
+            return None;
+        }
+        Some(user_theme_dir()?.join(format!("{}.json", name)))
+    }
 
-        let sidebar_width = 32;
+    /// Load a theme from a file anywhere, rather than by name
+    pub fn from_file(
+        path: &str,
+        sidebar_width: usize,
+    ) -> Result<(Self, Vec<ThemeError>), ThemeError> {
+        let file_contents = std::fs::read_to_string(path).map_err(|e| ThemeError {
+            path: path.to_owned(),
+            message: e.to_string(),
+        })?;
+        Self::from_str(&file_contents, path, sidebar_width)
+    }
 
-        Ok(Theme {
+    fn from_str(
+        contents: &str,
+        path: &str,
+        sidebar_width: usize,
+    ) -> Result<(Self, Vec<ThemeError>), ThemeError> {
+        let totalcfg = parse_theme_json(contents, path)?;
+        let mut errors = validate_theme(&totalcfg);
+        let mut extends = Vec::new();
+        let totalcfg = resolve_extends(totalcfg, &mut errors, &mut extends);
+        Ok((Self::from_json(&totalcfg, sidebar_width, extends), errors))
+    }
+
+    fn from_json(totalcfg: &serde_json::Value, sidebar_width: usize, extends: Vec<String>) -> Self {
+        Theme {
             sidebar_width,
-            servers,
-            channels,
-            edit,
-            messages,
-            status,
-        })
-    }
-
-    pub fn get_left_margin(&self) -> usize {
-        self.sidebar_width
-            + u16::max(
-                self.servers.border.left.width(),
-                self.channels.border.right.width(),
-            ) as usize
-            + u16::max(
-                self.servers.border.right.width(),
-                self.channels.border.right.width(),
-            ) as usize
-    }
-
-    pub fn get_list_height(&self, height: u16) -> usize {
-        (height
-            - 1
-            - self.channels.border.top.width()
-            - self.channels.border.bottom.width() * 2
-            - self.servers.border.top.width()
-            - self.servers.border.bottom.width()) as usize
-    }
-
-    pub fn get_servers_start_pos(&self) -> usize {
-        (self.channels.border.top.width() + 2 + self.channels.border.bottom.width() * 2) as usize
-    }
-
-    pub fn get_channels_start_pos(&self, height: u16) -> usize {
-        self.get_servers_start_pos()
-            + self.get_servers_height(height)
-            + self.channels.border.bottom.width() as usize
-            + self.servers.border.top.width() as usize
-    }
-
-    pub fn get_servers_height(&self, height: u16) -> usize {
-        let list_height = self.get_list_height(height);
-        if list_height % 2 == 0 {
-            list_height / 2 - 1
-        } else {
-            list_height / 2
+            extends,
+            servers: ThemedArea::new(totalcfg, "servers"),
+            channels: ThemedArea::new(totalcfg, "channels"),
+            edit: ThemedArea::new(totalcfg, "edit"),
+            messages: ThemedArea::new(totalcfg, "messages"),
+            status: ThemedArea::new(totalcfg, "status"),
         }
     }
-    pub fn get_channels_height(&self, height: u16) -> usize {
-        let list_height = self.get_list_height(height);
-        list_height / 2
+
+    /// The builtin default theme, which always loads
+    pub fn fallback(sidebar_width: usize) -> Self {
+        Self::from_json(&DEFAULT_THEME, sidebar_width, Vec::new())
+    }
+
+    /// Names of every theme that can be loaded: the builtins, then any others in the user's theme directory
+    pub fn available() -> Vec<String> {
+        let mut names: Vec<String> = BUILTIN_THEMES.keys().map(|name| name.to_string()).collect();
+        names.sort();
+        if let Some(Ok(entries)) = user_theme_dir().map(std::fs::read_dir) {
+            let mut files: Vec<String> = entries
+                .filter_map(|entry| {
+                    let path = entry.ok()?.path();
+                    if path.extension()? != "json" {
+                        return None;
+                    }
+                    Some(path.file_stem()?.to_str()?.to_owned())
+                })
+                .filter(|name| !names.contains(name))
+                .collect();
+            files.sort();
+            names.extend(files);
+        }
+        names
+    }
+
+    /// The part of the theme that a border is drawn with
+    pub fn area(&self, area: BorderArea) -> &ThemedArea {
+        match area {
+            BorderArea::Status => &self.status,
+            BorderArea::Channels => &self.channels,
+            BorderArea::Servers => &self.servers,
+            BorderArea::Messages => &self.messages,
+            BorderArea::Edit => &self.edit,
+        }
     }
 }
 
-fn border_rep(c: &OptionalFmtChar, n: usize) -> String {
-    if let Some(c) = c.0 {
-        format!(
-            "{}{}{}{}{}",
-            c.fg.to_string(fmtstring::Ground::Foreground),
-            c.bg.to_string(fmtstring::Ground::Background),
-            (format!("{}", c.ch)).repeat(n),
-            termion::color::Fg(termion::color::Reset),
-            termion::color::Bg(termion::color::Reset)
-        )
-    } else {
-        "".into()
+/// Limit a list's scroll offset so that it never scrolls past the last item
+pub fn clamp_scroll(scroll: usize, len: usize, height: usize) -> usize {
+    scroll.min(len.saturating_sub(height))
+}
+
+/// Which ways a border cell joins up with the cells around it
+#[derive(Clone, Copy, Default)]
+struct Links {
+    up: bool,
+    down: bool,
+    left: bool,
+    right: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy)]
+struct BorderCell<'a> {
+    links: Links,
+    side: Side, // which side of its box the cell was last drawn for
+    border: &'a ThemedBorder,
+}
+
+impl BorderCell<'_> {
+    /// The character that joins up with the neighbouring cells, or just the plain side
+    /// if the theme doesn't have one
+    fn glyph(&self) -> &OptionalFmtChar {
+        let b = self.border;
+        let straight = match self.side {
+            Side::Top => &b.top,
+            Side::Bottom => &b.bottom,
+            Side::Left => &b.left,
+            Side::Right => &b.right,
+        };
+        let Links {
+            up,
+            down,
+            left,
+            right,
+        } = self.links;
+        let joined = match (up, down, left, right) {
+            (false, true, false, true) => &b.tl,
+            (false, true, true, false) => &b.tr,
+            (true, false, false, true) => &b.bl,
+            (true, false, true, false) => &b.br,
+            (false, true, true, true) => &b.top_split,
+            (true, false, true, true) => &b.bottom_split,
+            (true, true, false, true) => &b.left_split,
+            (true, true, true, false) => &b.right_split,
+            (true, true, true, true) => &b.cross,
+            _ => straight,
+        };
+        if joined.0.is_some() {
+            joined
+        } else {
+            straight
+        }
     }
 }
 
+/// Draw the borders of everything in `layout`, joining up the ones that meet
+pub fn draw_borders(theme: &Theme, layout: &Layout, width: u16, height: u16) -> String {
+    let (width, height) = (width as usize, height as usize);
+    let mut grid: Vec<Option<BorderCell>> = vec![None; width * height];
+    let mut add = |x: u16, y: u16, links: Links, side: Side, border| {
+        let idx = (y as usize - 1) * width + x as usize - 1;
+        let Some(cell) = grid.get_mut(idx) else {
+            return;
+        };
+        let old = cell.map(|c| c.links).unwrap_or_default();
+        *cell = Some(BorderCell {
+            links: Links {
+                up: old.up || links.up,
+                down: old.down || links.down,
+                left: old.left || links.left,
+                right: old.right || links.right,
+            },
+            side,
+            border,
+        });
+    };
+
+    for (rect, border) in layout.borders() {
+        if rect.width == 0 || rect.height == 0 {
+            continue;
+        }
+        let style = &theme.area(border.area).border;
+        let (left, right) = (rect.x, rect.right() - 1);
+        let (top, bottom) = (rect.y, rect.bottom() - 1);
+        let vertical = |y: u16| Links {
+            up: y > top,
+            down: y < bottom,
+            ..Links::default()
+        };
+        let horizontal = |x: u16| Links {
+            left: x > left,
+            right: x < right,
+            ..Links::default()
+        };
+        // the top and bottom go last so that corners are drawn with their characters
+        if border.widths.left > 0 {
+            for y in top..=bottom {
+                add(left, y, vertical(y), Side::Left, style);
+            }
+        }
+        if border.widths.right > 0 {
+            for y in top..=bottom {
+                add(right, y, vertical(y), Side::Right, style);
+            }
+        }
+        if border.widths.top > 0 {
+            for x in left..=right {
+                add(x, top, horizontal(x), Side::Top, style);
+            }
+        }
+        if border.widths.bottom > 0 {
+            for x in left..=right {
+                add(x, bottom, horizontal(x), Side::Bottom, style);
+            }
+        }
+    }
+
+    // join up borders that run into each other, like the end of one box's top next to another box's corner
+    let at = |x: usize, y: usize| grid[y * width + x].map(|c| c.links);
+    let mut out = String::new();
+    for y in 0..height {
+        for x in 0..width {
+            let Some(mut cell) = grid[y * width + x] else {
+                continue;
+            };
+            let horizontal = |l: Links| l.left || l.right;
+            let vertical = |l: Links| l.up || l.down;
+            cell.links.left |= x > 0 && at(x - 1, y).is_some_and(horizontal);
+            cell.links.right |= x + 1 < width && at(x + 1, y).is_some_and(horizontal);
+            cell.links.up |= y > 0 && at(x, y - 1).is_some_and(vertical);
+            cell.links.down |= y + 1 < height && at(x, y + 1).is_some_and(vertical);
+            out.push_str(&format!(
+                "{}{}",
+                termion::cursor::Goto(x as u16 + 1, y as u16 + 1),
+                cell.glyph()
+            ));
+        }
+    }
+    out
+}
+
 impl Gui {
-    pub fn draw_servers<W: Write>(&self, screen: &mut W) {
-        let (_width, height) = termion::terminal_size().unwrap();
-        let height = height - 1;
+    pub fn draw_servers<W: Write>(&self, screen: &mut W, layout: &Layout) {
+        if let Some(rect) = layout.get(Pane::Channels) {
+            self.draw_channel_list(screen, rect);
+        }
+        if let Some(rect) = layout.get(Pane::Servers) {
+            self.draw_server_list(screen, rect);
+        }
+    }
 
-        let mut vert_pos = self.theme.get_servers_start_pos() as u16;
-        let mut idx = 0;
+    fn draw_channel_list<W: Write>(&self, screen: &mut W, rect: Rect) {
+        let width = rect.width as usize;
+        let mut vert_pos = rect.y;
         if let Some(curr_server) = self.curr_server {
             if let Ok(ref net) = &self.servers[curr_server].network {
-                for channel in &net.channels {
+                let scroll = clamp_scroll(
+                    self.channel_scroll,
+                    net.channels.len(),
+                    rect.height as usize,
+                );
+                for (idx, channel) in net
+                    .channels
+                    .iter()
+                    .enumerate()
+                    .skip(scroll)
+                    .take(rect.height as usize)
+                {
+                    let name = truncate(&channel.name, width);
                     write!(
                         screen,
-                        "{}{}{}{}{}{}{}{}",
-                        termion::cursor::Goto(
-                            1 + self.theme.channels.border.left.width(),
-                            vert_pos
-                        ),
-                        termion::color::Fg(termion::color::Reset),
-                        termion::color::Bg(termion::color::Reset),
+                        "{}{}{}{}{}{}",
+                        termion::cursor::Goto(rect.x, vert_pos),
+                        termion::style::Reset,
                         if net.curr_channel.is_some_and(|cc| idx == cc) {
                             if self.focus == Focus::ChannelList {
                                 &self.theme.channels.selected_text
@@ -334,64 +926,163 @@ impl Gui {
                         } else {
                             &self.theme.channels.text
                         },
-                        channel.name,
-                        " ".repeat(self.theme.sidebar_width - channel.name.len()),
-                        termion::color::Bg(termion::color::Reset),
-                        termion::color::Fg(termion::color::Reset),
+                        name,
+                        " ".repeat(width - unicode::width(&name)),
+                        termion::style::Reset,
                     )
                     .unwrap();
                     vert_pos += 1;
-                    idx += 1;
-                    //TODO scrolling
                 }
+                self.draw_scroll_indicators(screen, rect, scroll, net.channels.len());
             }
         }
 
-        while vert_pos < self.theme.get_channels_start_pos(height) as u16 {
+        while vert_pos < rect.bottom() {
             write!(
                 screen,
                 "{}{}",
-                termion::cursor::Goto(1 + self.theme.servers.border.left.width(), vert_pos),
-                " ".repeat(self.theme.sidebar_width),
+                termion::cursor::Goto(rect.x, vert_pos),
+                " ".repeat(width),
             )
             .unwrap();
             vert_pos += 1;
         }
-        vert_pos += 1;
-        // vert_pos = self.theme.get_channels_start_pos(height) as u16 + 1;
-        idx = 0;
-        for server in &self.servers {
-            let backup_name = format!("<{}:{}>", server.ip, server.port);
-            let display_name = server.name.as_ref().unwrap_or(&backup_name);
-            write!(
-                screen,
-                "{}{}{}{}{}{}{}{}",
-                termion::cursor::Goto(1 + self.theme.servers.border.left.width(), vert_pos),
-                termion::color::Fg(termion::color::Reset),
-                termion::color::Bg(termion::color::Reset),
-                if Some(idx) == self.curr_server {
-                    if self.focus == Focus::ServerList {
-                        &self.theme.servers.selected_text
+    }
+
+    fn draw_server_list<W: Write>(&self, screen: &mut W, rect: Rect) {
+        let width = rect.width as usize;
+        let mut vert_pos = rect.y;
+        let rows = self.server_rows();
+        let selected_row = self.selected_server_row(&rows);
+        let scroll = clamp_scroll(self.server_scroll, rows.len(), rect.height as usize);
+        for (row_idx, row) in rows
+            .iter()
+            .enumerate()
+            .skip(scroll)
+            .take(rect.height as usize)
+        {
+            let (text, colour) = match *row {
+                SidebarRow::Folder(folder_idx) => {
+                    let folder = &self.settings.folders[folder_idx];
+                    let unread = self.folder_unread(folder_idx);
+                    let text = if folder.collapsed && unread > 0 {
+                        format!("▸ {} ({})", folder.name, unread)
+                    } else if folder.collapsed {
+                        format!("▸ {}", folder.name)
                     } else {
-                        &self.theme.servers.unfocussed_selected_text
-                    }
-                } else if !server.is_online() {
-                    &self.theme.servers.error_text
+                        format!("▾ {}", folder.name)
+                    };
+                    (text, &self.theme.servers.text)
+                }
+                SidebarRow::Server(server_idx) => {
+                    let server = &self.servers[server_idx];
+                    // indent servers that live in a folder
+                    let in_folder = self
+                        .settings
+                        .folders
+                        .iter()
+                        .any(|f| Some(&f.name) == server.folder.as_ref());
+                    let indent = if in_folder { "  " } else { "" };
+                    let colour = if !server.is_online() {
+                        &self.theme.servers.error_text
+                    } else {
+                        &self.theme.servers.text
+                    };
+                    (format!("{}{}", indent, server_display_name(server)), colour)
+                }
+            };
+            let text = truncate(&text, width);
+            let colour = if Some(row_idx) == selected_row {
+                if self.focus == Focus::ServerList {
+                    &self.theme.servers.selected_text
                 } else {
-                    &self.theme.servers.text
-                },
-                display_name,
-                " ".repeat(self.theme.sidebar_width - display_name.len()),
-                termion::color::Fg(termion::color::Reset),
-                termion::color::Bg(termion::color::Reset),
+                    &self.theme.servers.unfocussed_selected_text
+                }
+            } else {
+                colour
+            };
+            write!(
+                screen,
+                "{}{}{}{}{}{}",
+                termion::cursor::Goto(rect.x, vert_pos),
+                termion::style::Reset,
+                colour,
+                text,
+                " ".repeat(width - unicode::width(&text)),
+                termion::style::Reset,
             )
             .unwrap();
             vert_pos += 1;
-            idx += 1;
+        }
+
+        // clear out rows left over from a longer list, e.g. after collapsing a folder
+        while vert_pos < rect.bottom() {
+            write!(
+                screen,
+                "{}{}",
+                termion::cursor::Goto(rect.x, vert_pos),
+                " ".repeat(width),
+            )
+            .unwrap();
+            vert_pos += 1;
+        }
+        self.draw_scroll_indicators(screen, rect, scroll, rows.len());
+    }
+
+    /// Draw arrows in the last column of a list if there are more items above or below
+    fn draw_scroll_indicators<W: Write>(
+        &self,
+        screen: &mut W,
+        rect: Rect,
+        scroll: usize,
+        len: usize,
+    ) {
+        if rect.height == 0 || rect.width == 0 {
+            return;
+        }
+        let arrow_x = rect.right() - 1;
+        if scroll > 0 {
+            write!(screen, "{}▲", termion::cursor::Goto(arrow_x, rect.y)).unwrap();
+        }
+        if scroll + (rect.height as usize) < len {
+            write!(
+                screen,
+                "{}▼",
+                termion::cursor::Goto(arrow_x, rect.bottom() - 1)
+            )
+            .unwrap();
         }
     }
 
-    pub fn draw_messages<W: Write>(&mut self, screen: &mut W, input_lines: u16) {
+    /// Show which server is selected at the top of the sidebar
+    pub fn draw_header<W: Write>(&self, screen: &mut W, layout: &Layout) {
+        let Some(rect) = layout.get(Pane::Header) else {
+            return;
+        };
+        let lines = match self.curr_server {
+            Some(idx) => [
+                "Connected to".to_owned(),
+                server_display_name(&self.servers[idx]),
+            ],
+            None => ["Not connected".to_owned(), String::new()],
+        };
+        for (y, line) in (rect.y..rect.bottom()).zip(lines.iter()) {
+            write!(
+                screen,
+                "{}{}{}{}",
+                termion::cursor::Goto(rect.x, y),
+                self.theme.status.text,
+                centred(line, rect.width as usize),
+                termion::style::Reset,
+            )
+            .unwrap();
+        }
+    }
+
+    pub fn draw_messages<W: Write>(&mut self, screen: &mut W, layout: &Layout) {
+        let Some(rect) = layout.get(Pane::Messages) else {
+            return;
+        };
         let mut nothing = Vec::new();
         let messages = if let Some(curr_server) = self.curr_server {
             self.servers[curr_server]
@@ -403,369 +1094,518 @@ impl Gui {
             &mut nothing
         };
 
-        // the actual height we have to work with, which is the window height
-        // minus the number of lines the input buffer is taking up.
-        // minus the various borders
-        let height = self.height - input_lines;
-        let max_messages = height as usize
-            - (self.theme.messages.border.top.width()
-                + self.theme.messages.border.bottom.width()
-                + self.theme.edit.border.bottom.width()
-                + self.theme.edit.border.top.width()) as usize;
         let len = messages.len();
-
-        //LOL not a good idea but it works
-        let start_idx = len as isize - max_messages as isize + self.scroll;
-        let start_idx = if start_idx < 0 { 0 } else { start_idx as usize };
-
-        if self.scroll > 0 {
-            self.scroll = 0;
-        }
-        if (self.scroll + start_idx as isize) <= 0 {
-            self.scroll = 0 - start_idx as isize;
+        let height = rect.height as usize;
+        let rows = |idx: usize| message_rows(messages, idx);
+
+        // the scroll is how far above the latest message the bottom one shown is, as a negative
+        // number. It goes up until the oldest message reaches the top of the screen.
+        let mut filled = 0;
+        let highest = (0..len)
+            .find(|&idx| {
+                filled += rows(idx);
+                filled >= height
+            })
+            .unwrap_or(len.saturating_sub(1));
+        // -1 if there aren't any
+        let mut bottom = (len as isize - 1 + self.scroll)
+            .max(highest as isize)
+            .min(len as isize - 1);
+
+        // keep the selected message on screen, scrolling as little as possible
+        if let Some(selected) = self.selected_message.filter(|n| (1..=len).contains(n)) {
+            let target = (len - selected) as isize;
+            bottom = bottom.max(target);
+            let mut shown_rows: usize = (target..=bottom).map(|idx| rows(idx as usize)).sum();
+            while bottom > target && shown_rows > height {
+                shown_rows -= rows(bottom as usize);
+                bottom -= 1;
+            }
         }
+        self.scroll = bottom + 1 - len as isize;
+
+        // messages from the bottom one up, until the screen is full
+        let mut filled = 0;
+        let top = (0..=bottom)
+            .rev()
+            .find(|&idx| {
+                filled += rows(idx as usize);
+                filled >= height
+            })
+            .unwrap_or(0);
+
+        // 1 space of padding on the left, where the bar for messages that mention us goes
+        let max_chars = (rect.width as usize).saturating_sub(1);
+        // lines are counted up from the bottom, so line n goes on row `bottom - n`
+        let bottom_row = rect.bottom();
+
+        let shown = top.max(0) as usize..(bottom + 1).max(0) as usize;
+        // a line saying what day it is goes before the first message of each day
+        let separators: Vec<Option<String>> = shown
+            .clone()
+            .map(|idx| {
+                if !starts_day(messages, idx) {
+                    return None;
+                }
+                let day = local_time(messages[idx].message.date)?.date_naive();
+                let format = if day.year() == chrono::Local::now().year() {
+                    "%A %-d %B"
+                } else {
+                    "%A %-d %B %Y"
+                };
+                Some(format!("— {} —", day.format(format)))
+            })
+            .collect();
 
-        let max_chars: usize = self.width as usize
-            - self.theme.get_left_margin()
-            - self.theme.messages.border.left.width() as usize
-            - self.theme.messages.border.right.width() as usize
-            - 1; // 1 space of padding on the left
-
-        // + 1 because zero-based indexing
-        // + 1 because 1 space of padding on the left
-        let message_start_x = self.theme.get_left_margin() as u16 + 1 + 1;
-
-        let max_lines = height - 2;
-        let total_lines = messages
-            [(start_idx as isize + self.scroll) as usize..(len as isize + self.scroll) as usize]
+        let total_lines = messages[shown.clone()]
             .iter()
-            .fold(0, |acc, msg| acc + msg.lines.len()); // lovely functional goodness
+            .zip(&separators)
+            .fold(0, |acc, (msg, sep)| {
+                acc + msg.lines.len() + sep.is_some() as usize
+            }); // lovely functional goodness
 
         let mut line = total_lines as u16;
 
         let mut buffer: String = "".to_string();
-
-        for (i, message) in messages
-            [(start_idx as isize + self.scroll) as usize..(len as isize + self.scroll) as usize]
-            .iter_mut()
-            .enumerate()
-        {
-            let total_idx = i as isize + start_idx as isize + self.scroll;
-            let highlight = self
-                .selected_message
-                .is_some_and(|x| x as isize == len as isize - total_idx);
+        let separator_style = self.theme.messages.date_separator.to_string();
+        // messages that mention us get a bar beside them, in the padding on the left
+        let mention_bar = format!("{}▌{}", self.theme.messages.mention, termion::style::Reset);
+
+        let first = shown.start;
+        for (i, (message, separator)) in messages[shown].iter_mut().zip(separators).enumerate() {
+            let highlight = self.selected_message == Some(len - (first + i));
+
+            let gutter = if message.mentions_us {
+                mention_bar.as_str()
+            } else {
+                " "
+            };
+
+            if let Some(separator) = separator {
+                if line <= rect.height {
+                    buffer.push_str(&termion::cursor::Goto(rect.x, bottom_row - line).to_string());
+                    buffer.push(' ');
+                    buffer.push_str(&separator_style);
+                    buffer.push_str(&centred(&separator, max_chars));
+                    buffer.push_str(termion::style::Reset.as_ref());
+                }
+                line -= 1;
+            }
 
             let num_lines: usize = message.lines.len();
             for i in 0..num_lines {
-                if line >= max_lines {
+                if line > rect.height {
                     line -= 1;
                     continue;
                 }
 
-                if highlight {
-                    buffer.push_str(termion::style::Bold.as_ref());
-                }
-
-                buffer.push_str(
-                    &termion::cursor::Goto(message_start_x, height - line - 1).to_string(),
-                );
-                buffer.push_str(message.lines[i].to_str());
-                buffer.push_str(&" ".repeat(max_chars - message.lines[i].len()));
-
-                if highlight {
-                    buffer.push_str(termion::style::NoBold.as_ref());
-                }
+                let extra = if highlight {
+                    termion::style::Bold.as_ref()
+                } else {
+                    ""
+                };
+                buffer.push_str(&termion::cursor::Goto(rect.x, bottom_row - line).to_string());
+                buffer.push_str(gutter);
+                buffer.push_str(&message.lines[i].render(extra));
+                buffer.push_str(&" ".repeat(max_chars.saturating_sub(message.lines[i].width())));
                 line -= 1;
             }
         }
         // Fill any remaining space at the top with spaces, so that messages don't stick around in channels without a full history
-        let spaces = " ".repeat(max_chars);
-        line = max_lines - 1;
+        let spaces = " ".repeat(max_chars + 1); // including the padding, where the bar for mentions goes
+        line = rect.height;
         while line > total_lines as u16 {
-            buffer.push_str(&termion::cursor::Goto(message_start_x, height - line - 1).to_string());
+            buffer.push_str(&termion::cursor::Goto(rect.x, bottom_row - line).to_string());
             buffer.push_str(&spaces);
             line -= 1;
         }
-        write!(
-            screen,
-            "{}{}{}",
-            termion::color::Fg(termion::color::Reset),
-            termion::color::Bg(termion::color::Reset),
-            buffer
-        )
-        .unwrap();
+        write!(screen, "{}{}", termion::style::Reset, buffer).unwrap();
     }
 
-    pub fn draw_status_line<W: Write>(&self, screen: &mut W) {
+    pub fn draw_status_line<W: Write>(&self, screen: &mut W, layout: &Layout) {
+        let Some(rect) = layout.get(Pane::Status) else {
+            return;
+        };
         write!(
             screen,
-            "{}{}{}{}{}{}",
-            termion::cursor::Goto(1, self.height),
+            "{}{}{}{}{}",
+            termion::cursor::Goto(rect.x, rect.y),
             self.theme.status.system_message,
-            self.system_message,
-            " ".repeat(self.width as usize - self.system_message.len()),
-            termion::color::Bg(termion::color::Reset),
-            termion::color::Fg(termion::color::Reset),
+            truncate(&self.system_message, rect.width as usize),
+            " ".repeat((rect.width as usize).saturating_sub(unicode::width(&self.system_message))),
+            termion::style::Reset,
         )
         .unwrap();
     }
 
-    pub fn draw_input_buffer<W: Write>(&self, screen: &mut W) -> (u16, u16) {
-        let begin_x = self.theme.sidebar_width as u16
-            + self.theme.servers.border.left.width()
-            + self.theme.servers.border.right.width()
-            + 2; // ?
-        let max_drawing_width = self.width
-            - (begin_x
-                + self.theme.messages.border.right.width()
-                + self.theme.messages.border.left.width());
-
-        // + 1 so that it adds a new line for the cursor to go on (as if the cursor is 1 extra char)
-        let num_lines = ((self.buffer.data.chars().count() + 1) as f64 / max_drawing_width as f64)
-            .ceil() as u16; // TODO kinda inefficient if you think about it
-
-        let mut iter = self.buffer.data.chars();
-        let mut pos = 0;
-        let mut curr_line = 0;
-
-        while pos < self.buffer.data.len() {
-            let mut len = 0;
-            for ch in iter.by_ref().take(max_drawing_width as usize) {
-                len += ch.len_utf8();
-            }
-            let line = &self.buffer.data[pos..pos + len];
-            write!(
-                screen,
-                "{}{}",
-                termion::cursor::Goto(begin_x, self.height - 1 - num_lines + curr_line),
-                line
-            )
-            .unwrap();
-
-            // fill the rest of the line with spaces if it doesn't fill the whole line, to make sure nothing is left behind
-            let curr_line_len = line.len();
-            if curr_line_len < max_drawing_width as usize {
-                write!(
-                    screen,
-                    "{}",
-                    " ".repeat(max_drawing_width as usize - curr_line_len)
-                )
-                .unwrap();
-            }
-
-            pos += len;
-            curr_line += 1;
-        }
-
-        // special case: if we just started a new line & it has nothing on it,
-        // clear the line, because there isn't any bit of the line to trigger the
-        // previous line clearing thingie
-        if curr_line < num_lines {
+    pub fn draw_input_buffer<W: Write>(&self, screen: &mut W, layout: &Layout) {
+        let Some(rect) = layout.get(Pane::Input) else {
+            return;
+        };
+        let begin_x = rect.x + 1; // 1 space of padding on the left, like the messages
+        let max_drawing_width = input_width(rect);
+
+        let rows = self.buffer.rows(max_drawing_width as usize);
+        for line in 0..rect.height {
+            // fill the rest of each line with spaces, to make sure nothing is left behind
+            let text = rows
+                .get(line as usize)
+                .map(|(_, text)| text.as_str())
+                .unwrap_or("");
             write!(
                 screen,
-                "{}{}",
-                termion::cursor::Goto(begin_x, self.height - 2),
-                " ".repeat(max_drawing_width as usize)
+                "{}{}{}",
+                termion::cursor::Goto(begin_x, rect.y + line),
+                text,
+                " ".repeat((max_drawing_width as usize).saturating_sub(unicode::width(text)))
             )
             .unwrap();
         }
-        (num_lines, max_drawing_width)
     }
 
-    pub fn draw_prompt<W: Write>(&self, screen: &mut W) {
-        let y = self.height - self.prompt.as_ref().unwrap().height() - 1;
-        self.prompt.as_ref().unwrap().draw(
-            screen,
-            self.theme.sidebar_width as u16 + 4,
-            y,
-            &self.theme,
-        );
+    pub fn draw_prompt<W: Write>(&self, screen: &mut W, layout: &Layout) {
+        let y = self
+            .height
+            .saturating_sub(self.prompt.as_ref().unwrap().height() + 1)
+            .max(1);
+        let x = layout.get(Pane::Messages).map(|r| r.x + 1).unwrap_or(1);
+        self.prompt
+            .as_ref()
+            .unwrap()
+            .draw(screen, x, y, &self.theme);
     }
 
     pub fn draw_all<W: Write>(&mut self, screen: &mut W) {
-        self.draw_status_line(screen);
+        let layout = self.layout();
+        self.draw_status_line(screen, &layout);
+        self.draw_header(screen, &layout);
 
         match self.mode {
             Mode::Messages | Mode::EditMessage => {
-                let (num_input_lines, max_drawing_width) = self.draw_input_buffer(screen);
+                self.draw_input_buffer(screen, &layout);
                 if !self.servers.is_empty() {
-                    self.draw_messages(screen, num_input_lines);
-                    self.draw_servers(screen);
+                    self.draw_messages(screen, &layout);
+                    self.draw_servers(screen, &layout);
                 }
-                let cursor_x_pos = self.buffer.edit_position % max_drawing_width as usize;
-                let cursor_y_pos = self.buffer.edit_position / max_drawing_width as usize;
-
-                write!(
-                    screen,
-                    "{}",
-                    termion::cursor::Goto(
-                        self.theme.sidebar_width as u16
-                            + self.theme.servers.border.left.width()
-                            + self.theme.servers.border.right.width()
-                            + 2
-                            + cursor_x_pos as u16,
-                        self.height - 1 - num_input_lines.max(1) + cursor_y_pos as u16
+                if let Some(rect) = layout.get(Pane::Input) {
+                    let width = input_width(rect) as usize;
+                    let (cursor_y_pos, cursor_x_pos) = self.buffer.cursor(width);
+                    write!(
+                        screen,
+                        "{}",
+                        termion::cursor::Goto(
+                            rect.x + 1 + cursor_x_pos as u16,
+                            rect.y + cursor_y_pos as u16
+                        )
                     )
-                )
-                .unwrap();
+                    .unwrap();
+                }
             }
             Mode::NewServer => {
                 if !self.servers.is_empty() {
-                    self.draw_servers(screen);
+                    self.draw_servers(screen, &layout);
+                }
+                self.draw_prompt(screen, &layout);
+            }
+            Mode::ImageView => self.draw_image_view(screen),
+            Mode::EditHistory => {
+                if !self.servers.is_empty() {
+                    self.draw_servers(screen, &layout);
                 }
-                self.draw_prompt(screen);
+                self.draw_edit_history(screen, &layout);
+            }
+            Mode::LinkPicker => {
+                if !self.servers.is_empty() {
+                    self.draw_messages(screen, &layout);
+                    self.draw_servers(screen, &layout);
+                }
+                self.draw_prompt(screen, &layout);
+            }
+            Mode::Settings => {
+                if !self.servers.is_empty() {
+                    self.draw_servers(screen, &layout);
+                }
+                self.draw_settings_preview(screen, &layout);
+                self.draw_prompt(screen, &layout);
+            }
+        }
+    }
+
+    /// Draw the versions of a message where the messages usually go, scrolled to the newest
+    fn draw_edit_history<W: Write>(&mut self, screen: &mut W, layout: &Layout) {
+        let Some(rect) = layout.get(Pane::Messages) else {
+            return;
+        };
+        let Some(history) = self.edit_history.as_mut() else {
+            return;
+        };
+        let width = (rect.width as usize).saturating_sub(1); // 1 space of padding on the left
+        let height = rect.height as usize;
+        let total = history.lines(width, &self.theme.messages).len();
+        history.scroll = history.scroll.min(total.saturating_sub(height));
+        let end = total - history.scroll;
+        let lines = history.lines(width, &self.theme.messages); // already rendered for this width now
+        let shown = &lines[end.saturating_sub(height)..end];
+        let mut buffer = String::new();
+        for row in 0..height {
+            buffer.push_str(&termion::cursor::Goto(rect.x + 1, rect.y + row as u16).to_string());
+            match shown.get(row) {
+                Some(line) => {
+                    buffer.push_str(&line.render(""));
+                    buffer.push_str(&" ".repeat(width.saturating_sub(line.width())));
+                }
+                None => buffer.push_str(&" ".repeat(width)),
+            }
+        }
+        write!(screen, "{}{}", termion::style::Reset, buffer).unwrap();
+    }
+
+    /// Draw the image being viewed over the whole screen, with what it is along the bottom
+    fn draw_image_view<W: Write>(&mut self, screen: &mut W) {
+        let (width, height) = (self.width, self.height);
+        let Some(view) = self.image_view.as_mut() else {
+            return;
+        };
+        let (url, image) = &view.images[view.index];
+        let rows = match view.rendered {
+            Some((size, ref rows)) if size == (width, height) => rows,
+            _ => {
+                // the bottom row is for the caption
+                let rows = images::render(
+                    &image.image,
+                    width as u32,
+                    height.saturating_sub(1).max(1) as u32,
+                );
+                &view.rendered.insert(((width, height), rows)).1
             }
-            Mode::Settings => {}
+        };
+        write!(screen, "{}", termion::clear::All).unwrap();
+        let top = (height as usize).saturating_sub(1 + rows.len()) / 2;
+        for (y, row) in rows.iter().enumerate() {
+            let x = (width as usize).saturating_sub(fmt_width(row)) / 2;
+            write!(
+                screen,
+                "{}{}{}",
+                termion::cursor::Goto(x as u16 + 1, (top + y) as u16 + 1),
+                downsample_escapes(row.to_str()),
+                termion::style::Reset
+            )
+            .unwrap();
+        }
+        let caption = format!(
+            "({}/{}) {}  ←/→ for other images, esc to close",
+            view.index + 1,
+            view.images.len(),
+            if url.starts_with("data:") {
+                "[image]"
+            } else {
+                url
+            }
+        );
+        write!(
+            screen,
+            "{}{}{}{}",
+            termion::cursor::Goto(1, height),
+            self.theme.status.text,
+            truncate(&caption, width as usize),
+            termion::style::Reset
+        )
+        .unwrap();
+    }
+
+    /// Clear the messages and show an example one, drawn with the settings as they are in the prompt
+    fn draw_settings_preview<W: Write>(&self, screen: &mut W, layout: &Layout) {
+        let (Some(prompt), Some(settings_screen)) = (&self.prompt, &self.settings_screen) else {
+            return;
+        };
+        let Some(rect) = layout.get(Pane::Messages) else {
+            return;
+        };
+        let mut peers = HashMap::new();
+        peers.insert(
+            0,
+            Peer {
+                uuid: 0,
+                name: prompt.get_str("Nickname").unwrap_or("").to_owned(),
+                pfp: settings_screen
+                    .pfp()
+                    .cloned()
+                    .unwrap_or(FmtString::from_str("  ")),
+            },
+        );
+        let message = api::Message {
+            uuid: 0,
+            content: "This is what messages will look like, with **bold**, *italics* and `code`"
+                .into(),
+            author_uuid: 0,
+            channel_uuid: 0,
+            date: chrono::Utc::now().timestamp() as i32,
+            edited: false,
+        };
+        let preview = LoadedMessage::from_message(message, &peers, &self.message_format());
+
+        let message_start_x = rect.x + 1;
+        let max_chars = (rect.width as usize).saturating_sub(1);
+        // the input box isn't used while the settings are open, so the preview can go over it too
+        let bottom = layout
+            .get(Pane::Input)
+            .map(|input| input.bottom())
+            .unwrap_or(rect.bottom());
+
+        let mut title = StyledLine::default();
+        title.push_str("", "Preview:");
+        let mut lines = vec![title];
+        lines.extend(preview.lines);
+        for y in rect.y..bottom {
+            let line = lines.get((y - rect.y) as usize);
+            write!(
+                screen,
+                "{}{}{}",
+                termion::cursor::Goto(message_start_x, y),
+                line.map(|l| l.render("")).unwrap_or_default(),
+                " ".repeat(max_chars.saturating_sub(line.map(StyledLine::width).unwrap_or(0))),
+            )
+            .unwrap();
         }
     }
 }
 
-pub fn draw_border(theme: &Theme) -> String {
-    let (width, height) = termion::terminal_size().unwrap();
-    let height = height - 1;
-    let channels_height = theme.get_channels_height(height);
-    let servers_height = theme.get_servers_height(height);
+/// How many columns fit on each line of the input box
+pub fn input_width(rect: Rect) -> u16 {
+    rect.width.saturating_sub(1).max(1) // 1 space of padding on the left
+}
 
-    let left_margin = theme.sidebar_width;
+/// The server's name, or where it is if we don't know its name yet
+fn server_display_name(server: &Server) -> String {
+    match server.name {
+        Some(ref name) => name.clone(),
+        None => format!("<{}:{}>", server.ip, server.port),
+    }
+}
 
-    let total_border_width = (theme.servers.border.left.width()
-        + theme.servers.border.right.width()
-        + theme.messages.border.left.width()
-        + theme.messages.border.right.width()) as usize;
-    let space_padding = " ".repeat(width as usize - left_margin - total_border_width);
-    let rs = termion::color::Fg(termion::color::Reset).to_string()
-        + (&termion::color::Bg(termion::color::Reset).to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
 
-    format!("{0}{1}{sttl}{2}{3}\r\n{stleft}{rs}{4}{stright}{mleft}{space_padding}{mright}\r\n{stleft}{rs}{5}{stright}{mleft}{space_padding}{mright}\r\n{6}{7}{8}{9}{sbl}{10}{11}",
-/*0*/       termion::cursor::Goto(1, 1),
-/*1*/       "",
-/*2*/       border_rep(&theme.status.border.top, left_margin),
+    fn errors(theme: serde_json::Value) -> Vec<(String, String)> {
+        validate_theme(&theme)
+            .into_iter()
+            .map(|e| (e.path, e.message))
+            .collect()
+    }
 
-/*3*/       if theme.messages.border.left.width() == 0 || theme.channels.border.right.width() == 0 {
-            format!("{sttop_split}{}{mtr}", 
-                border_rep(&theme.messages.border.top, width as usize - left_margin - total_border_width),
+    fn debug(value: impl fmt::Debug) -> String {
+        format!("{:?}", value)
+    }
 
-                mtr = theme.messages.border.tr,
-                sttop_split = theme.status.border.top_split,
-            )
-        } else {
-            format!("{sttr}{mtl}{}{mtr}", 
-                border_rep(&theme.messages.border.top, width as usize - left_margin - total_border_width),
+    #[test]
+    fn wrong_types_are_reported_where_they_are() {
+        assert_eq!(
+            errors(json!({"messages": 5})),
+            [("messages".to_owned(), "expected an object".to_owned())]
+        );
+        assert_eq!(
+            errors(json!({"messages": {"border-tl": ["x", 5, "red"]}})),
+            [(
+                "messages.border-tl[1]".to_owned(),
+                "expected a colour name, 256 colour index or #rrggbb as a string".to_owned()
+            )]
+        );
+        assert_eq!(
+            errors(json!({"edit": {"text-attributes": ["bold", 3]}})),
+            [(
+                "edit.text-attributes".to_owned(),
+                "expected a list of strings".to_owned()
+            )]
+        );
+        assert_eq!(
+            errors(json!({"extends": ["default"], "status": {"nope": "red"}})),
+            [
+                (
+                    "extends".to_owned(),
+                    "expected the name of a theme".to_owned()
+                ),
+                ("status.nope".to_owned(), "unknown key".to_owned())
+            ]
+        );
+        assert_eq!(errors(json!([])).len(), 1);
+    }
 
-                mtr = theme.messages.border.tr,
-                mtl = theme.messages.border.tl,
-                sttr = theme.status.border.tr,
-            )
-        },
+    #[test]
+    fn unknown_colours() {
+        assert_eq!(
+            errors(json!({"global": {"text-foreground": "purple", "text-background": "#12345"}})),
+            [
+                (
+                    "global.text-background".to_owned(),
+                    "unknown colour '#12345'".to_owned()
+                ),
+                (
+                    "global.text-foreground".to_owned(),
+                    "unknown colour 'purple'".to_owned()
+                )
+            ]
+        );
+        assert!(errors(json!({"global": {"text-foreground": "light red"}})).is_empty());
+    }
 
-/*4*/       centred("Connected to", left_margin),
+    #[test]
+    fn missing_and_broken_keys_use_the_defaults() {
+        let (theme, errors) = Theme::from_str(
+            r##"{"global": {"text-foreground": "#010203"}, "messages": {"text-background": 5}}"##,
+            "test",
+            20,
+        )
+        .unwrap();
+        assert_eq!(errors.len(), 1);
+        let fallback = Theme::fallback(20);
+        assert_eq!(
+            debug(theme.messages.text.fg),
+            debug(ThemeColour::Rgb(1, 2, 3))
+        );
+        assert_eq!(
+            debug(theme.messages.text.bg),
+            debug(fallback.messages.text.bg)
+        );
+        assert_eq!(
+            debug(&theme.channels.border),
+            debug(&fallback.channels.border)
+        );
+    }
 
-/*5*/       centred("cospox.com", theme.sidebar_width),
+    #[test]
+    fn extends_merges_on_top() {
+        let mut errors = Vec::new();
+        let mut seen = Vec::new();
+        let theme = json!({"extends": "cmus", "global": {"text-foreground": "red"}});
+        let merged = resolve_extends(theme, &mut errors, &mut seen);
+        assert!(errors.is_empty());
+        assert_eq!(seen, ["cmus"]);
+        assert_eq!(merged["global"]["text-foreground"], "red");
+        assert_eq!(merged["global"]["system-message-background"], "white");
+    }
 
-/*6*/       if theme.channels.border.bottom.width() > 0 {
-            format!("{stleft_split}{}{stright_split}{mleft}{}{mright}\r\n",
-                border_rep(&theme.channels.border.bottom, left_margin),
-                space_padding,
-                stleft_split = theme.status.border.left_split,
-                stright_split = theme.status.border.right_split,
-                mright = theme.messages.border.right,
-                mleft = theme.messages.border.left,
-            )
-        } else {
-            "".to_string()
-        },
-
-/*7*/       format!("{cleft}{rs}{}{cright}{rs}{mleft}{}{mright}\r\n",
-            " ".repeat(left_margin),
-            space_padding,
-            rs = rs,
-            cleft = theme.channels.border.left,
-            cright = theme.channels.border.right,
-            mright = theme.messages.border.right,
-            mleft = theme.messages.border.left,
-        ).repeat(channels_height),
-
-/*8*/       if theme.channels.border.bottom.width() > 0 && theme.servers.border.top.width() > 0 {
-            format!("{cbl}{}{cbr}{mleft}{}{mright}\r\n{stl}{}{str}{mleft}{}{mright}\r\n",
-                border_rep(&theme.channels.border.bottom, left_margin),
-                space_padding,
-                border_rep(&theme.servers.border.top, left_margin),
-                space_padding,
-                cbl = theme.channels.border.bl,
-                cbr = theme.channels.border.br,
-                str = theme.servers.border.tr,
-                stl = theme.servers.border.tl,
-                mright = theme.messages.border.right,
-                mleft = theme.messages.border.left,
-            )
-        } else if theme.channels.border.bottom.width() > 0 {
-            format!("{cleft_split}{}{cright_split}{mleft}{}{mright}\r\n", 
-                border_rep(&theme.channels.border.bottom, left_margin),
-                space_padding,
-                cleft_split = theme.channels.border.left_split,
-                cright_split = theme.channels.border.right_split,
-                mright = theme.messages.border.right,
-                mleft = theme.messages.border.left,
-            )
-        } else if theme.servers.border.top.width() > 0 {
-            format!("{sleft_split}{}{sright_split}{mleft}{}{mright}\r\n", 
-                border_rep(&theme.servers.border.top, left_margin),
-                space_padding,
-                sleft_split = theme.servers.border.left_split,
-                sright_split = theme.servers.border.right_split,
-                mright = theme.messages.border.right,
-                mleft = theme.messages.border.left,
-            )
-        } else {
-            "".to_string()
-        },
-
-/*9*/       format!("{sleft}{rs}{}{sright}{rs}{mleft}{}{mright}\r\n", 
-            " ".repeat(theme.sidebar_width), 
-            space_padding,
-            rs = rs,
-            sleft = theme.servers.border.left,
-            sright = theme.servers.border.right,
-            mright = theme.messages.border.right,
-            mleft = theme.messages.border.left,
-        ).repeat(servers_height),
-
-/*10*/      border_rep(&theme.servers.border.bottom, left_margin),
-
-/*11*/      if theme.messages.border.left.width() == 0 || theme.servers.border.right.width() == 0 {
-            format!("{sbottom_split}{}{mbr}", 
-                border_rep(&theme.messages.border.bottom, width as usize - left_margin - total_border_width),
-
-                mbr = theme.messages.border.br,
-                sbottom_split = theme.servers.border.bottom_split,
-            )
-        } else {
-            format!("{sbr}{mbl}{}{mbr}", 
-                border_rep(&theme.messages.border.bottom, width as usize - left_margin - total_border_width),
+    #[test]
+    fn extends_cycles_stop() {
+        // as if this theme had been reached by going round from cmus
+        let mut errors = Vec::new();
+        let mut seen = vec!["cmus".to_owned()];
+        let theme = json!({"extends": "cmus", "global": {"text-foreground": "red"}});
+        let merged = resolve_extends(theme.clone(), &mut errors, &mut seen);
+        assert_eq!(merged, theme);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, "extends");
+        assert_eq!(errors[0].message, "'cmus' ends up extending itself");
+    }
 
-                mbr = theme.messages.border.br,
-                mbl = theme.messages.border.bl,
-                sbr = theme.servers.border.br,
-            )
-        },
-
-        //stl = theme.servers.border.tl,
-        sttl = theme.status.border.tl,
-        sbl = theme.servers.border.bl,
-        //ctop_split = theme.channels.border.top_split,
-        //sleft = theme.servers.border.left,
-        //cleft = theme.channels.border.left,
-        stleft = theme.status.border.left,
-        //sright = theme.servers.border.right,
-        //sleft_split = theme.servers.border.left_split,
-        //sright_split = theme.servers.border.right_split,
-        //sbottom_split = theme.servers.border.bottom_split,
-        mright = theme.messages.border.right,
-        //cright = theme.channels.border.right,
-        stright = theme.status.border.right,
-        mleft = theme.messages.border.left,
-        rs = rs,
-        //mbr = theme.messages.border.br,
-        //mtr = theme.messages.border.tr,
-    )
+    #[test]
+    fn extending_a_missing_theme() {
+        let mut errors = Vec::new();
+        let theme = json!({"extends": "no such theme, hopefully"});
+        let merged = resolve_extends(theme.clone(), &mut errors, &mut Vec::new());
+        assert_eq!(merged, theme);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.starts_with("couldn't load"));
+    }
 }
@@ -1,12 +1,25 @@
 use super::Focus;
 use super::Mode;
+use crate::clipboard::Yank;
+use crate::drawing::clamp_scroll;
+use crate::folders::SidebarRow;
 use crate::gui::Gui;
+use crate::layout::{Layout, Pane, SidebarSide};
 use crate::prompt::EditBuffer;
 use crate::prompt::PromptEvent;
 use crate::server::Identification;
 use crate::server::WriteAsterRequestAsync;
 use termion::event::{Event, Key, MouseButton, MouseEvent};
 
+/// Adjust a list's scroll offset so that the item at `idx` is within the `height` visible rows
+fn scroll_to_show(scroll: &mut usize, idx: usize, height: usize) {
+    if idx < *scroll {
+        *scroll = idx;
+    } else if height > 0 && idx >= *scroll + height {
+        *scroll = idx + 1 - height;
+    }
+}
+
 impl Gui {
     async fn send_message_to_server(&mut self, server: usize) -> Result<(), String> {
         let net = self.servers[server]
@@ -65,7 +78,7 @@ impl Gui {
     }
 
     fn select_message_up(&mut self) {
-        // TODO modify scroll if goes offscreen
+        // drawing scrolls to keep it on screen
         self.selected_message = match self.selected_message {
             None => Some(1),
             Some(n) => Some(n + 1),
@@ -82,20 +95,34 @@ impl Gui {
     async fn focus_edit_event(&mut self, event: Event) {
         match event {
             Event::Key(Key::Char('\n')) => self.handle_send_message().await,
+            // completes a name after an @, or if there isn't one, it's just typed
+            Event::Key(Key::Char('\t')) if self.complete_mention() => (),
             Event::Key(Key::Char(ch)) => self.buffer.push(ch),
             Event::Key(Key::Backspace) => self.buffer.pop(),
             Event::Key(Key::Ctrl('h')) => self.buffer.pop_word(),
             Event::Key(Key::Left) => self.buffer.left(),
             Event::Key(Key::Right) => self.buffer.right(),
 
-            Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _)) => {
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, x, y))
+                if !self.in_sidebar(x, y) =>
+            {
                 self.scroll -= 1;
             }
 
-            Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, _, _)) => {
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, x, y))
+                if !self.in_sidebar(x, y) =>
+            {
                 self.scroll += 1;
             }
 
+            Event::Key(Key::Ctrl('o')) => self.open_link_picker(),
+            Event::Key(Key::Alt('i')) => self.open_image_view(),
+            Event::Key(Key::Alt('h')) => self.open_edit_history(),
+            Event::Key(Key::Alt('r')) => self.reply(),
+            Event::Key(Key::Alt('j')) => self.jump_to_original().await,
+            Event::Key(Key::Ctrl('y')) => self.yank_key(Yank::Content),
+            Event::Key(Key::Alt('y')) => self.yank_key(Yank::Author),
+            Event::Key(Key::Alt('Y')) => self.yank_key(Yank::Reference),
             Event::Key(Key::Up) => self.select_message_up(),
             Event::Key(Key::Down) => self.select_message_down(),
             Event::Key(Key::Esc) if self.mode == Mode::EditMessage => {
@@ -108,35 +135,77 @@ impl Gui {
     }
 
     fn focus_servers_event(&mut self, event: Event) {
-        if let Some(curr_server) = self.curr_server.as_mut() {
-            match event {
-                Event::Key(Key::Up) => {
-                    if *curr_server > 0 {
-                        *curr_server -= 1;
-                    }
-                }
+        let rows = self.server_rows();
+        if rows.is_empty() {
+            return;
+        }
+        let Some(curr_row) = self.selected_server_row(&rows) else {
+            self.select_server_row(rows[0]);
+            self.show_selected_server();
+            return;
+        };
+        let page = self.list_height(Pane::Servers);
+        match event {
+            Event::Key(Key::Up) if curr_row > 0 => self.select_server_row(rows[curr_row - 1]),
+            Event::Key(Key::Down) if curr_row < rows.len() - 1 => {
+                self.select_server_row(rows[curr_row + 1])
+            }
 
-                Event::Key(Key::Down) => {
-                    if *curr_server < self.servers.len() - 1 {
-                        *curr_server += 1;
-                    }
+            Event::Key(Key::PageUp) => self.select_server_row(rows[curr_row.saturating_sub(page)]),
+            Event::Key(Key::PageDown) => {
+                self.select_server_row(rows[(curr_row + page).min(rows.len() - 1)])
+            }
+            Event::Key(Key::Home) => self.select_server_row(rows[0]),
+            Event::Key(Key::End) => self.select_server_row(rows[rows.len() - 1]),
+
+            // folders: enter or space toggles, right expands, left collapses
+            Event::Key(Key::Char('\n')) | Event::Key(Key::Char(' ')) => {
+                if let SidebarRow::Folder(folder) = rows[curr_row] {
+                    self.toggle_folder(folder);
+                }
+            }
+            Event::Key(Key::Right) => {
+                if let SidebarRow::Folder(folder) = rows[curr_row] {
+                    self.set_folder_collapsed(folder, false);
                 }
-                _ => (),
             }
-        } else if !self.servers.is_empty() {
-            self.curr_server = Some(0);
+            Event::Key(Key::Left) => match rows[curr_row] {
+                SidebarRow::Folder(folder) => self.set_folder_collapsed(folder, true),
+                SidebarRow::Server(server) => {
+                    let folder_name = self.servers[server].folder.clone();
+                    if let Some(folder) = self
+                        .settings
+                        .folders
+                        .iter()
+                        .position(|f| Some(&f.name) == folder_name.as_ref())
+                    {
+                        self.set_folder_collapsed(folder, true);
+                        self.selected_folder = Some(folder);
+                    }
+                }
+            },
+
+            Event::Key(Key::Char('n')) => self.start_command("/folder new "),
+            Event::Key(Key::Char('r')) => self.start_command("/folder rename "),
+            Event::Key(Key::Char('m')) => self.start_command("/folder move "),
+            _ => (),
         }
+        self.show_selected_server();
     }
 
     async fn focus_channels_event(&mut self, event: Event) {
         let Some(curr_server) = self.curr_server else {
             return;
         };
+        let page = self.list_height(Pane::Channels);
         let s = &mut self.servers[curr_server];
-        let net = s
-            .network
-            .as_mut()
-            .expect("Offline server somehow changed their channel");
+        let Ok(ref mut net) = s.network else {
+            return; // offline servers don't have any channels
+        };
+        if net.channels.is_empty() {
+            return;
+        }
+        let last = net.channels.len() - 1;
         let switching_channel = match event {
             Event::Key(Key::Up) => {
                 if net.curr_channel.is_some_and(|x| x > 0) {
@@ -147,52 +216,162 @@ impl Gui {
             }
 
             Event::Key(Key::Down) => {
-                if net.curr_channel.is_some_and(|x| x < net.channels.len() - 1) {
+                if net.curr_channel.is_some_and(|x| x < last) {
                     Some(net.curr_channel.unwrap() + 1)
-                } else if net.curr_channel.is_none() && !net.channels.is_empty() {
+                } else if net.curr_channel.is_none() {
                     Some(0)
                 } else {
                     None
                 }
             }
+
+            Event::Key(Key::PageUp) => Some(net.curr_channel.unwrap_or(0).saturating_sub(page)),
+            Event::Key(Key::PageDown) => {
+                Some(net.curr_channel.map(|c| (c + page).min(last)).unwrap_or(0))
+            }
+            Event::Key(Key::Home) => Some(0),
+            Event::Key(Key::End) => Some(last),
             _ => None,
         };
 
-        if let Some(idx) = switching_channel {
+        if let Some(idx) = switching_channel.filter(|&idx| net.curr_channel != Some(idx)) {
             net.switch_channel(idx).await;
         }
+        self.show_selected_channel();
+    }
+
+    /// Adjust the channel list's scroll so that the current channel is visible
+    pub fn show_selected_channel(&mut self) {
+        let height = self.list_height(Pane::Channels);
+        let selected = self
+            .curr_server
+            .and_then(|s| self.servers[s].network.as_ref().ok())
+            .and_then(|net| net.curr_channel);
+        match selected {
+            Some(idx) => scroll_to_show(&mut self.channel_scroll, idx, height),
+            None => self.channel_scroll = 0,
+        }
+    }
+
+    /// Adjust the server list's scroll so that the selected row is visible
+    pub fn show_selected_server(&mut self) {
+        let height = self.list_height(Pane::Servers);
+        let rows = self.server_rows();
+        if let Some(idx) = self.selected_server_row(&rows) {
+            scroll_to_show(&mut self.server_scroll, idx, height);
+        }
+    }
+
+    /// How many rows of a list are visible, or 0 if it's hidden
+    fn list_height(&self, pane: Pane) -> usize {
+        self.layout()
+            .get(pane)
+            .map(|rect| rect.height as usize)
+            .unwrap_or(0)
+    }
+
+    /// Whether `x`, `y` is inside one of the sidebar's panes
+    fn in_sidebar(&self, x: u16, y: u16) -> bool {
+        matches!(
+            self.layout().pane_at(x, y),
+            Some(Pane::Header | Pane::Channels | Pane::Servers)
+        )
+    }
+
+    /// Scroll whichever sidebar list is at `x`, `y` by `amount` rows
+    fn scroll_sidebar(&mut self, layout: &Layout, x: u16, y: u16, amount: isize) {
+        let Some(pane) = layout.pane_at(x, y) else {
+            return;
+        };
+        let height = layout.get(pane).unwrap().height as usize; // unwrap: the pane was just found
+        match pane {
+            Pane::Channels => {
+                let len = self
+                    .curr_server
+                    .and_then(|s| self.servers[s].network.as_ref().ok())
+                    .map(|net| net.channels.len())
+                    .unwrap_or(0);
+                let scroll = clamp_scroll(self.channel_scroll, len, height);
+                self.channel_scroll =
+                    clamp_scroll(scroll.saturating_add_signed(amount), len, height);
+            }
+            Pane::Servers => {
+                let len = self.server_rows().len();
+                let scroll = clamp_scroll(self.server_scroll, len, height);
+                self.server_scroll =
+                    clamp_scroll(scroll.saturating_add_signed(amount), len, height);
+            }
+            _ => (),
+        }
     }
 
     pub async fn focus_any_event(&mut self, event: Event) {
-        if let Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y)) = event {
-            if x >= self.theme.sidebar_width as u16
-                + self.theme.channels.border.left.width()
-                + self.theme.channels.border.right.width()
-                || x <= self.theme.channels.border.left.width()
+        let layout = self.layout();
+        // the column of the border between the sidebar and the messages, which can be dragged
+        let sidebar_border_x = layout.sidebar_border_x();
+        match event {
+            Event::Mouse(MouseEvent::Press(MouseButton::Left, x, _))
+                if Some(x) == sidebar_border_x =>
             {
-                return;
+                self.resizing_sidebar = true;
+            }
+            Event::Mouse(MouseEvent::Hold(x, _)) if self.resizing_sidebar => {
+                let Some(border_x) = sidebar_border_x else {
+                    return;
+                };
+                // the sidebar gets wider as its border is dragged away from it
+                let moved = match self.settings.layout.sidebar {
+                    SidebarSide::Left => x as isize - border_x as isize,
+                    SidebarSide::Right => border_x as isize - x as isize,
+                };
+                self.set_sidebar_width(self.theme.sidebar_width.saturating_add_signed(moved));
+            }
+            Event::Mouse(MouseEvent::Release(_, _)) => self.resizing_sidebar = false,
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, x, y)) => {
+                self.scroll_sidebar(&layout, x, y, -1)
+            }
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, x, y)) => {
+                self.scroll_sidebar(&layout, x, y, 1)
+            }
+            Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y)) => {
+                self.click_sidebar(&layout, x, y).await
             }
+            _ => (),
+        }
+    }
 
-            if y >= self.theme.get_servers_start_pos() as u16
-                && y < self.theme.get_channels_start_pos(self.height) as u16 - 1
-            {
-                let idx = y as usize - self.theme.get_servers_start_pos();
-                let Some(curr_server) = self.curr_server.map(|idx| &mut self.servers[idx]) else {
+    async fn click_sidebar(&mut self, layout: &Layout, x: u16, y: u16) {
+        let Some(pane) = layout.pane_at(x, y) else {
+            return;
+        };
+        let rect = layout.get(pane).unwrap(); // unwrap: the pane was just found
+        let height = rect.height as usize;
+        match pane {
+            Pane::Channels => {
+                let Some(curr_server) = self.curr_server else {
                     return;
                 };
-                if let Ok(ref mut net) = curr_server.network {
-                    if idx < net.channels.len() && !net.curr_channel.is_some_and(|c| c == idx) {
+                if let Ok(ref mut net) = self.servers[curr_server].network {
+                    let scroll = clamp_scroll(self.channel_scroll, net.channels.len(), height);
+                    let idx = scroll + (y - rect.y) as usize;
+                    if idx < net.channels.len() && net.curr_channel != Some(idx) {
                         net.switch_channel(idx).await;
                     }
                 }
-            } else if y >= self.theme.get_channels_start_pos(self.height) as u16
-                && y < self.height - self.theme.servers.border.bottom.width()
-            {
-                let idx = y as usize - self.theme.get_channels_start_pos(self.height);
-                if idx < self.servers.len() {
-                    self.curr_server = Some(idx);
+            }
+            Pane::Servers => {
+                let rows = self.server_rows();
+                let scroll = clamp_scroll(self.server_scroll, rows.len(), height);
+                match rows.get(scroll + (y - rect.y) as usize) {
+                    Some(&SidebarRow::Folder(folder)) => {
+                        self.toggle_folder(folder);
+                        self.selected_folder = Some(folder);
+                    }
+                    Some(&SidebarRow::Server(server)) => self.select_server(server),
+                    None => (),
                 }
             }
+            _ => (),
         }
     }
 
@@ -209,6 +388,12 @@ impl Gui {
             Event::Key(Key::Alt('e')) => {
                 self.focus = Focus::Edit;
             }
+            Event::Key(Key::Alt('<')) => {
+                self.set_sidebar_width(self.theme.sidebar_width.saturating_sub(1));
+            }
+            Event::Key(Key::Alt('>')) => {
+                self.set_sidebar_width(self.theme.sidebar_width + 1);
+            }
             _ => (),
         }
         if self.mode == Mode::Messages || self.mode == Mode::EditMessage {
@@ -222,7 +407,7 @@ impl Gui {
         } else if self.mode == Mode::NewServer {
             let p = self.prompt.as_mut().unwrap();
             match p.handle_event(key) {
-                Some(PromptEvent::ButtonPressed("Connect")) => {
+                Some(PromptEvent::ButtonPressed("Connect")) if p.validate() => {
                     // TODO LOTS OF ERROR HANDLING LOL
                     // this will break at the slight hint of any issue
 
@@ -237,9 +422,18 @@ impl Gui {
                     self.mode = Mode::Messages;
                     self.prompt = None;
                 }
+                Some(PromptEvent::ButtonPressed("Connect")) => (), // invalid fields are shown by validate()
                 Some(PromptEvent::ButtonPressed(_)) => unreachable!(), // no idea
                 None => (),
             }
+        } else if self.mode == Mode::Settings {
+            self.settings_event(key).await;
+        } else if self.mode == Mode::LinkPicker {
+            self.link_picker_event(key);
+        } else if self.mode == Mode::ImageView {
+            self.image_view_event(key);
+        } else if self.mode == Mode::EditHistory {
+            self.edit_history_event(key);
         }
         true
     }
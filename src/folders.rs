@@ -0,0 +1,195 @@
+use crate::api::Folder;
+use crate::gui::{CommandError, Gui};
+use crate::prompt::EditBuffer;
+use crate::Focus;
+
+/// One line of the server list: either a folder header or a server
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SidebarRow {
+    Folder(usize),
+    Server(usize),
+}
+
+impl Gui {
+    /// The rows of the server list as they are displayed, top to bottom.
+    /// Servers without a folder come first, followed by each folder and its (uncollapsed) servers.
+    pub fn server_rows(&self) -> Vec<SidebarRow> {
+        let known_folder = |name: &String| self.settings.folders.iter().any(|f| &f.name == name);
+        let mut rows: Vec<SidebarRow> = (0..self.servers.len())
+            .filter(|&idx| !self.servers[idx].folder.as_ref().is_some_and(known_folder))
+            .map(SidebarRow::Server)
+            .collect();
+
+        for (f_idx, folder) in self.settings.folders.iter().enumerate() {
+            rows.push(SidebarRow::Folder(f_idx));
+            if folder.collapsed {
+                continue;
+            }
+            rows.extend(
+                (0..self.servers.len())
+                    .filter(|&idx| self.servers[idx].folder.as_ref() == Some(&folder.name))
+                    .map(SidebarRow::Server),
+            );
+        }
+        rows
+    }
+
+    /// Total unread messages across all of the servers in a folder
+    pub fn folder_unread(&self, folder: usize) -> usize {
+        let name = &self.settings.folders[folder].name;
+        self.servers
+            .iter()
+            .filter(|s| s.folder.as_ref() == Some(name))
+            .map(|s| s.unread)
+            .sum()
+    }
+
+    fn folder_of_server(&self, server: usize) -> Option<usize> {
+        let name = self.servers[server].folder.as_ref()?;
+        self.settings.folders.iter().position(|f| &f.name == name)
+    }
+
+    /// The row that should be highlighted in the server list. If the current
+    /// server is hidden inside a collapsed folder, that's the folder's header.
+    pub fn selected_server_row(&self, rows: &[SidebarRow]) -> Option<usize> {
+        if let Some(folder) = self.selected_folder {
+            return rows.iter().position(|r| *r == SidebarRow::Folder(folder));
+        }
+        let server = self.curr_server?;
+        rows.iter()
+            .position(|r| *r == SidebarRow::Server(server))
+            .or_else(|| {
+                let folder = self.folder_of_server(server)?;
+                rows.iter().position(|r| *r == SidebarRow::Folder(folder))
+            })
+    }
+
+    pub fn select_server(&mut self, idx: usize) {
+        self.curr_server = Some(idx);
+        self.selected_folder = None;
+        self.servers[idx].unread = 0;
+        self.channel_scroll = 0;
+        self.show_selected_channel();
+        self.load_images();
+    }
+
+    pub fn select_server_row(&mut self, row: SidebarRow) {
+        match row {
+            SidebarRow::Server(idx) => self.select_server(idx),
+            SidebarRow::Folder(idx) => self.selected_folder = Some(idx),
+        }
+    }
+
+    pub fn set_folder_collapsed(&mut self, folder: usize, collapsed: bool) {
+        // uploaded on quit, see upload_folders_on_quit
+        self.settings.folders[folder].collapsed = collapsed;
+    }
+
+    pub fn toggle_folder(&mut self, folder: usize) {
+        let collapsed = self.settings.folders[folder].collapsed;
+        self.set_folder_collapsed(folder, !collapsed);
+    }
+
+    /// Put a command into the edit buffer for the user to finish off
+    pub fn start_command(&mut self, command: &str) {
+        self.buffer = EditBuffer::new(command.to_owned());
+        self.focus = Focus::Edit;
+    }
+
+    /// The folder that folder commands act on: the highlighted one, or else the current server's
+    fn target_folder(&self) -> Option<usize> {
+        self.selected_folder
+            .or_else(|| self.curr_server.and_then(|s| self.folder_of_server(s)))
+    }
+
+    /// `/folder new <name>`, `/folder rename <name>`, `/folder move [name]`, `/folder delete`
+    pub fn handle_folder_command(&mut self, cmd: &str) -> Result<(), CommandError> {
+        let folders = self.settings.folders.clone();
+        self.try_folder_command(cmd)?;
+        // the sync server only knows about the folders themselves, not which servers are in them
+        if self.settings.folders != folders {
+            self.upload_sync_data();
+        }
+        Ok(())
+    }
+
+    fn try_folder_command(&mut self, cmd: &str) -> Result<(), CommandError> {
+        let args = cmd.split_once(' ').map(|(_, rest)| rest).unwrap_or("");
+        let (subcommand, name) = args.split_once(' ').unwrap_or((args, ""));
+        let name = name.trim();
+        match subcommand {
+            "new" => {
+                if name.is_empty() {
+                    return Err(CommandError("Usage: /folder new <name>".into()));
+                }
+                if self.settings.folders.iter().any(|f| f.name == name) {
+                    return Err(CommandError(format!("Folder '{}' already exists", name)));
+                }
+                self.settings.folders.push(Folder {
+                    name: name.to_owned(),
+                    collapsed: false,
+                });
+                self.send_system(&format!("Created folder '{}'", name));
+                Ok(())
+            }
+            "rename" => {
+                if name.is_empty() {
+                    return Err(CommandError("Usage: /folder rename <new name>".into()));
+                }
+                let folder = self
+                    .target_folder()
+                    .ok_or(CommandError("No folder is selected".into()))?;
+                if self.settings.folders.iter().any(|f| f.name == name) {
+                    return Err(CommandError(format!("Folder '{}' already exists", name)));
+                }
+                let old_name =
+                    std::mem::replace(&mut self.settings.folders[folder].name, name.to_owned());
+                for server in &mut self.servers {
+                    if server.folder.as_ref() == Some(&old_name) {
+                        server.folder = Some(name.to_owned());
+                    }
+                }
+                self.send_system(&format!("Renamed folder '{}' to '{}'", old_name, name));
+                Ok(())
+            }
+            "move" => {
+                let server = self.curr_server.ok_or(CommandError(
+                    "No server is selected you silly goose!".into(),
+                ))?;
+                if name.is_empty() {
+                    self.servers[server].folder = None;
+                    return Ok(());
+                }
+                if !self.settings.folders.iter().any(|f| f.name == name) {
+                    self.settings.folders.push(Folder {
+                        name: name.to_owned(),
+                        collapsed: false,
+                    });
+                }
+                self.servers[server].folder = Some(name.to_owned());
+                self.selected_folder = None;
+                Ok(())
+            }
+            "delete" => {
+                let folder = self
+                    .target_folder()
+                    .ok_or(CommandError("No folder is selected".into()))?;
+                let removed = self.settings.folders.remove(folder);
+                for server in &mut self.servers {
+                    if server.folder.as_ref() == Some(&removed.name) {
+                        server.folder = None;
+                    }
+                }
+                self.selected_folder = None;
+                self.send_system(&format!(
+                    "Deleted folder '{}', its servers have been ungrouped",
+                    removed.name
+                ));
+                Ok(())
+            }
+            _ => Err(CommandError(
+                "Usage: /folder new|rename|move|delete [name]".into(),
+            )),
+        }
+    }
+}
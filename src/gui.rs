@@ -1,15 +1,26 @@
 extern crate termion;
 
-use crate::api::Request;
-use crate::drawing::Theme;
+use crate::api::{Folder, Request};
+use crate::drawing::{input_width, join_theme_errors, Theme};
+use crate::history::EditHistory;
+use crate::images::{ImageCache, ImageLoader, ImageView};
+use crate::layout::{
+    Border, BorderArea, BorderWidths, Direction, Layout, Node, Pane, SidebarSide, Size,
+};
+use crate::links::LinkPicker;
+use crate::markdown::MessageStyles;
+use crate::mentions::Completion;
 use crate::prompt::{EditBuffer, Prompt, PromptField};
-use crate::server::{Identification, LoadedMessage, Server};
+use crate::server::{
+    HistoryRequest, Identification, LoadedMessage, MessageFormat, Server, ServerId,
+};
+use crate::settings::SettingsScreen;
+use crate::template::Template;
 use crate::Focus;
 use crate::LocalMessage;
 use crate::Mode;
 use crate::Settings;
 use std::io::Write;
-use std::net::SocketAddr;
 use std::sync::mpsc::Sender;
 use tokio::sync::broadcast;
 
@@ -19,6 +30,9 @@ pub struct Gui {
     pub tx: Sender<LocalMessage>,
     pub servers: Vec<Server>,
     pub curr_server: Option<usize>,
+    pub selected_folder: Option<usize>, // a folder header highlighted in the server list
+    pub channel_scroll: usize,
+    pub server_scroll: usize,
     pub mode: Mode,
     pub focus: Focus,
     pub theme: Theme,
@@ -29,8 +43,22 @@ pub struct Gui {
     pub cancel: broadcast::Sender<()>,
     pub settings: Settings,
     pub selected_message: Option<usize>,
+    pub resizing_sidebar: bool, // the sidebar border is being dragged with the mouse
+    pub settings_screen: Option<SettingsScreen>,
+    pub link_picker: Option<LinkPicker>,
+    pub template: Template, // parsed from settings.message_template
+    pub completion: Option<Completion>,
+    pub clipboard: Option<String>, // waiting to be sent to the terminal
+    pub images: ImageCache,
+    pub image_loader: ImageLoader,
+    pub image_view: Option<ImageView>,
+    pub edit_history: Option<EditHistory>,
+    pub uploaded_folders: Vec<Folder>, // as the sync server last had them
 }
 
+pub const MIN_SIDEBAR_WIDTH: usize = 8;
+const MIN_MESSAGES_WIDTH: usize = 24;
+
 #[derive(Debug)]
 pub struct CommandError(pub String);
 
@@ -41,15 +69,24 @@ impl Gui {
         settings: Settings,
         servers: Vec<Server>,
     ) -> Self {
-        Gui {
+        let (theme, theme_errors) = match Theme::new(&settings.theme, settings.sidebar_width) {
+            Ok(loaded) => loaded,
+            Err(e) => (Theme::fallback(settings.sidebar_width), vec![e]),
+        };
+        // the settings screen won't save one that doesn't parse, but the config file might have
+        let template = Template::parse(&settings.message_template).unwrap_or_default();
+        let mut gui = Gui {
             scroll: 0,
             buffer: EditBuffer::new("".into()),
-            tx,
+            tx: tx.clone(),
             servers,
             curr_server: None,
+            selected_folder: None,
+            channel_scroll: 0,
+            server_scroll: 0,
             mode: Mode::Messages,
             focus: Focus::Edit,
-            theme: Theme::new(&settings.theme).unwrap(),
+            theme,
             system_message: "".into(),
 
             prompt: None,
@@ -57,16 +94,36 @@ impl Gui {
             height: 0,
 
             cancel,
+            // whatever they were loaded from is up to date
+            uploaded_folders: settings.folders.clone(),
             settings,
             selected_message: None,
+            resizing_sidebar: false,
+            settings_screen: None,
+            link_picker: None,
+            template,
+            completion: None,
+            clipboard: None,
+            images: ImageCache::default(),
+            image_loader: ImageLoader::new(tx),
+            image_view: None,
+            edit_history: None,
+        };
+        if !theme_errors.is_empty() {
+            gui.send_system(&format!(
+                "Problems with theme '{}': {}",
+                gui.settings.theme,
+                join_theme_errors(&theme_errors)
+            ));
         }
+        gui
     }
 
     pub fn send_system(&mut self, message: &str) {
         self.system_message = format!("System: {}", message);
     }
 
-    fn get_selected_message(&self) -> Result<&LoadedMessage, CommandError> {
+    pub fn get_selected_message(&self) -> Result<&LoadedMessage, CommandError> {
         let Some(selected_message) = self.selected_message else {
             return Err(CommandError("No message selected to edit!".to_string()));
         };
@@ -133,18 +190,7 @@ impl Gui {
             },
             "/d" | "/delete" => self.delete_message().await,
             "/nick" => {
-                argv[1].clone_into(&mut self.settings.uname);
-                for server in &mut self.servers {
-                    if let Ok(ref mut net) = server.network {
-                        net.write(Request::Nick {
-                            nick: argv[1].to_owned(),
-                        })
-                        .await
-                        .unwrap(); // TODO UNWRAP REEE
-                    } else {
-                        // TODO: cache this to send later
-                    }
-                }
+                self.set_nick(argv[1]).await;
                 Ok(())
             }
 
@@ -171,39 +217,71 @@ impl Gui {
                     )?);
 
                 //It is possible that this unwrap fails due to the time interval since it was last checked. fuck it I cba
-                net.write(Request::History {
-                    num: 100,
-                    channel: net.channels[net.curr_channel.unwrap()].uuid,
-                    before_message: None,
-                })
-                .await
-                .unwrap();
+                let channel = net.channels[net.curr_channel.unwrap()].uuid;
+                net.jump_to = None;
+                net.request_history(HistoryRequest::Messages, 100, channel, None)
+                    .await
+                    .unwrap();
                 // self.draw_messages();
+                self.show_selected_channel();
 
                 Ok(())
             }
-            "/theme" => {
-                if argv.len() != 2 {
-                    self.send_system("Expected exactly one argument");
+            "/theme" => match argv.get(1).copied() {
+                Some("list") => {
+                    let themes = Theme::available()
+                        .into_iter()
+                        .map(|name| {
+                            if name == self.settings.theme {
+                                format!("{} (current)", name)
+                            } else {
+                                name
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    self.send_system(&format!("Themes: {}", themes.join(", ")));
+                    Ok(())
                 }
-
-                match Theme::new(argv[1]) {
-                    Ok(theme) => {
-                        self.theme = theme;
-                        argv[1].clone_into(&mut self.settings.theme);
-                        self.send_system(&format!("Changed theme to {}", argv[1]));
-                        // all of these potentially need drawing
-                        // self.draw_border();
-                        // self.draw_messages();
-                        // self.draw_servers();
-                        // self.draw_status_line();
-                        // self.draw_input_buffer();
+                Some("check") => {
+                    let Some((_, path)) = cmd.split_once(" check ") else {
+                        return Err(CommandError("Usage: /theme check <file>".into()));
+                    };
+                    let errors = match Theme::from_file(path, self.theme.sidebar_width) {
+                        Ok((_, errors)) => errors,
+                        Err(e) => vec![e],
+                    };
+                    if errors.is_empty() {
+                        self.send_system(&format!("No problems found in {}", path));
+                    } else {
+                        self.send_system(&format!(
+                            "{} problem(s) in {}: {}",
+                            errors.len(),
+                            path,
+                            join_theme_errors(&errors)
+                        ));
                     }
-                    Err(_e) => self.send_system("Nonexistant or invalid theme provided"),
+                    Ok(())
                 }
-
-                Ok(())
-            }
+                Some(name) if argv.len() == 2 => {
+                    let (theme, errors) = Theme::new(name, self.theme.sidebar_width)
+                        .map_err(|e| CommandError(format!("Couldn't load theme: {}", e)))?;
+                    self.theme = theme;
+                    name.clone_into(&mut self.settings.theme);
+                    if errors.is_empty() {
+                        self.send_system(&format!("Changed theme to {}", name));
+                    } else {
+                        self.send_system(&format!(
+                            "Changed theme to {}, with problems: {}",
+                            name,
+                            join_theme_errors(&errors)
+                        ));
+                    }
+                    Ok(())
+                }
+                _ => Err(CommandError(
+                    "Usage: /theme <name> | /theme list | /theme check <file>".into(),
+                )),
+            },
 
             "/connect" => {
                 // possibility to connect using [username@]hostname[:port] instead of interactive menu
@@ -233,6 +311,7 @@ impl Gui {
                             PromptField::String {
                                 name: "IP",
                                 default: None,
+                                max_width: 48,
                             },
                             PromptField::U16 {
                                 name: "Port",
@@ -241,6 +320,7 @@ impl Gui {
                             PromptField::String {
                                 name: "Username",
                                 default: Some(self.settings.uname.clone()),
+                                max_width: 48,
                             },
                         ],
                         vec!["Connect", "Cancel"],
@@ -249,11 +329,66 @@ impl Gui {
 
                 Ok(())
             }
+            "/folder" => self.handle_folder_command(&cmd),
+            "/copy" => self.handle_copy_command(&argv),
+            "/images" => self.handle_images_command(&argv),
+            "/history" => {
+                self.open_edit_history();
+                Ok(())
+            }
+            "/reply" => {
+                self.reply();
+                Ok(())
+            }
+            "/jump" => {
+                self.jump_to_original().await;
+                Ok(())
+            }
+            "/settings" => {
+                self.open_settings();
+                Ok(())
+            }
             _ => Err(CommandError(format!("Unknown command '{}'", argv[0]))),
         }
     }
 
+    pub async fn set_nick(&mut self, nick: &str) {
+        let format = self.message_format();
+        for server in &mut self.servers {
+            if let Ok(ref mut net) = server.network {
+                // so mentions of the new name are picked up straight away
+                net.nick = Some(nick.to_owned());
+            }
+            let format = server.message_format(&format);
+            if let Ok(ref mut net) = server.network {
+                for message in &mut net.loaded_messages {
+                    message.rebuild(&net.peers, &format);
+                }
+                net.write(Request::Nick {
+                    nick: nick.to_owned(),
+                })
+                .await
+                .unwrap(); // TODO UNWRAP REEE
+            } else {
+                // TODO: cache this to send later
+            }
+        }
+    }
+
     pub async fn connect_to_server(&mut self, ip: String, port: u16, id: Identification) {
+        if let Some(idx) = self
+            .servers
+            .iter()
+            .position(|server| server.is_same_server(&ip, port, &id))
+        {
+            // already got it, so just jump to the existing one instead of connecting twice
+            self.select_server(idx);
+            let server = &self.servers[idx];
+            let name = server.name.clone().unwrap_or(format!("{}:{}", ip, port));
+            self.send_system(&format!("Already added {}, switched to it", name));
+            return;
+        }
+
         let mut conn = Server::new(
             ip,
             port,
@@ -286,10 +421,165 @@ impl Gui {
         file.write_all(prefs.to_string().as_bytes()).unwrap();
     }
 
-    pub fn get_server_by_addr(&mut self, addr: SocketAddr) -> Option<&mut Server> {
-        self.servers.iter_mut().find(|server| match server.network {
-            Ok(ref net) => net.remote_addr == addr,
-            Err(_) => false,
-        })
+    /// Reload the theme if it, or a theme it extends, is the one that changed on disk. Returns
+    /// whether it was reloaded.
+    pub fn theme_file_changed(&mut self, path: &std::path::Path) -> bool {
+        let affected = std::iter::once(&self.settings.theme)
+            .chain(&self.theme.extends)
+            .any(|name| Theme::path(name).as_deref() == Some(path));
+        if !affected {
+            return false;
+        }
+        match Theme::new(&self.settings.theme, self.theme.sidebar_width) {
+            Ok((theme, errors)) => {
+                self.theme = theme;
+                if errors.is_empty() {
+                    self.send_system(&format!("Reloaded theme {}", self.settings.theme));
+                } else {
+                    self.send_system(&format!(
+                        "Reloaded theme {}, with problems: {}",
+                        self.settings.theme,
+                        join_theme_errors(&errors)
+                    ));
+                }
+                true
+            }
+            Err(e) => {
+                // keep the old one, it's probably just half-saved
+                self.send_system(&format!("Couldn't reload theme: {}", e));
+                false
+            }
+        }
+    }
+
+    /// Change the preferred sidebar width, within the limits of the current terminal size
+    pub fn set_sidebar_width(&mut self, width: usize) {
+        self.settings.sidebar_width = width.max(MIN_SIDEBAR_WIDTH);
+        self.fit_sidebar();
+    }
+
+    /// Size the sidebar as close to the preferred width as the terminal allows,
+    /// always leaving some room for messages
+    pub fn fit_sidebar(&mut self) {
+        let max_width = (self.width as usize)
+            .saturating_sub(MIN_MESSAGES_WIDTH)
+            .max(MIN_SIDEBAR_WIDTH);
+        self.theme.sidebar_width = self
+            .settings
+            .sidebar_width
+            .clamp(MIN_SIDEBAR_WIDTH, max_width);
+    }
+
+    pub fn message_format(&self) -> MessageFormat {
+        let width = self
+            .layout()
+            .get(Pane::Messages)
+            .map(|rect| rect.width.saturating_sub(1)) // 1 space of padding on the left
+            .unwrap_or(0);
+        MessageFormat {
+            width: width as usize,
+            time_format: self.settings.time_format.clone(),
+            // rounded down so that the messages only need rebuilding once a minute
+            relative_to: self
+                .settings
+                .relative_times
+                .then(|| chrono::Utc::now().timestamp() / 60 * 60),
+            group_window: self.settings.group_minutes as i64 * 60,
+            raw_markdown: self.settings.raw_markdown,
+            template: self.template.clone(),
+            styles: MessageStyles::new(&self.theme.messages),
+            images: Some(self.images.clone()),
+            own_uuid: None, // filled in by Server::message_format
+            own_name: None,
+        }
+    }
+
+    /// Work out where everything goes on the screen, with the input box as tall as it needs to be
+    pub fn layout(&self) -> Layout {
+        let layout = Layout::new(&self.layout_tree(1), self.width, self.height);
+        let input_lines = match layout.get(Pane::Input) {
+            Some(rect) => self.buffer.height(input_width(rect) as usize),
+            None => 1,
+        };
+        if input_lines == 1 {
+            layout
+        } else {
+            Layout::new(
+                &self.layout_tree(input_lines as u16),
+                self.width,
+                self.height,
+            )
+        }
+    }
+
+    fn layout_tree(&self, input_lines: u16) -> Node {
+        let border = |area: BorderArea| {
+            Some(Border {
+                area,
+                widths: self.theme.area(area).border.widths(),
+            })
+        };
+        // only the top and bottom of the input box's border are used, it sits inside the messages' border
+        let input_border = Border {
+            area: BorderArea::Edit,
+            widths: BorderWidths {
+                left: 0,
+                right: 0,
+                ..self.theme.edit.border.widths()
+            },
+        };
+
+        let sidebar_panes: Vec<Node> = self
+            .settings
+            .layout
+            .sidebar_panes
+            .iter()
+            .filter_map(|&pane| {
+                let (size, area) = match pane {
+                    Pane::Header => (Size::Fixed(2), BorderArea::Status),
+                    Pane::Channels => (Size::Fill, BorderArea::Channels),
+                    Pane::Servers => (Size::Fill, BorderArea::Servers),
+                    _ => return None, // the rest can't go in the sidebar
+                };
+                Some(Node::pane(pane, size, border(area)))
+            })
+            .collect();
+        let main = Node::split(
+            Direction::Vertical,
+            Size::Fill,
+            border(BorderArea::Messages),
+            vec![
+                Node::pane(Pane::Messages, Size::Fill, None),
+                Node::pane(Pane::Input, Size::Fixed(input_lines), Some(input_border)),
+            ],
+        );
+
+        let mut columns = vec![main];
+        if !sidebar_panes.is_empty() {
+            let sidebar = Node::split(
+                Direction::Vertical,
+                Size::Fixed(self.theme.sidebar_width as u16),
+                None,
+                sidebar_panes,
+            )
+            .mark_as_sidebar();
+            match self.settings.layout.sidebar {
+                SidebarSide::Left => columns.insert(0, sidebar),
+                SidebarSide::Right => columns.push(sidebar),
+            }
+        }
+        Node::split(
+            Direction::Vertical,
+            Size::Fill,
+            None,
+            vec![
+                Node::split(Direction::Horizontal, Size::Fill, None, columns),
+                Node::pane(Pane::Status, Size::Fixed(1), None),
+            ],
+        )
+    }
+
+    pub fn get_server_by_id(&mut self, id: ServerId) -> Option<&mut Server> {
+        self.servers.iter_mut().find(|server| server.id == id)
     }
 }
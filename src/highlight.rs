@@ -0,0 +1,379 @@
+/// The kinds of token in a code block that get their own colour
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Token {
+    Keyword,
+    Type,
+    String,
+    Number,
+    Comment,
+    Variable,
+}
+
+impl Token {
+    pub const ALL: [Token; 6] = [
+        Token::Keyword,
+        Token::Type,
+        Token::String,
+        Token::Number,
+        Token::Comment,
+        Token::Variable,
+    ];
+
+    /// What it's called in theme keys, e.g. "syntax-keyword-foreground"
+    pub fn name(self) -> &'static str {
+        match self {
+            Token::Keyword => "keyword",
+            Token::Type => "type",
+            Token::String => "string",
+            Token::Number => "number",
+            Token::Comment => "comment",
+            Token::Variable => "variable",
+        }
+    }
+}
+
+struct Language {
+    tags: &'static [&'static str], // what goes after the ``` to use it
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    capitalised_types: bool, // whether names starting with a capital letter are types
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    variables: bool, // shell style $NAME and ${NAME}
+}
+
+const LANGUAGES: [Language; 5] = [
+    Language {
+        tags: &["rust", "rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "static", "struct", "super", "trait",
+            "true", "type", "unsafe", "use", "where", "while",
+        ],
+        types: &[
+            "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32",
+            "i64", "i128", "isize", "f32", "f64",
+        ],
+        capitalised_types: true,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'], // not ' because of lifetimes
+        variables: false,
+    },
+    Language {
+        tags: &["sh", "bash", "zsh", "shell", "console"],
+        keywords: &[
+            "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case",
+            "esac", "in", "function", "return", "export", "local", "readonly", "break", "continue",
+        ],
+        types: &[],
+        capitalised_types: false,
+        line_comment: Some("#"),
+        block_comment: None,
+        quotes: &['"', '\''],
+        variables: true,
+    },
+    Language {
+        tags: &["json"],
+        keywords: &["true", "false", "null"],
+        types: &[],
+        capitalised_types: false,
+        line_comment: None,
+        block_comment: None,
+        quotes: &['"'],
+        variables: false,
+    },
+    Language {
+        tags: &["python", "py"],
+        keywords: &[
+            "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class",
+            "continue", "def", "del", "elif", "else", "except", "finally", "for", "from", "global",
+            "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise",
+            "return", "try", "while", "with", "yield",
+        ],
+        types: &[
+            "int", "str", "float", "bool", "list", "dict", "tuple", "set", "bytes",
+        ],
+        capitalised_types: true,
+        line_comment: Some("#"),
+        block_comment: None,
+        quotes: &['"', '\''],
+        variables: false,
+    },
+    Language {
+        tags: &["javascript", "js", "typescript", "ts"],
+        keywords: &[
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "export",
+            "extends",
+            "false",
+            "finally",
+            "for",
+            "function",
+            "if",
+            "import",
+            "in",
+            "instanceof",
+            "let",
+            "new",
+            "null",
+            "of",
+            "return",
+            "super",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "typeof",
+            "undefined",
+            "var",
+            "void",
+            "while",
+            "yield",
+        ],
+        types: &["number", "string", "boolean", "any", "unknown", "never"],
+        capitalised_types: true,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        variables: false,
+    },
+];
+
+/// What's still open at the end of a line
+#[derive(Clone, Copy)]
+enum State {
+    Normal,
+    BlockComment,
+    String(char), // the quote that ends it
+}
+
+/// Splits the lines of one code block into tokens, keeping track of
+/// comments and strings that carry on from one line to the next
+pub struct Highlighter {
+    language: &'static Language,
+    state: State,
+}
+
+impl Highlighter {
+    /// A highlighter for the language named after the ```, or None if it's not one we know
+    pub fn new(tag: &str) -> Option<Self> {
+        let tag = tag.to_lowercase();
+        LANGUAGES
+            .iter()
+            .find(|language| language.tags.contains(&tag.as_str()))
+            .map(|language| Self {
+                language,
+                state: State::Normal,
+            })
+    }
+
+    /// Split the next line of the code block into runs of text and what kind of token they are, if any
+    pub fn line<'a>(&mut self, line: &'a str) -> Vec<(Option<Token>, &'a str)> {
+        let lang = self.language;
+        let mut out: Vec<(Option<Token>, std::ops::Range<usize>)> = Vec::new();
+        let mut push = |token: Option<Token>, range: std::ops::Range<usize>| match out.last_mut() {
+            // join up neighbouring bits of the same kind, so there are fewer spans to draw
+            Some((last, prev)) if *last == token => prev.end = range.end,
+            _ => out.push((token, range)),
+        };
+
+        let mut pos = 0;
+        while pos < line.len() {
+            let rest = &line[pos..];
+            match self.state {
+                State::BlockComment => {
+                    let (_, end) = lang.block_comment.unwrap(); // only set for languages that have them
+                    let len = match rest.find(end) {
+                        Some(idx) => {
+                            self.state = State::Normal;
+                            idx + end.len()
+                        }
+                        None => rest.len(),
+                    };
+                    push(Some(Token::Comment), pos..pos + len);
+                    pos += len;
+                    continue;
+                }
+                State::String(quote) => {
+                    let len = string_end(rest, quote);
+                    if len.is_some() {
+                        self.state = State::Normal;
+                    }
+                    let len = len.unwrap_or(rest.len());
+                    push(Some(Token::String), pos..pos + len);
+                    pos += len;
+                    continue;
+                }
+                State::Normal => (),
+            }
+
+            let ch = rest.chars().next().unwrap(); // unwrap: rest isn't empty
+            let at_word_start = line[..pos]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace);
+            if let Some(comment) = lang.line_comment {
+                // in shells, # only starts a comment at the start of a word
+                if rest.starts_with(comment) && (!lang.variables || at_word_start) {
+                    push(Some(Token::Comment), pos..line.len());
+                    break;
+                }
+            }
+            if let Some((start, _)) = lang.block_comment {
+                if rest.starts_with(start) {
+                    self.state = State::BlockComment;
+                    push(Some(Token::Comment), pos..pos + start.len());
+                    pos += start.len();
+                    continue;
+                }
+            }
+            if lang.quotes.contains(&ch) {
+                self.state = State::String(ch);
+                push(Some(Token::String), pos..pos + 1);
+                pos += 1;
+                continue;
+            }
+            if lang.variables && ch == '$' {
+                let len = variable_len(rest);
+                push(Some(Token::Variable), pos..pos + len);
+                pos += len;
+                continue;
+            }
+            if ch.is_alphanumeric() || ch == '_' {
+                let len = if ch.is_ascii_digit() {
+                    number_len(rest)
+                } else {
+                    rest.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                        .unwrap_or(rest.len())
+                };
+                let word = &rest[..len];
+                let token = if ch.is_ascii_digit() {
+                    Some(Token::Number)
+                } else if lang.keywords.contains(&word) {
+                    Some(Token::Keyword)
+                } else if lang.types.contains(&word)
+                    || (lang.capitalised_types && ch.is_uppercase())
+                {
+                    Some(Token::Type)
+                } else {
+                    None
+                };
+                push(token, pos..pos + len);
+                pos += len;
+                continue;
+            }
+            push(None, pos..pos + ch.len_utf8());
+            pos += ch.len_utf8();
+        }
+        out.into_iter()
+            .map(|(token, range)| (token, &line[range]))
+            .collect()
+    }
+}
+
+/// How far into `text` the string ends, including the closing quote,
+/// or None if it carries on to the next line
+fn string_end(text: &str, quote: char) -> Option<usize> {
+    let mut chars = text.char_indices();
+    while let Some((idx, ch)) = chars.next() {
+        if ch == '\\' && quote != '\'' {
+            chars.next(); // escaped, so it can't end the string
+        } else if ch == quote {
+            return Some(idx + ch.len_utf8());
+        }
+    }
+    None
+}
+
+/// How long the number at the start of `text` is. A dot only counts if a digit comes straight
+/// after it, so that `1..10` is two numbers and `1.max(2)` doesn't take the method name.
+fn number_len(text: &str) -> usize {
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, ch)) = chars.next() {
+        let fraction = ch == '.' && chars.peek().is_some_and(|(_, next)| next.is_ascii_digit());
+        if !(ch.is_alphanumeric() || ch == '_' || fraction) {
+            return idx;
+        }
+    }
+    text.len()
+}
+
+/// How long the shell variable at the start of `text` is, like $HOME, ${HOME} or $1
+fn variable_len(text: &str) -> usize {
+    let name = &text[1..];
+    if name.starts_with('{') {
+        return name.find('}').map(|idx| idx + 2).unwrap_or(text.len());
+    }
+    let len = name
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(name.len());
+    if len > 0 {
+        return len + 1;
+    }
+    // special variables like $? and $@
+    match name.chars().next() {
+        Some(c) if "?@#$!*-".contains(c) => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(tag: &str, line: &str) -> Vec<(Option<Token>, String)> {
+        Highlighter::new(tag)
+            .unwrap()
+            .line(line)
+            .into_iter()
+            .map(|(token, text)| (token, text.to_owned()))
+            .collect()
+    }
+
+    fn token(token: Option<Token>, text: &str) -> (Option<Token>, String) {
+        (token, text.to_owned())
+    }
+
+    #[test]
+    fn ranges_are_two_numbers() {
+        assert_eq!(
+            tokens("rust", "1..10"),
+            vec![
+                token(Some(Token::Number), "1"),
+                token(None, ".."),
+                token(Some(Token::Number), "10"),
+            ]
+        );
+        assert_eq!(
+            tokens("rust", "0..=n"),
+            vec![token(Some(Token::Number), "0"), token(None, "..=n")]
+        );
+    }
+
+    #[test]
+    fn numbers_keep_fractions_and_suffixes() {
+        assert_eq!(
+            tokens("rust", "1.5f32"),
+            vec![token(Some(Token::Number), "1.5f32")]
+        );
+        assert_eq!(
+            tokens("rust", "2.max"),
+            vec![token(Some(Token::Number), "2"), token(None, ".max")]
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use crate::diff::{diff, Change};
+use crate::drawing::ThemedArea;
+use crate::gui::{CommandError, Gui};
+use crate::styled::StyledLine;
+use crate::{unicode, Mode};
+use termion::event::{Event, Key};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Every version of a message that's been seen, shown one after the other with what changed each time
+pub struct EditHistory {
+    versions: Vec<String>, // oldest first, ending with the current one
+    pub scroll: usize,     // how many lines up from the bottom
+    rendered: Option<(usize, Vec<StyledLine>)>, // for the width it was drawn at
+    mode: Mode,            // what to go back to afterwards
+}
+
+impl EditHistory {
+    /// The lines to draw at `width` columns
+    pub fn lines(&mut self, width: usize, area: &ThemedArea) -> &[StyledLine] {
+        if self.rendered.as_ref().is_none_or(|(w, _)| *w != width) {
+            self.rendered = Some((width, self.render(width, area)));
+        }
+        &self.rendered.as_ref().unwrap().1 // unwrap: just set above if it wasn't already
+    }
+
+    fn render(&self, width: usize, area: &ThemedArea) -> Vec<StyledLine> {
+        let text = area.text.to_string();
+        let heading = format!("{}{}", text, termion::style::Bold);
+        let (added, removed) = (area.diff_added.to_string(), area.diff_removed.to_string());
+        let mut lines = Vec::new();
+        for (idx, version) in self.versions.iter().enumerate() {
+            if idx > 0 {
+                lines.push(StyledLine::default());
+            }
+            let mut title = StyledLine::default();
+            match idx {
+                0 => title.push_str(&heading, "Original"),
+                _ if idx == self.versions.len() - 1 => title.push_str(&heading, "Now"),
+                _ => title.push_str(&heading, &format!("Edit {}", idx)),
+            }
+            lines.push(title);
+            let pieces = match idx {
+                0 => vec![(text.as_str(), version.as_str())],
+                _ => diff(&self.versions[idx - 1], version)
+                    .into_iter()
+                    .map(|(change, piece)| {
+                        let style = match change {
+                            Change::Same => &text,
+                            Change::Added => &added,
+                            Change::Removed => &removed,
+                        };
+                        (style.as_str(), piece)
+                    })
+                    .collect(),
+            };
+            lines.extend(wrap(&pieces, width));
+        }
+        lines
+    }
+}
+
+/// Lay out styled text in rows of `width` columns, indented by two spaces, starting new rows at newlines
+fn wrap(pieces: &[(&str, &str)], width: usize) -> Vec<StyledLine> {
+    let indent = "  ";
+    let new_row = || {
+        let mut row = StyledLine::default();
+        row.push_str("", indent);
+        row
+    };
+    let mut rows = vec![new_row()];
+    for (style, text) in pieces {
+        for grapheme in text.graphemes(true) {
+            if grapheme == "\n" || grapheme == "\r\n" {
+                rows.push(new_row());
+                continue;
+            }
+            let row = rows.last().unwrap(); // unwrap: there's always at least one
+            if row.width() + unicode::grapheme_width(grapheme) > width {
+                rows.push(new_row());
+            }
+            rows.last_mut().unwrap().push_str(style, grapheme);
+        }
+    }
+    rows
+}
+
+impl Gui {
+    /// Show how the selected message has changed since it was first seen
+    pub fn open_edit_history(&mut self) {
+        if let Err(CommandError(e)) = self.try_open_edit_history() {
+            self.send_system(&e);
+        }
+    }
+
+    fn try_open_edit_history(&mut self) -> Result<(), CommandError> {
+        if self.selected_message.is_none() {
+            return Err(CommandError("No message selected".into()));
+        }
+        let message = &self.get_selected_message()?.message;
+        let net = self.servers[self.curr_server.unwrap()] // unwrap: checked by get_selected_message
+            .network
+            .as_ref()
+            .unwrap(); // unwrap: so is this
+        let Some(earlier) = net.edit_history.get(&message.uuid) else {
+            return Err(CommandError(if message.edited {
+                "The message was edited before it was loaded, so its earlier versions aren't known"
+                    .into()
+            } else {
+                "The selected message hasn't been edited".into()
+            }));
+        };
+        let mut versions = earlier.clone();
+        versions.push(message.content.clone());
+        self.edit_history = Some(EditHistory {
+            versions,
+            scroll: 0,
+            rendered: None,
+            mode: self.mode,
+        });
+        self.mode = Mode::EditHistory;
+        Ok(())
+    }
+
+    pub fn edit_history_event(&mut self, event: Event) {
+        let Some(history) = self.edit_history.as_mut() else {
+            return;
+        };
+        match event {
+            Event::Key(Key::Esc) | Event::Key(Key::Char('q')) => {
+                self.mode = history.mode;
+                self.edit_history = None;
+            }
+            // drawing keeps it from going past the top
+            Event::Key(Key::Up) => history.scroll += 1,
+            Event::Key(Key::Down) => history.scroll = history.scroll.saturating_sub(1),
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use crate::gui::{CommandError, Gui};
+use crate::{LocalMessage, Mode};
+use base64::prelude::*;
+use fmtstring::FmtString;
+use image::DynamicImage;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use termion::event::{Event, Key};
+
+/// How many pixels dct-tiv turns into one character, going by avatars, which are 14x16 for two
+const CELL_PIXELS: (u32, u32) = (7, 16);
+/// The most columns and rows a preview under a message can take up
+pub const THUMBNAIL_SIZE: (u32, u32) = (32, 8);
+/// Anything bigger than this is more likely to be a decompression bomb than a picture
+const MAX_DIMENSION: u32 = 16384;
+/// How many images can be loading at once
+const IMAGE_WORKERS: usize = 4;
+/// How long the image fetcher gets before it's killed
+const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+/// How often to check whether the image fetcher has finished
+const FETCH_POLL: Duration = Duration::from_millis(50);
+
+pub struct LoadedImage {
+    pub thumbnail: Vec<FmtString>,
+    pub image: DynamicImage, // kept for the full screen view
+}
+
+#[derive(Clone)]
+pub enum Preview {
+    Loading,
+    Failed(String),
+    Loaded(Arc<LoadedImage>),
+}
+
+/// Previews of every image that's been seen, by URL. Clones share the previews, so when one
+/// arrives only the messages that link to it need rebuilding, not everything using a clone.
+#[derive(Clone, Default)]
+pub struct ImageCache(Rc<RefCell<HashMap<String, Preview>>>);
+
+impl PartialEq for ImageCache {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl ImageCache {
+    pub fn get(&self, url: &str) -> Option<Preview> {
+        self.0.borrow().get(url).cloned()
+    }
+
+    fn insert(&self, url: String, preview: Preview) {
+        self.0.borrow_mut().insert(url, preview);
+    }
+}
+
+/// The images from one message shown as big as they'll go, one at a time
+pub struct ImageView {
+    pub images: Vec<(String, Arc<LoadedImage>)>,
+    pub index: usize,
+    pub rendered: Option<((u16, u16), Vec<FmtString>)>, // for the screen size it was drawn at
+    mode: Mode,                                         // what to go back to afterwards
+}
+
+/// Where an image comes from
+enum Source<'a> {
+    Data(&'a str), // the base64 part of a data URI
+    File(PathBuf),
+    Http,
+}
+
+/// What loading an image needs from the settings
+#[derive(Clone)]
+pub struct Fetcher {
+    command: String,   // e.g. "curl -sfL", which gets the URL added on the end
+    directory: String, // where file:// links can be read from, or empty for nowhere
+    limit: u64,        // in bytes
+}
+
+impl Fetcher {
+    /// Whether an image from `source` can be fetched at all with these settings
+    fn can_fetch(&self, source: &Source) -> bool {
+        match source {
+            Source::Data(_) => true,
+            Source::File(_) => !self.directory.trim().is_empty(),
+            Source::Http => !self.command.trim().is_empty(),
+        }
+    }
+}
+
+/// Where to get the image a link points to, or None if it's not one we can show.
+/// Web links are only images if they look like one, since fetching every page would be a lot.
+fn source(url: &str) -> Option<Source<'_>> {
+    if let Some(rest) = url.strip_prefix("data:image/") {
+        let (_, data) = rest.split_once(";base64,")?;
+        return Some(Source::Data(data));
+    }
+    if let Some(path) = url.strip_prefix("file://") {
+        return Some(Source::File(PathBuf::from(path)));
+    }
+    if url.starts_with("https://") || url.starts_with("http://") {
+        let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+        let extensions = [".png", ".jpg", ".jpeg", ".gif", ".webp", ".bmp"];
+        if extensions.iter().any(|ext| path.ends_with(ext)) {
+            return Some(Source::Http);
+        }
+    }
+    None
+}
+
+/// The file a file:// link points to, as long as it's a plain file inside `directory`. Links come
+/// from other people, so anything else, like a FIFO or a file somewhere private, is refused.
+fn allowed_file(path: &Path, directory: &str) -> Result<PathBuf, String> {
+    let directory = std::fs::canonicalize(directory)
+        .map_err(|e| format!("couldn't find the image directory: {}", e))?;
+    // the same error whether it exists or not, so links can't be used to find out
+    let outside = || "it's not a file in the image directory".to_owned();
+    let path = std::fs::canonicalize(path).map_err(|_| outside())?;
+    let is_file = std::fs::metadata(&path).is_ok_and(|meta| meta.is_file());
+    if !path.starts_with(&directory) || !is_file {
+        return Err(outside());
+    }
+    Ok(path)
+}
+
+/// Get the bytes of the image at `url`, as long as it's no bigger than the limit
+fn fetch(url: &str, fetcher: &Fetcher) -> Result<Vec<u8>, String> {
+    let limit = fetcher.limit;
+    let too_big = || format!("it's over the size limit of {} KB", limit / 1024);
+    let source = source(url).ok_or("not an image")?;
+    if !fetcher.can_fetch(&source) {
+        return Err("there's no way to fetch it set up".into());
+    }
+    match source {
+        Source::Data(data) => {
+            // base64 is 4 characters for every 3 bytes
+            if data.len() as u64 / 4 * 3 > limit {
+                return Err(too_big());
+            }
+            BASE64_STANDARD
+                .decode(data)
+                .map_err(|e| format!("bad base64: {}", e))
+        }
+        Source::File(path) => {
+            let path = allowed_file(&path, &fetcher.directory)?;
+            let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+            read_limited(file, limit).ok_or_else(too_big)
+        }
+        Source::Http => {
+            let mut words = fetcher.command.split_whitespace();
+            let program = words.next().ok_or("no image fetcher is set")?;
+            let mut child = Command::new(program)
+                .args(words)
+                .arg(url)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| format!("couldn't run {}: {}", program, e))?;
+            let stdout = child.stdout.take().unwrap(); // unwrap: it's piped above
+            let mut timed_out = false;
+            // read on another thread, so the fetcher can be killed if it takes too long
+            let bytes = std::thread::scope(|scope| {
+                let reader = scope.spawn(move || read_limited(stdout, limit));
+                let deadline = Instant::now() + FETCH_TIMEOUT;
+                while !reader.is_finished() {
+                    if !timed_out && Instant::now() > deadline {
+                        // its end of the pipe closes when it's killed, so the read finishes
+                        let _ = child.kill();
+                        timed_out = true;
+                    }
+                    std::thread::sleep(FETCH_POLL);
+                }
+                reader.join().unwrap_or(None)
+            });
+            if bytes.is_none() {
+                let _ = child.kill();
+            }
+            let status = child.wait().map_err(|e| e.to_string())?;
+            if timed_out {
+                return Err(format!(
+                    "{} took longer than {} seconds",
+                    program,
+                    FETCH_TIMEOUT.as_secs()
+                ));
+            }
+            let bytes = bytes.ok_or_else(too_big)?;
+            if !status.success() {
+                return Err(format!("{} failed with {}", program, status));
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+/// Read everything from `reader`, or None if there's more than `limit` bytes of it
+fn read_limited<R: Read>(reader: R, limit: u64) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut bytes).ok()?;
+    (bytes.len() as u64 <= limit).then_some(bytes)
+}
+
+/// A few threads that load images one at a time each, however many are asked for at once
+pub struct ImageLoader {
+    jobs: mpsc::Sender<(String, Fetcher)>,
+}
+
+impl ImageLoader {
+    /// Start the threads, which send each image to the main loop once it's loaded
+    pub fn new(tx: mpsc::Sender<LocalMessage>) -> Self {
+        let (jobs, queue) = mpsc::channel::<(String, Fetcher)>();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..IMAGE_WORKERS {
+            let (queue, tx) = (queue.clone(), tx.clone());
+            std::thread::spawn(move || loop {
+                // the lock is only held while waiting for a job, not while loading it
+                let job = queue.lock().unwrap().recv();
+                let Ok((url, fetcher)) = job else {
+                    return;
+                };
+                let result = load(&url, &fetcher).map(Arc::new);
+                if tx.send(LocalMessage::ImageLoaded(url, result)).is_err() {
+                    return;
+                }
+            });
+        }
+        ImageLoader { jobs }
+    }
+
+    fn load(&self, url: String, fetcher: Fetcher) {
+        // the threads only stop once this is dropped, so they're still there to send to
+        let _ = self.jobs.send((url, fetcher));
+    }
+}
+
+fn load(url: &str, fetcher: &Fetcher) -> Result<LoadedImage, String> {
+    let bytes = fetch(url, fetcher)?;
+    let mut reader = image::io::Reader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+    let mut limits = image::io::Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|e| e.to_string())?;
+    Ok(LoadedImage {
+        thumbnail: render(&image, THUMBNAIL_SIZE.0, THUMBNAIL_SIZE.1),
+        image,
+    })
+}
+
+/// Draw an image as characters, as big as it can be within `columns` and `rows` without stretching it
+pub fn render(image: &DynamicImage, columns: u32, rows: u32) -> Vec<FmtString> {
+    let (width, height) = (image.width().max(1) as f64, image.height().max(1) as f64);
+    let scale = f64::min(
+        (columns * CELL_PIXELS.0) as f64 / width,
+        (rows * CELL_PIXELS.1) as f64 / height,
+    );
+    let columns = ((width * scale / CELL_PIXELS.0 as f64).round() as u32).clamp(1, columns);
+    let rows = ((height * scale / CELL_PIXELS.1 as f64).round() as u32).clamp(1, rows);
+    let resized = image
+        .resize_exact(
+            columns * CELL_PIXELS.0,
+            rows * CELL_PIXELS.1,
+            image::imageops::FilterType::Triangle,
+        )
+        .into_rgb8();
+    dct_tiv::textify_dct(
+        &resized,
+        &dct_tiv::DEFAULT_DCT_MATRICIES,
+        &dct_tiv::DEFAULT_PALETTE,
+    )
+}
+
+impl Gui {
+    /// Start loading previews of the images linked in the current server's messages,
+    /// for any that haven't been already. Web links are fetched without asking, see `image_fetcher`.
+    pub fn load_images(&mut self) {
+        let Some(server) = self.curr_server.map(|idx| &self.servers[idx]) else {
+            return;
+        };
+        let Ok(ref net) = server.network else {
+            return;
+        };
+        if server.hide_images {
+            return;
+        }
+        let fetcher = Fetcher {
+            command: self.settings.image_fetcher.clone(),
+            directory: self.settings.image_directory.clone(),
+            limit: self.settings.image_size_limit * 1024,
+        };
+        let mut started = Vec::new();
+        for message in &net.loaded_messages {
+            for url in message.links() {
+                let wanted = source(url).is_some_and(|source| fetcher.can_fetch(&source));
+                if !wanted || self.images.get(url).is_some() {
+                    continue;
+                }
+                self.images.insert(url.clone(), Preview::Loading);
+                self.image_loader.load(url.clone(), fetcher.clone());
+                started.push(url.clone());
+            }
+        }
+        self.rebuild_previews(&started);
+    }
+
+    pub fn image_loaded(&mut self, url: String, result: Result<Arc<LoadedImage>, String>) {
+        let preview = match result {
+            Ok(image) => Preview::Loaded(image),
+            Err(e) => Preview::Failed(e),
+        };
+        self.images.insert(url.clone(), preview);
+        self.rebuild_previews(&[url]);
+    }
+
+    /// Rebuild the messages in every server that link to any of `urls`, since their previews have changed
+    fn rebuild_previews(&mut self, urls: &[String]) {
+        if urls.is_empty() {
+            return;
+        }
+        let format = self.message_format();
+        for server in &mut self.servers {
+            let format = server.message_format(&format);
+            let Ok(ref mut net) = server.network else {
+                continue;
+            };
+            for message in &mut net.loaded_messages {
+                if message.links().iter().any(|url| urls.contains(url)) {
+                    message.rebuild(&net.peers, &format);
+                }
+            }
+        }
+    }
+
+    /// `/images on|off`, for whether to show previews in the current server
+    pub fn handle_images_command(&mut self, argv: &[&str]) -> Result<(), CommandError> {
+        let hide = match argv.get(1).copied() {
+            Some("on") => false,
+            Some("off") => true,
+            _ => return Err(CommandError("Usage: /images on|off".into())),
+        };
+        let server = self
+            .curr_server
+            .ok_or(CommandError("No server is selected".into()))?;
+        self.servers[server].hide_images = hide;
+        let format = self.servers[server].message_format(&self.message_format());
+        if let Ok(ref mut net) = self.servers[server].network {
+            for message in &mut net.loaded_messages {
+                message.rebuild(&net.peers, &format);
+            }
+        }
+        self.load_images();
+        self.send_system(if hide {
+            "Image previews are off for this server"
+        } else {
+            "Image previews are on for this server"
+        });
+        Ok(())
+    }
+
+    /// Show the images in the selected message full screen
+    pub fn open_image_view(&mut self) {
+        if let Err(CommandError(e)) = self.try_open_image_view() {
+            self.send_system(&e);
+        }
+    }
+
+    fn try_open_image_view(&mut self) -> Result<(), CommandError> {
+        if self.selected_message.is_none() {
+            return Err(CommandError("No message selected".into()));
+        }
+        let images: Vec<(String, Arc<LoadedImage>)> = self
+            .get_selected_message()?
+            .links()
+            .iter()
+            .filter_map(|url| match self.images.get(url) {
+                Some(Preview::Loaded(image)) => Some((url.clone(), image)),
+                _ => None,
+            })
+            .collect();
+        if images.is_empty() {
+            return Err(CommandError(
+                "The selected message has no images that have loaded".into(),
+            ));
+        }
+        self.image_view = Some(ImageView {
+            images,
+            index: 0,
+            rendered: None,
+            mode: self.mode,
+        });
+        self.mode = Mode::ImageView;
+        Ok(())
+    }
+
+    pub fn image_view_event(&mut self, event: Event) {
+        let Some(view) = self.image_view.as_mut() else {
+            return;
+        };
+        let count = view.images.len();
+        match event {
+            Event::Key(Key::Esc) | Event::Key(Key::Char('q')) => {
+                self.mode = view.mode;
+                self.image_view = None;
+            }
+            Event::Key(Key::Left) => {
+                view.index = (view.index + count - 1) % count;
+                view.rendered = None;
+            }
+            Event::Key(Key::Right) => {
+                view.index = (view.index + 1) % count;
+                view.rendered = None;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding `images/inside.png` and `outside.png`
+    fn scratch(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("aster-cli-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("images")).unwrap();
+        std::fs::write(base.join("images/inside.png"), b"in").unwrap();
+        std::fs::write(base.join("outside.png"), b"out").unwrap();
+        base
+    }
+
+    #[test]
+    fn files_inside_the_directory_are_allowed() {
+        let base = scratch("inside");
+        let directory = base.join("images");
+        let allowed = allowed_file(&directory.join("inside.png"), directory.to_str().unwrap());
+        assert_eq!(
+            allowed,
+            Ok(std::fs::canonicalize(directory.join("inside.png")).unwrap())
+        );
+        assert!(allowed_file(&directory, directory.to_str().unwrap()).is_err());
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn dot_dot_cant_leave_the_directory() {
+        let base = scratch("dotdot");
+        let directory = base.join("images");
+        let escape = directory.join("../outside.png");
+        assert_eq!(
+            allowed_file(&escape, directory.to_str().unwrap()),
+            Err("it's not a file in the image directory".to_owned())
+        );
+        // the same error as for a file that isn't there
+        assert_eq!(
+            allowed_file(
+                &directory.join("../missing.png"),
+                directory.to_str().unwrap()
+            ),
+            allowed_file(&escape, directory.to_str().unwrap())
+        );
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_cant_leave_the_directory() {
+        let base = scratch("symlink");
+        let directory = base.join("images");
+        std::os::unix::fs::symlink(base.join("outside.png"), directory.join("link.png")).unwrap();
+        std::os::unix::fs::symlink(&base, directory.join("parent")).unwrap();
+        for path in ["link.png", "parent/outside.png"] {
+            assert!(
+                allowed_file(&directory.join(path), directory.to_str().unwrap()).is_err(),
+                "{} was allowed",
+                path
+            );
+        }
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn no_directory_means_no_files() {
+        let base = scratch("empty");
+        let fetcher = Fetcher {
+            command: String::new(),
+            directory: String::new(),
+            limit: 1024,
+        };
+        let url = format!("file://{}", base.join("outside.png").display());
+        assert!(!fetcher.can_fetch(&source(&url).unwrap()));
+        assert_eq!(
+            fetch(&url, &fetcher),
+            Err("there's no way to fetch it set up".to_owned())
+        );
+        assert!(allowed_file(&base.join("outside.png"), "").is_err());
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
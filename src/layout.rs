@@ -0,0 +1,468 @@
+use serde::{Deserialize, Serialize};
+
+/// The parts of the screen that something gets drawn in
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pane {
+    Header, // "Connected to" and the current server
+    Channels,
+    Servers,
+    Messages,
+    Input,
+    Status,
+}
+
+/// Which side of the screen the sidebar goes on
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SidebarSide {
+    Left,
+    Right,
+}
+
+/// How the screen is laid out, as set in the config file
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutSettings {
+    pub sidebar: SidebarSide,
+    /// What goes in the sidebar, from top to bottom. Anything left out is hidden,
+    /// and the sidebar is hidden completely if this is empty.
+    pub sidebar_panes: Vec<Pane>,
+}
+
+impl Default for LayoutSettings {
+    fn default() -> Self {
+        Self {
+            sidebar: SidebarSide::Left,
+            sidebar_panes: vec![Pane::Header, Pane::Channels, Pane::Servers],
+        }
+    }
+}
+
+/// A rectangle of the terminal, in the 1-based coordinates that termion uses
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// The column just past the right hand side
+    pub fn right(&self) -> u16 {
+        self.x + self.width
+    }
+
+    /// The row just past the bottom
+    pub fn bottom(&self) -> u16 {
+        self.y + self.height
+    }
+}
+
+/// Which theme area a border takes its characters from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BorderArea {
+    Status,
+    Channels,
+    Servers,
+    Messages,
+    Edit,
+}
+
+/// How wide the border is on each side, which is 0 if the theme leaves that side out
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct BorderWidths {
+    pub left: u16,
+    pub right: u16,
+    pub top: u16,
+    pub bottom: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Border {
+    pub area: BorderArea,
+    pub widths: BorderWidths,
+}
+
+/// How big a node is along the direction of the split it's in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Size {
+    Fixed(u16), // not counting borders
+    Fill,       // share whatever is left over with the other `Fill` nodes
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Horizontal, // side by side
+    Vertical,   // stacked on top of each other
+}
+
+pub enum NodeKind {
+    Pane(Pane),
+    Split(Direction, Vec<Node>),
+}
+
+/// One part of the layout tree. Borders of neighbouring nodes that face each other are
+/// drawn on top of each other rather than side by side, so there's only ever one line between them.
+pub struct Node {
+    pub kind: NodeKind,
+    pub size: Size,
+    pub border: Option<Border>,
+    pub is_sidebar: bool, // so the sidebar can be found again to resize it
+}
+
+impl Node {
+    pub fn pane(pane: Pane, size: Size, border: Option<Border>) -> Self {
+        Self {
+            kind: NodeKind::Pane(pane),
+            size,
+            border,
+            is_sidebar: false,
+        }
+    }
+
+    pub fn split(
+        direction: Direction,
+        size: Size,
+        border: Option<Border>,
+        children: Vec<Node>,
+    ) -> Self {
+        Self {
+            kind: NodeKind::Split(direction, children),
+            size,
+            border,
+            is_sidebar: false,
+        }
+    }
+
+    pub fn mark_as_sidebar(mut self) -> Self {
+        self.is_sidebar = true;
+        self
+    }
+
+    /// How wide the border on the outside of this node is, whether it's this node's or its children's
+    fn edge(&self) -> BorderWidths {
+        if let Some(border) = self.border {
+            return border.widths;
+        }
+        let NodeKind::Split(direction, ref children) = self.kind else {
+            return BorderWidths::default();
+        };
+        let (Some(first), Some(last)) = (children.first(), children.last()) else {
+            return BorderWidths::default();
+        };
+        let edges: Vec<BorderWidths> = children.iter().map(Node::edge).collect();
+        let max = |side: fn(&BorderWidths) -> u16| edges.iter().map(side).max().unwrap_or(0);
+        match direction {
+            Direction::Horizontal => BorderWidths {
+                left: first.edge().left,
+                right: last.edge().right,
+                top: max(|e| e.top),
+                bottom: max(|e| e.bottom),
+            },
+            Direction::Vertical => BorderWidths {
+                left: max(|e| e.left),
+                right: max(|e| e.right),
+                top: first.edge().top,
+                bottom: last.edge().bottom,
+            },
+        }
+    }
+}
+
+/// Where everything ends up on the screen
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Layout {
+    panes: Vec<(Pane, Rect)>, // the insides of each pane, not counting borders
+    borders: Vec<(Rect, Border)>, // the outsides of everything with a border
+    sidebar: Option<Rect>,
+}
+
+impl Layout {
+    /// Lay out `root` to fill a terminal `width` by `height`
+    pub fn new(root: &Node, width: u16, height: u16) -> Self {
+        let mut layout = Layout::default();
+        layout.place(
+            root,
+            Rect {
+                x: 1,
+                y: 1,
+                width,
+                height,
+            },
+        );
+        layout
+    }
+
+    /// The inside of a pane, or None if it's hidden
+    pub fn get(&self, pane: Pane) -> Option<Rect> {
+        self.panes
+            .iter()
+            .find(|(p, _)| *p == pane)
+            .map(|(_, rect)| *rect)
+    }
+
+    /// Which pane the cell at `x`, `y` is inside, not counting borders
+    pub fn pane_at(&self, x: u16, y: u16) -> Option<Pane> {
+        self.panes
+            .iter()
+            .find(|(_, rect)| rect.contains(x, y))
+            .map(|(pane, _)| *pane)
+    }
+
+    pub fn borders(&self) -> &[(Rect, Border)] {
+        &self.borders
+    }
+
+    /// The column of the border between the sidebar and the messages, if there is a sidebar
+    pub fn sidebar_border_x(&self) -> Option<u16> {
+        let sidebar = self.sidebar?;
+        if sidebar.x == 1 {
+            Some(sidebar.right() - 1)
+        } else {
+            Some(sidebar.x)
+        }
+    }
+
+    fn place(&mut self, node: &Node, outer: Rect) {
+        let widths = node.border.map(|b| b.widths).unwrap_or_default();
+        if let Some(border) = node.border {
+            self.borders.push((outer, border));
+        }
+        if node.is_sidebar {
+            self.sidebar = Some(outer);
+        }
+        let inner = Rect {
+            x: outer.x + widths.left,
+            y: outer.y + widths.top,
+            width: outer.width.saturating_sub(widths.left + widths.right),
+            height: outer.height.saturating_sub(widths.top + widths.bottom),
+        };
+
+        let (direction, children) = match node.kind {
+            NodeKind::Pane(pane) => {
+                self.panes.push((pane, inner));
+                return;
+            }
+            NodeKind::Split(direction, ref children) => (direction, children),
+        };
+
+        // the borders before and after each child along the direction of the split
+        let edges: Vec<(u16, u16)> = children
+            .iter()
+            .map(|child| {
+                let edge = child.edge();
+                match direction {
+                    Direction::Horizontal => (edge.left, edge.right),
+                    Direction::Vertical => (edge.top, edge.bottom),
+                }
+            })
+            .collect();
+        // neighbouring borders that face each other share a line
+        let overlaps: Vec<u16> = edges
+            .windows(2)
+            .map(|pair| u16::min(pair[0].1, pair[1].0))
+            .collect();
+
+        let available = overlaps.iter().fold(
+            match direction {
+                Direction::Horizontal => inner.width,
+                Direction::Vertical => inner.height,
+            },
+            |total, &overlap| total.saturating_add(overlap),
+        );
+        let fixed: u16 = children
+            .iter()
+            .zip(&edges)
+            .filter_map(|(child, (before, after))| match child.size {
+                Size::Fixed(size) => Some(size.saturating_add(before + after)),
+                Size::Fill => None,
+            })
+            .fold(0, u16::saturating_add);
+        let fills = children.iter().filter(|c| c.size == Size::Fill).count() as u16;
+        let left_over = available.saturating_sub(fixed);
+
+        let (mut pos, end) = match direction {
+            Direction::Horizontal => (inner.x, inner.right()),
+            Direction::Vertical => (inner.y, inner.bottom()),
+        };
+        let mut fill_idx = 0;
+        for (idx, (child, (before, after))) in children.iter().zip(&edges).enumerate() {
+            let size = match child.size {
+                Size::Fixed(size) => size.saturating_add(before + after),
+                Size::Fill => {
+                    // give any rows that don't divide evenly to the first ones
+                    let extra = (fill_idx < left_over % fills) as u16;
+                    fill_idx += 1;
+                    left_over / fills + extra
+                }
+            }
+            .min(end.saturating_sub(pos)); // anything that doesn't fit is cut off
+            let rect = match direction {
+                Direction::Horizontal => Rect {
+                    x: pos,
+                    y: inner.y,
+                    width: size,
+                    height: inner.height,
+                },
+                Direction::Vertical => Rect {
+                    x: inner.x,
+                    y: pos,
+                    width: inner.width,
+                    height: size,
+                },
+            };
+            self.place(child, rect);
+            pos = pos
+                .saturating_add(size)
+                .saturating_sub(overlaps.get(idx).copied().unwrap_or(0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn border() -> Option<Border> {
+        Some(Border {
+            area: BorderArea::Messages,
+            widths: BorderWidths {
+                left: 1,
+                right: 1,
+                top: 1,
+                bottom: 1,
+            },
+        })
+    }
+
+    fn rect(x: u16, y: u16, width: u16, height: u16) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// A sidebar of `width` next to the messages, both with borders
+    fn with_sidebar(side: SidebarSide, width: u16) -> Node {
+        let sidebar = Node::pane(Pane::Channels, Size::Fixed(width), border()).mark_as_sidebar();
+        let messages = Node::pane(Pane::Messages, Size::Fill, border());
+        let children = match side {
+            SidebarSide::Left => vec![sidebar, messages],
+            SidebarSide::Right => vec![messages, sidebar],
+        };
+        Node::split(Direction::Horizontal, Size::Fill, None, children)
+    }
+
+    #[test]
+    fn borders_are_shared() {
+        let root = Node::split(
+            Direction::Horizontal,
+            Size::Fill,
+            None,
+            vec![
+                Node::pane(Pane::Channels, Size::Fill, border()),
+                Node::pane(Pane::Messages, Size::Fill, border()),
+            ],
+        );
+        let layout = Layout::new(&root, 10, 5);
+        let outsides: Vec<Rect> = layout.borders().iter().map(|(rect, _)| *rect).collect();
+        // column 6 is the right border of one and the left border of the other
+        assert_eq!(outsides, [rect(1, 1, 6, 5), rect(6, 1, 5, 5)]);
+        assert_eq!(layout.get(Pane::Channels), Some(rect(2, 2, 4, 3)));
+        assert_eq!(layout.get(Pane::Messages), Some(rect(7, 2, 3, 3)));
+    }
+
+    #[test]
+    fn fill_remainder_goes_to_the_first() {
+        let root = Node::split(
+            Direction::Vertical,
+            Size::Fill,
+            None,
+            vec![
+                Node::pane(Pane::Header, Size::Fill, None),
+                Node::pane(Pane::Channels, Size::Fixed(2), None),
+                Node::pane(Pane::Servers, Size::Fill, None),
+                Node::pane(Pane::Messages, Size::Fill, None),
+            ],
+        );
+        let layout = Layout::new(&root, 3, 12);
+        let heights: Vec<u16> = [Pane::Header, Pane::Channels, Pane::Servers, Pane::Messages]
+            .iter()
+            .map(|&pane| layout.get(pane).unwrap().height)
+            .collect();
+        assert_eq!(heights, [4, 2, 3, 3]);
+    }
+
+    #[test]
+    fn overflow_is_clipped() {
+        let root = Node::split(
+            Direction::Vertical,
+            Size::Fill,
+            None,
+            vec![
+                Node::pane(Pane::Header, Size::Fixed(3), None),
+                Node::pane(Pane::Channels, Size::Fixed(4), None),
+                Node::pane(Pane::Servers, Size::Fill, None),
+            ],
+        );
+        let layout = Layout::new(&root, 3, 5);
+        assert_eq!(layout.get(Pane::Header), Some(rect(1, 1, 3, 3)));
+        assert_eq!(layout.get(Pane::Channels), Some(rect(1, 4, 3, 2)));
+        assert_eq!(layout.get(Pane::Servers).unwrap().height, 0);
+        assert_eq!(layout.pane_at(1, 6), None);
+
+        // sizes that don't fit in a u16 once borders are added are clipped too
+        let root = Node::split(
+            Direction::Horizontal,
+            Size::Fill,
+            None,
+            vec![
+                Node::pane(Pane::Channels, Size::Fixed(u16::MAX), border()),
+                Node::pane(Pane::Messages, Size::Fixed(u16::MAX), border()),
+            ],
+        );
+        let layout = Layout::new(&root, 10, 5);
+        assert_eq!(layout.get(Pane::Channels), Some(rect(2, 2, 8, 3)));
+        assert_eq!(layout.get(Pane::Messages).unwrap().width, 0);
+    }
+
+    #[test]
+    fn sidebar_on_either_side() {
+        let layout = Layout::new(&with_sidebar(SidebarSide::Left, 10), 40, 10);
+        assert_eq!(layout.get(Pane::Channels), Some(rect(2, 2, 10, 8)));
+        assert_eq!(layout.get(Pane::Messages), Some(rect(13, 2, 27, 8)));
+        assert_eq!(layout.sidebar_border_x(), Some(12));
+
+        let layout = Layout::new(&with_sidebar(SidebarSide::Right, 10), 40, 10);
+        assert_eq!(layout.get(Pane::Messages), Some(rect(2, 2, 27, 8)));
+        assert_eq!(layout.get(Pane::Channels), Some(rect(30, 2, 10, 8)));
+        assert_eq!(layout.sidebar_border_x(), Some(29));
+    }
+
+    #[test]
+    fn hidden_panes() {
+        let root = Node::pane(Pane::Messages, Size::Fill, None);
+        let layout = Layout::new(&root, 10, 10);
+        assert_eq!(layout.get(Pane::Channels), None);
+        assert_eq!(layout.sidebar_border_x(), None);
+    }
+
+    #[test]
+    fn finds_panes_but_not_borders() {
+        let layout = Layout::new(&with_sidebar(SidebarSide::Left, 10), 40, 10);
+        assert_eq!(layout.pane_at(2, 2), Some(Pane::Channels));
+        assert_eq!(layout.pane_at(11, 9), Some(Pane::Channels));
+        assert_eq!(layout.pane_at(13, 5), Some(Pane::Messages));
+        assert_eq!(layout.pane_at(12, 5), None);
+        assert_eq!(layout.pane_at(1, 1), None);
+        assert_eq!(layout.pane_at(41, 5), None);
+    }
+}
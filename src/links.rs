@@ -0,0 +1,105 @@
+use crate::drawing::truncate;
+use crate::gui::{CommandError, Gui};
+use crate::prompt::{Prompt, PromptEvent, PromptField};
+use crate::Mode;
+use std::process::{Command, Stdio};
+use termion::event::Event;
+
+/// Everything the link picker needs besides the prompt itself
+pub struct LinkPicker {
+    links: Vec<String>, // in the same order as the prompt's options
+    mode: Mode,         // what to go back to afterwards
+}
+
+/// How wide a link can be in the picker before it's cut short
+const MAX_LINK_WIDTH: usize = 60;
+
+impl Gui {
+    /// Let the user choose one of the links in the selected message to open
+    pub fn open_link_picker(&mut self) {
+        if let Err(CommandError(e)) = self.try_open_link_picker() {
+            self.send_system(&e);
+        }
+    }
+
+    fn try_open_link_picker(&mut self) -> Result<(), CommandError> {
+        if self.selected_message.is_none() {
+            return Err(CommandError("No message selected".into()));
+        }
+        let links: Vec<String> = self
+            .get_selected_message()?
+            .links()
+            .iter()
+            .filter(|url| !url.starts_with("data:")) // images, which alt+i shows instead
+            .cloned()
+            .collect();
+        if links.is_empty() {
+            return Err(CommandError("The selected message has no links".into()));
+        }
+        // numbered, so that links which look the same once they're cut short can still be told apart
+        let options = links
+            .iter()
+            .enumerate()
+            .map(|(idx, url)| format!("{}. {}", idx + 1, truncate(url, MAX_LINK_WIDTH)))
+            .collect();
+        self.prompt = Some(Prompt::new(
+            "Open link",
+            vec![PromptField::Choice {
+                name: "Link",
+                options,
+                default: 0,
+            }],
+            vec!["Open", "Cancel"],
+        ));
+        self.link_picker = Some(LinkPicker {
+            links,
+            mode: self.mode,
+        });
+        self.mode = Mode::LinkPicker;
+        Ok(())
+    }
+
+    pub fn link_picker_event(&mut self, event: Event) {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return;
+        };
+        let url = match prompt.handle_event(event) {
+            Some(PromptEvent::ButtonPressed("Open")) => {
+                let idx = prompt.get_choice_index("Link").unwrap(); // unwrap: it's defined above
+                self.link_picker
+                    .as_ref()
+                    .and_then(|picker| picker.links.get(idx))
+                    .cloned()
+            }
+            Some(PromptEvent::ButtonPressed("Cancel")) => None,
+            Some(PromptEvent::ButtonPressed(_)) => unreachable!(),
+            None => return,
+        };
+        self.prompt = None;
+        if let Some(picker) = self.link_picker.take() {
+            self.mode = picker.mode;
+        }
+        if let Some(url) = url {
+            if let Err(e) = open_url(&url) {
+                self.send_system(&format!("Couldn't open {}: {}", url, e));
+            }
+        }
+    }
+}
+
+/// Open a URL in the user's browser, without waiting for it to close
+fn open_url(url: &str) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    // anything it prints would end up all over the screen
+    command
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
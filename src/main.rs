@@ -1,17 +1,24 @@
 mod api;
+mod cells;
+mod clipboard;
+mod colour;
+mod diff;
 
 use crate::api::Response;
-use crate::drawing::draw_border;
+use crate::cells::CellBuffer;
+use crate::drawing::draw_borders;
+use crate::layout::LayoutSettings;
 use crate::prompt::*;
-use crate::server::Server;
-use api::{Status, SyncData, SyncServer};
+use crate::server::{MessageFormat, Server, ServerId};
+use api::{Folder, Status, SyncData, SyncServer};
 use drawing::Theme;
 use fmtstring::FmtString;
 use native_tls::TlsConnector;
 use serde::{Deserialize, Serialize};
 use server::WriteAsterRequest;
 use std::io::{stdin, stdout, BufRead, BufReader, Write};
-use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use termion::event::{Event, Key};
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
@@ -19,9 +26,22 @@ use tokio::sync::broadcast;
 
 mod drawing;
 mod events;
+mod folders;
 mod gui;
+mod highlight;
+mod history;
+mod images;
+mod layout;
+mod links;
+mod markdown;
+mod mentions;
 mod prompt;
+mod reply;
 mod server;
+mod settings;
+mod styled;
+mod template;
+mod unicode;
 
 use gui::Gui;
 
@@ -31,6 +51,9 @@ pub enum Mode {
     Messages,
     Settings,
     EditMessage,
+    LinkPicker,
+    ImageView,
+    EditHistory,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -46,13 +69,59 @@ pub enum DisplayMessage {
     System(FmtString),
 }
 
+/// Most events to handle before drawing, so the screen still updates while packets keep coming in
+const MAX_EVENTS_PER_DRAW: usize = 256;
+
+/// How often to redraw when nothing else happens, so that relative times don't go out of date
+const TICK_SECS: u64 = 20;
+
+/// Send a Tick every few seconds until the returned flag is set
+fn start_ticker(tx: std::sync::mpsc::Sender<LocalMessage>) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(std::time::Duration::from_secs(TICK_SECS));
+        if stopped.load(Ordering::Relaxed) || tx.send(LocalMessage::Tick).is_err() {
+            return;
+        }
+    });
+    stop
+}
+
 pub enum LocalMessage {
     Keyboard(Event),
-    Network(String, SocketAddr),
+    Network(String, ServerId),
     NetError(String),
+    ThemeChanged(std::path::PathBuf),
+    ImageLoaded(String, Result<std::sync::Arc<images::LoadedImage>, String>),
+    Tick, // nothing happened, but it's time to redraw things like relative times
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// When to show a desktop notification for a new message
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationRule {
+    Always,
+    Unfocussed, // only for messages we're not looking at, or when we've been away for a bit
+    Mentions,   // like Unfocussed, but only for messages that mention us
+    Never,
+}
+
+impl NotificationRule {
+    pub const ALL: [NotificationRule; 4] =
+        [Self::Always, Self::Unfocussed, Self::Mentions, Self::Never];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Always => "Always",
+            Self::Unfocussed => "When unfocussed",
+            Self::Mentions => "Only mentions",
+            Self::Never => "Never",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Settings {
     pub uname: String,
     pub passwd: String,
@@ -61,6 +130,22 @@ pub struct Settings {
     pub sync_port: u16,
     pub theme: String,
     pub sidebar_width: usize,
+    pub notifications: NotificationRule,
+    pub time_format: String,
+    pub relative_times: bool, // "5 min ago" instead of the time for recent messages
+    pub group_minutes: u16, // how close together messages from one person are for them to share a header
+    pub raw_markdown: bool,
+    pub message_template: String, // see template::Template for what can go in it
+    /// Command that prints an image from the web, with the URL added on the end. Once it's set, links
+    /// to images are fetched as soon as their messages load, including ones other people post, so
+    /// whoever hosts the image sees the reader's IP address. Leave it empty to only show images that
+    /// come with the message or from `image_directory`.
+    pub image_fetcher: String,
+    pub image_directory: String, // file:// image links are only shown from in here, and not at all if it's empty
+    pub image_size_limit: u64,   // in KB
+    pub layout: LayoutSettings,
+    pub folders: Vec<Folder>,
+    pub sync_folders: bool, // whether the sync server stores folders, since they're only sent if it does
 }
 
 async fn init_server_from_syncserver(
@@ -95,6 +180,8 @@ async fn init_server_from_syncserver(
         conn.uuid = serv.uuid;
         conn.uname = Some(serv.uname.clone());
     }
+    conn.folder.clone_from(&serv.folder);
+    conn.hide_images = serv.hide_images;
     Some(conn)
 }
 
@@ -105,6 +192,16 @@ fn load_config_json() -> serde_json::Value {
         "passwd": Option::<&str>::None,
         "theme": "default",
         "sidebar_width": 32,
+        "notifications": "unfocussed",
+        "time_format": "%H:%M",
+        "relative_times": false,
+        "group_minutes": 5,
+        "raw_markdown": false,
+        "message_template": template::DEFAULT_TEMPLATE,
+        "image_fetcher": "",
+        "image_directory": "",
+        "image_size_limit": 4096,
+        "layout": LayoutSettings::default(),
         "pfp": "iVBORw0KGgoAAAANSUhEUgAAAEAAAABACAYAAACqaXHeAAABhGlDQ1BJQ0MgcHJvZmlsZQAAKJF9kT1Iw0AcxV9TtSIVBzuIOmSoThZERRy1CkWoEGqFVh1MLv2CJg1Jiouj4Fpw8GOx6uDirKuDqyAIfoC4uTkpukiJ/0sKLWI8OO7Hu3uPu3eAUC8zzeoYBzTdNlOJuJjJroqhVwTRhQjCGJKZZcxJUhK+4+seAb7exXiW/7k/R6+asxgQEIlnmWHaxBvE05u2wXmfOMKKskp8Tjxm0gWJH7muePzGueCywDMjZjo1TxwhFgttrLQxK5oa8RRxVNV0yhcyHquctzhr5Spr3pO/MJzTV5a5TnMYCSxiCRJEKKiihDJsxGjVSbGQov24j3/Q9UvkUshVAiPHAirQILt+8D/43a2Vn5zwksJxoPPFcT5GgNAu0Kg5zvex4zROgOAzcKW3/JU6MPNJeq2lRY+Avm3g4rqlKXvA5Q4w8GTIpuxKQZpCPg+8n9E3ZYH+W6BnzeutuY/TByBNXSVvgINDYLRA2es+7+5u7+3fM83+fgAWfnKC/m8eaQAAAAZiS0dEAAAAAAAA+UO7fwAAAAlwSFlzAAAuIwAALiMBeKU/dgAAAAd0SU1FB+UDBhQPDH2XXtUAAAAZdEVYdENvbW1lbnQAQ3JlYXRlZCB3aXRoIEdJTVBXgQ4XAAAIyUlEQVR42t1ba0xT2Rb+TikVTqk0iOkoFC2IJlpiCBQiBMYAakREiUb54fxRE+ThTbxkjI/wMDdDgterCaNmVBxjRqEqPiDgKwaCkRBxkEhqTCq2KJYpJpaW0sPDQu8PisFyTt9Hoetn1977nO/ba62utc7eBFiW6urq8NTU1HULFiyIDQwMjPb395eMj4+Hmc3mn/h8PgBgeHgY/v7+Wh6Pp/ny5Yt6ZGTk7djYWNfTp0/b9+/f/5HN9yPYWLStrS05Ojp628TExI7g4OBIT9YaGhpScTic2z09PfVJSUltc5aAhw8fCqVS6d6AgIBCkiQj2SCWoijV6Ojoue7u7j8zMzP1c4KA9vZ24ZIlS46HhIQc4HK5QfgOYjabh3U63R/9/f2/JSUl6X8YAX19fQXBwcH/4XK5IfgBYjabdQaDoUQsFp//rgT09PREC4XCayRJJmAOCEVRHXq9fs+KFSvesk6ARqPZExwcfIHD4ZCYQzI5OUkZDIa8sLCwa6wRMDAw8LtAICjCHBaj0XhWJBIddHY8x5lBV65cCdRqtfVzHTwACASCIq1WW3/+/PlAr1iAXC4n09PT7/P5/J8xj8RkMrW2tLRk7tq1i/KEAGJgYOCeQCDIxjwUo9HYIBKJtgOwuOUCOp2uar6Ct7pDtk6nq3LLAj5//rwnMDDwL/iAUBT1S2ho6DWnCejv718pFAq7AJDwDaH0en3s0qVLlU65QFBQ0F8+BB4ASCsmxzFAr9cXcLncBPiYcLncBL1eX2DXBVQqlVAkEr0jCILV3H5sbAwajQZ6/VQdIxQKER4eDh6PxyoJFotF9+nTpyiJRKKntYDQ0NBjbII3GAyoqamBTCZDTEwMUlJSkJKSAqlUCplMhpqaGhgMBvaaHwQRsmjRomO0FqDVaoUCgaCPIAhWSlqFQoGioiK8ePHC7jiZTIaLFy9i5cqVbFnB8PDwsFgkEum/IcBkMv2bIIj/sfHQ169fIyEhYeZLgCAIupcDQRCIiIjAo0ePEBERwVbhVBwUFHTa1gUK2XjY4OAgCgsLbU2RyUQBAB8+fEBJSQlMJhNbrlD4TQwwGo3JBEF4vY1lNptx8uRJh2ZPJ3V1dbh06RJbBEQODQ0lfyWAw+FsY+NBDQ0NqKpizkRzc3ORm5vLqD9+/DhaWlpYIWEaM2FNFd8B8KoFvHnzBvHx8Yz62tpaZGdPlRmNjY3YvXs304tCoVBg2bJl3uZARZJkFMdoNIZ7G7zBYEBBQQGj/urVq1/BA0BWVhbkcjlTwEJpaSkb8SDSaDSGc/z8/NZ52+9Pnz6Njo4OWn1xcTG2b98+6/esrCyUlZUxxoPq6mqvu4Gfn986DoBYby764MEDnDp1ilaXmJiI4uJicLlc2n+A/Px8bN68mXbusWPH2IgHsQRFUbcA7PTGakqlErGxzHy+fPkSq1atsruGWq2GVCpl2jEoFApv5gd1HAASb6w0NDSEgweZe5FyudwheACQSCS4d+8erW5iYgJlZWWgKMpbBEg4AMI8XWViYgJVVVV49uwZLJbZ3afDhw8zmjadZGRkoLy8nFZ38+ZNb+YHYQRFURZv+P3OnfRelJycjLq6OixcuNClNY1GI/bt24empiZafVNTE9avX+95UuQpAT09PVi7di2jvqury+3CRqVSISYmhlbH4/Hw6tUrj+MBx5PJRqMRxcXFjPpbt255VNVFRkYyxoPx8XGUl5d7HA/cJsBiseDcuXN48uQJYxq7adMmj03UXjy4ceMGLl++7LEL/APgJ1cnPn78GDk5ObS6tLQ0XL9+3a7fj46OQqPRwGKxIDw8HAEBAXYtbe/evWhqaqKtJD2IB1qCoqi/AcS56ptSqZSxrO3u7kZUVBTj/M7OThw6dAidnZ0AgLi4OJw5cwZxcXFuPZPH46G7uxtisdhVAjo5ANSu+v3Ro0cZwd+5c8cueKVSidTU1K/gpwlJTU2FUqm0Gw/q6+u9HQ/UHAAufVO/cOECGhsbaXVlZWXYuHGj3flMcx3pbOOBbb4hl8vdiQdvOQC6nB3d3NzMWLBkZmYiPz+f0TJmpsPu6KbrhQMHDmDLli20zzly5AhaW1tdIaCLA6DdmZHv37/H1q1bGV+ssrISAoHA4TqrV692SzctAoEAlZWVtBknAOzYsQN9fX3OEtDOIUnyIwCVvVEmkwmlpaWM+rt37yIy0rmWwoYNG9zS2dYLDQ0NtLqRkRGUl5djZGTEmYbIx+k84La9kW1tbairq6PVnThxAhkZGU7bnEwmQ21tLW2xJJPJnF4nPT2dMT+Qy+Voa3N4pPA2AEwX5vUAfrUX+ekSoZycHOTl5Tn0e1vJzs6GWq3Gx49Th0DFYjEWL17sWgJjjQcdHR24f/8+bVfKgdR/7Qk66gs+f/4caWlps15AoVBg+fLl+JGiVquxZs2aWZvQ3NyMxMREu/1A21T4HNPo+Ph4VFRUfLP7ra2tPxz8dDyw7RRVVFTYbcjOxDrTAoQA+gAwfhrr7e3F4OAgJBIJhEIh5pLodDr09vYiJCTE0cYMAxCTJPntpzErCSftxQIfkf+SJHl4lgXMsIJ3AEJ8FLwOQNT07s8qh62KEh/e/ZKZ4GdZwAxLeA7A106JdJAkmehsQ+QXAJQPgaesmJzrCJEkqQSQ50ME5FkxOd8SI0nyGoCzPgD+rBULXCIAACYnJ/8FoGEeg2+wYmBOqR06D0WRAO4D+HmegW8FkEmSpN1Y5rArbF1g8zyzhAYAmx2Bd8oCbKzhdwBF88Dnnb4w4fKVGYqi9gC4gLl3lJayRnv2rszMICEawLU5lCx1WCyWPXw+3+VLU259GSJJ8q01qyq05tc/MrcvJEky0R3wblsATQF1HMABe6W0l2UYwB8AfrPN7b87ATZE7LVaRSRLwFXWZsafngL3OgE2ZCQD2AZghxfIUGGqgVlPkuTcvTzNJCaTKZwgiHWYOowVjakjOWGY/UFWC0CDqU91bwF0WSyWdj6fz+r1+f8DKPNT9Y1ZEZEAAAAASUVORK5CYII=",
     });
     let mut preferences_path = dirs::preference_dir().unwrap();
@@ -139,18 +236,43 @@ async fn load_servers(
     servers
 }
 
+/// Whether two saved servers are the same account on the same host
+fn is_duplicate(a: &SyncServer, b: &SyncServer) -> bool {
+    a.ip.eq_ignore_ascii_case(&b.ip)
+        && a.port == b.port
+        && server::same_account((a.uuid, Some(&a.uname)), (b.uuid, Some(&b.uname)))
+}
+
+/// Collapse duplicate entries in the saved server list into the first occurrence,
+/// filling in anything it's missing from the later copies.
+/// Returns the merged list and how many entries were removed.
+fn merge_duplicate_servers(servers: Vec<SyncServer>) -> (Vec<SyncServer>, usize) {
+    let mut merged: Vec<SyncServer> = Vec::new();
+    let mut removed = 0;
+    for serv in servers {
+        if let Some(existing) = merged.iter_mut().find(|m| is_duplicate(m, &serv)) {
+            existing.uuid = existing.uuid.or(serv.uuid);
+            existing.name = existing.name.take().or(serv.name);
+            existing.pfp = existing.pfp.take().or(serv.pfp);
+            existing.folder = existing.folder.take().or(serv.folder);
+            existing.hide_images |= serv.hide_images;
+            removed += 1;
+        } else {
+            merged.push(serv);
+        }
+    }
+    (merged, removed)
+}
+
 enum AuthMode {
     Login,
     Register,
 }
 
-fn load_sync_data(
+fn connect_to_sync_server(
     ip: &str,
     port: u16,
-    uname: &str,
-    passwd: &str,
-    auth: AuthMode,
-) -> Result<(Option<SyncData>, Vec<SyncServer>), std::io::Error> {
+) -> Result<native_tls::TlsStream<std::net::TcpStream>, std::io::Error> {
     let conn = std::net::TcpStream::connect((ip, port))?;
 
     let cx = TlsConnector::builder()
@@ -158,9 +280,63 @@ fn load_sync_data(
         .build()
         .expect("Couldn't initialise a TLS connection");
 
-    let mut conn = cx
-        .connect(ip, conn)
-        .expect("AAA TEST can't init tls connection");
+    cx.connect(ip, conn)
+        .map_err(|e| std::io::Error::other(e.to_string()))
+}
+
+/// Upload our username and pfp to the sync server, and folders too if it stores them
+fn save_sync_data(
+    ip: &str,
+    port: u16,
+    uname: &str,
+    passwd: &str,
+    pfp: &str,
+    folders: Option<&[Folder]>,
+) -> Result<(), std::io::Error> {
+    use std::io::{Error, ErrorKind};
+    let mut conn = connect_to_sync_server(ip, port)?;
+    conn.write_request(&api::Request::Login {
+        passwd: passwd.to_owned(),
+        uname: Some(uname.to_owned()),
+        uuid: None,
+    })?;
+    conn.write_request(&api::Request::SyncSet {
+        uname: uname.to_owned(),
+        pfp: pfp.to_owned(),
+        folders: folders.map(<[Folder]>::to_vec),
+    })?;
+
+    let mut reader = BufReader::new(conn);
+    loop {
+        let mut buf = String::new();
+        if BufRead::read_line(&mut reader, &mut buf)? == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "The sync server closed the connection",
+            ));
+        }
+        let response: Response = serde_json::from_str(&buf)?;
+        match response {
+            Response::SyncSet {
+                status: Status::Ok, ..
+            } => return Ok(()),
+            Response::APIVersion { .. } => (),
+            res if res.status() != Status::Ok => {
+                return Err(Error::other(format!("{}: {}", res.name(), res.status())))
+            }
+            _ => (),
+        }
+    }
+}
+
+fn load_sync_data(
+    ip: &str,
+    port: u16,
+    uname: &str,
+    passwd: &str,
+    auth: AuthMode,
+) -> Result<(Option<SyncData>, Vec<SyncServer>), std::io::Error> {
+    let mut conn = connect_to_sync_server(ip, port)?;
 
     match auth {
         AuthMode::Login => conn.write_request(&api::Request::Login {
@@ -218,14 +394,11 @@ fn load_sync_data(
             res => {
                 // error on non-OK status. it's fine if the server sends us data we don't care about, as long as it's Ok
                 if res.status() != Ok {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!(
-                            "Unexpected result from the server: {}: {}",
-                            res.name(),
-                            res.status()
-                        ),
-                    ));
+                    return Err(Error::other(format!(
+                        "Unexpected result from the server: {}: {}",
+                        res.name(),
+                        res.status()
+                    )));
                 }
             }
         }
@@ -246,7 +419,7 @@ fn get_sync_details<W: Write>(
         || config["sync_port"].is_null()
         || show_error.is_some()
     {
-        let theme = Theme::new("default").unwrap(); // TODO get this from a legitimate source, rn its validity is questionable
+        let theme = Theme::fallback(0); // TODO get this from a legitimate source
 
         let mut prompt = Prompt::new(
             "Enter login details",
@@ -254,14 +427,17 @@ fn get_sync_details<W: Write>(
                 PromptField::String {
                     name: "Username",
                     default: config["uname"].as_str().map(|s| s.to_owned()),
+                    max_width: 48,
                 },
-                PromptField::String {
+                PromptField::Password {
                     name: "Password",
                     default: config["passwd"].as_str().map(|s| s.to_owned()),
+                    max_width: 48,
                 },
                 PromptField::String {
                     name: "Sync server IP",
                     default: config["sync_ip"].as_str().map(|s| s.to_owned()),
+                    max_width: 48,
                 },
                 PromptField::U16 {
                     name: "Sync server port",
@@ -277,9 +453,9 @@ fn get_sync_details<W: Write>(
         );
         let (w, h) = termion::terminal_size().unwrap();
         let ph = prompt.height();
-        let pw = 18 + 15; // approx
-        let x = (w - pw) / 2;
-        let y = (h - ph) / 2;
+        let pw = prompt.width();
+        let x = w.saturating_sub(pw) / 2 + 1;
+        let y = h.saturating_sub(ph) / 2 + 1;
         write!(screen, "{}", termion::clear::All).unwrap();
         if let Some(err) = show_error {
             write!(screen, "{}{}", termion::cursor::Goto(1, 1), err).unwrap();
@@ -292,7 +468,9 @@ fn get_sync_details<W: Write>(
             let prompt_event = prompt.handle_event(event.unwrap().unwrap());
             match prompt_event {
                 Some(PromptEvent::ButtonPressed("Login"))
-                | Some(PromptEvent::ButtonPressed("Register")) => {
+                | Some(PromptEvent::ButtonPressed("Register"))
+                    if prompt.validate() =>
+                {
                     let sync_ip = prompt.get_str("Sync server IP").unwrap().to_owned();
                     let sync_port = prompt.get_u16("Sync server port").unwrap();
                     let uname = prompt.get_str("Username").unwrap().to_owned();
@@ -315,6 +493,8 @@ fn get_sync_details<W: Write>(
                     ));
                 }
                 Some(PromptEvent::ButtonPressed("Quit")) => return Err(()),
+                Some(PromptEvent::ButtonPressed("Login"))
+                | Some(PromptEvent::ButtonPressed("Register")) => (), // invalid fields, validate() shows why
                 Some(PromptEvent::ButtonPressed(_)) => unreachable!(),
                 None => (),
             }
@@ -338,6 +518,29 @@ fn load_settings(config: &serde_json::Value, sync_data: Option<SyncData>) -> Set
 
     let theme = config["theme"].as_str().unwrap_or("default").to_string();
     let sidebar_width = config["sidebar_width"].as_u64().unwrap_or(32) as usize;
+    let notifications = serde_json::from_value(config["notifications"].clone())
+        .unwrap_or(NotificationRule::Unfocussed);
+    let time_format = config["time_format"]
+        .as_str()
+        .unwrap_or("%H:%M")
+        .to_string();
+    let relative_times = config["relative_times"].as_bool().unwrap_or(false);
+    // clamped before narrowing, since a hand-edited config could have anything in it
+    let group_minutes = config["group_minutes"]
+        .as_u64()
+        .unwrap_or(5)
+        .min(settings::MAX_GROUP_MINUTES as u64) as u16;
+    let raw_markdown = config["raw_markdown"].as_bool().unwrap_or(false);
+    let message_template = config["message_template"]
+        .as_str()
+        .unwrap_or(template::DEFAULT_TEMPLATE)
+        .to_string();
+    let image_fetcher = config["image_fetcher"].as_str().unwrap_or("").to_string();
+    let image_directory = config["image_directory"].as_str().unwrap_or("").to_string();
+    let image_size_limit = config["image_size_limit"].as_u64().unwrap_or(4096);
+    let layout = serde_json::from_value(config["layout"].clone()).unwrap_or_default();
+    let folders: Vec<Folder> =
+        serde_json::from_value(config["folders"].clone()).unwrap_or_default();
     if let Some(sync_data) = sync_data {
         Settings {
             uname: sync_data.uname,
@@ -347,6 +550,18 @@ fn load_settings(config: &serde_json::Value, sync_data: Option<SyncData>) -> Set
             sync_port,
             theme,
             sidebar_width,
+            notifications,
+            time_format,
+            relative_times,
+            group_minutes,
+            raw_markdown,
+            message_template,
+            image_fetcher,
+            image_directory,
+            image_size_limit,
+            layout,
+            sync_folders: sync_data.folders.is_some(),
+            folders: sync_data.folders.unwrap_or(folders),
         }
     } else {
         let uname = config["uname"].as_str().unwrap().to_owned(); // yea i think this unwrap is O.K. rn
@@ -359,6 +574,18 @@ fn load_settings(config: &serde_json::Value, sync_data: Option<SyncData>) -> Set
             sync_port,
             theme,
             sidebar_width,
+            notifications,
+            time_format,
+            relative_times,
+            group_minutes,
+            raw_markdown,
+            message_template,
+            image_fetcher,
+            image_directory,
+            image_size_limit,
+            layout,
+            folders,
+            sync_folders: false,
         }
     }
 }
@@ -375,6 +602,29 @@ fn process_input(tx: std::sync::mpsc::Sender<LocalMessage>) {
     }
 }
 
+/// Poll the user's theme directory, telling the main loop whenever a theme file is saved
+fn watch_themes(tx: std::sync::mpsc::Sender<LocalMessage>) {
+    let Some(dir) = drawing::user_theme_dir() else {
+        return;
+    };
+    let mut last_modified = std::collections::HashMap::new();
+    loop {
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let Ok(modified) = entry.metadata().and_then(|m| m.modified()) else {
+                continue;
+            };
+            let path = entry.path();
+            let changed = last_modified
+                .insert(path.clone(), modified)
+                .is_some_and(|prev| prev != modified);
+            if changed && tx.send(LocalMessage::ThemeChanged(path)).is_err() {
+                return; // the main loop has gone away
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let (tx, rx): (
@@ -415,26 +665,41 @@ async fn main() {
     };
 
     let a: Vec<SyncServer> = serde_json::from_value(conf["servers"].clone()).unwrap(); // TODO temp
+    let (a, num_duplicates) = merge_duplicate_servers(a);
 
     let settings = load_settings(&conf, sync_data);
     let servers = load_servers(&a, tx.clone(), cancel_tx.clone(), settings.passwd.clone()).await;
 
     let mut last_width = 0;
-    let mut last_height = 0;
     let mut last_theme = settings.theme.clone();
+    let mut last_layout = None;
+    let mut last_format: Option<MessageFormat> = None;
+    let mut last_mode = Mode::Messages;
 
     let mut gui = Gui::new(tx.clone(), cancel_tx.clone(), settings, servers).await;
+    if num_duplicates > 0 {
+        gui.send_system(&format!(
+            "Merged {} duplicate server(s) in your config",
+            num_duplicates
+        ));
+    }
     screen.flush().unwrap();
 
     let input_tx = tx.clone();
     tokio::spawn(async move {
         process_input(input_tx);
     });
+    let theme_tx = tx.clone();
+    std::thread::spawn(move || watch_themes(theme_tx));
+    // only running while relative times are on, since nothing else needs it
+    let mut ticker: Option<Arc<AtomicBool>> = None;
 
     let mut last_interacted = std::time::SystemTime::now();
 
+    let mut frame = CellBuffer::new(0, 0);
     loop {
-        let ev = rx.recv().unwrap();
+        let mut ev = rx.recv().unwrap();
+        let mut redraw_border = false;
         let (width, height) = termion::terminal_size().unwrap();
 
         if width < 32 || height < 8 {
@@ -442,77 +707,225 @@ async fn main() {
             return;
         }
 
-        if last_width != width || last_height != height || last_theme != gui.settings.theme {
-            let border = draw_border(&gui.theme);
-            write!(screen, "{}", border).unwrap();
+        gui.width = width;
+        gui.height = height; // TODO get rid of this and do it properly ffs
+        if last_width != width {
+            gui.fit_sidebar();
+        }
+        frame.resize(width, height);
+
+        // deal with everything that's already arrived before drawing, so that a burst of packets is only drawn once
+        let mut handled = 0;
+        loop {
+            match ev {
+                LocalMessage::Keyboard(key) => {
+                    if !gui.handle_keyboard(key).await {
+                        drop(screen);
+                        gui.save_config();
+                        gui.upload_folders_on_quit();
+                        cancel_tx.send(());
+                        return;
+                    }
+                    last_interacted = std::time::SystemTime::now();
+                }
+
+                LocalMessage::Network(msg, id) => {
+                    let obj: Result<serde_json::Value, serde_json::Error> =
+                        serde_json::from_str(&msg);
+                    match obj {
+                        Ok(obj) => {
+                            let response: Response = serde_json::from_value(obj).unwrap();
+                            // for formatting the messages
+                            let format = gui.message_format();
+                            let notifications = gui.settings.notifications;
+                            let we_are_the_selected_server =
+                                gui.curr_server.is_some_and(|idx| gui.servers[idx].id == id);
+                            match gui
+                                .get_server_by_id(id)
+                                .expect("Network packet recv'd for offline server")
+                                .handle_network_packet(
+                                    response,
+                                    &format,
+                                    notifications,
+                                    last_interacted.elapsed().expect("Could not get the time"),
+                                    we_are_the_selected_server,
+                                )
+                                .await
+                            {
+                                Ok(()) => (),
+                                Err(e) => gui.send_system(&e),
+                            }
+                            gui.finish_jump();
+                            gui.load_images();
+                        }
+                        Err(_) => {
+                            //ignore for now
+                        }
+                    }
+                }
+                LocalMessage::NetError(e) => {
+                    gui.send_system(&e);
+                }
+                LocalMessage::ThemeChanged(path) => {
+                    redraw_border |= gui.theme_file_changed(&path);
+                }
+                LocalMessage::ImageLoaded(url, result) => gui.image_loaded(url, result),
+                LocalMessage::Tick => (),
+            }
+            handled += 1;
+            if handled >= MAX_EVENTS_PER_DRAW {
+                break;
+            }
+            match rx.try_recv() {
+                Ok(next) => ev = next,
+                Err(_) => break,
+            }
+        }
+        let layout = gui.layout();
+        if last_layout.as_ref() != Some(&layout)
+            || last_theme != gui.settings.theme
+            || redraw_border
+            || last_mode != gui.mode
+        // the image view covers the borders
+        {
+            let borders = draw_borders(&gui.theme, &layout, width, height);
+            write!(frame, "{}{}", termion::clear::All, borders).unwrap();
             last_theme.clone_from(&gui.settings.theme);
+            last_layout = Some(layout);
+            last_mode = gui.mode;
+        }
 
+        if gui.settings.relative_times != ticker.is_some() {
+            match ticker.take() {
+                Some(stop) => stop.store(true, Ordering::Relaxed),
+                None => ticker = Some(start_ticker(tx.clone())),
+            }
+        }
+
+        let format = gui.message_format();
+        let minute_passed = last_format.as_ref().is_some_and(|last| {
+            last.relative_to != format.relative_to
+                && *last
+                    == MessageFormat {
+                        relative_to: last.relative_to,
+                        ..format.clone()
+                    }
+        });
+        if minute_passed {
+            // only the last hour's worth of messages can say how long ago they were sent
+            let since = last_format
+                .as_ref()
+                .and_then(|last| last.relative_to)
+                .into_iter()
+                .chain(format.relative_to)
+                .min()
+                .unwrap_or_default();
+            for server in &mut gui.servers {
+                let format = server.message_format(&format);
+                if let Ok(ref mut net) = server.network {
+                    net.rebuild_recent(since, &format);
+                }
+            }
+            last_format = Some(format);
+        } else if last_format.as_ref() != Some(&format) {
             // TODO kinda ugly
             for server in &mut gui.servers {
+                let format = server.message_format(&format);
                 let Ok(ref mut net) = server.network else {
                     continue;
                 };
-                let max_message_width = width as usize - gui.theme.sidebar_width - 4; // TODO why 4???
+                net.regroup(&format);
                 for message in &mut net.loaded_messages {
-                    message.rebuild(&net.peers, max_message_width);
+                    message.rebuild(&net.peers, &format);
                 }
             }
+            last_format = Some(format);
         }
+        gui.draw_all(&mut frame);
+        frame.present(&mut screen).unwrap();
+        if let Some(text) = gui.clipboard.take() {
+            write!(screen, "{}", clipboard::osc52(&text)).unwrap();
+            screen.flush().unwrap();
+        }
+        last_width = width;
+    }
+}
 
-        gui.width = width;
-        gui.height = height; // TODO get rid of this and do it properly ffs
-
-        match ev {
-            LocalMessage::Keyboard(key) => {
-                if !gui.handle_keyboard(key).await {
-                    drop(screen);
-                    gui.save_config();
-                    cancel_tx.send(());
-                    return;
-                }
-                last_interacted = std::time::SystemTime::now();
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            LocalMessage::Network(msg, addr) => {
-                let obj: Result<serde_json::Value, serde_json::Error> = serde_json::from_str(&msg);
-                match obj {
-                    Ok(obj) => {
-                        let response: Response = serde_json::from_value(obj).unwrap();
-                        // for formatting the messages
-                        let max_message_width = width as usize - gui.theme.sidebar_width - 4; // TODO why 4???
-                        let we_are_the_selected_server = gui.curr_server.is_some_and(|idx| {
-                            gui.servers[idx]
-                                .network
-                                .as_ref()
-                                .is_ok_and(|net| net.remote_addr == addr)
-                        });
-                        match gui
-                            .get_server_by_addr(addr)
-                            .expect("Network packet recv'd for offline server")
-                            .handle_network_packet(
-                                response,
-                                max_message_width,
-                                last_interacted.elapsed().expect("Could not get the time"),
-                                we_are_the_selected_server,
-                            )
-                            .await
-                        {
-                            Ok(()) => (),
-                            Err(e) => gui.send_system(&e),
-                        }
-                    }
-                    Err(_) => {
-                        //ignore for now
-                    }
-                }
-            }
-            LocalMessage::NetError(e) => {
-                gui.send_system(&e);
-            }
+    fn saved(ip: &str, port: i32, uuid: Option<i64>, uname: &str) -> SyncServer {
+        SyncServer {
+            uuid,
+            uname: uname.to_owned(),
+            ip: ip.to_owned(),
+            port,
+            pfp: None,
+            name: None,
+            idx: 0,
+            folder: None,
+            hide_images: false,
         }
-        gui.draw_all(&mut screen);
-        screen.flush().unwrap();
-        last_width = width;
-        last_height = height;
+    }
+
+    #[test]
+    fn merges_on_uuid() {
+        let (merged, removed) = merge_duplicate_servers(vec![
+            saved("example.com", 2345, Some(1), "old name"),
+            saved("example.com", 2345, Some(1), "new name"),
+            saved("example.com", 2345, Some(2), "old name"),
+        ]);
+        assert_eq!(removed, 1);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].uname, "old name");
+        assert_eq!(merged[1].uuid, Some(2));
+    }
+
+    #[test]
+    fn falls_back_to_uname() {
+        let (merged, removed) = merge_duplicate_servers(vec![
+            saved("example.com", 2345, None, "alice"),
+            saved("example.com", 2345, Some(1), "alice"),
+            saved("example.com", 2345, None, "bob"),
+        ]);
+        assert_eq!(removed, 1);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].uuid, Some(1));
+        assert_eq!(merged[1].uname, "bob");
+    }
+
+    #[test]
+    fn host_is_case_insensitive_but_port_isnt() {
+        let (merged, removed) = merge_duplicate_servers(vec![
+            saved("Example.COM", 2345, Some(1), "alice"),
+            saved("example.com", 2345, Some(1), "alice"),
+            saved("example.com", 2346, Some(1), "alice"),
+        ]);
+        assert_eq!(removed, 1);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].ip, "Example.COM");
+        assert_eq!(merged[1].port, 2346);
+    }
+
+    #[test]
+    fn fills_in_from_later_copies() {
+        let mut first = saved("example.com", 2345, None, "alice");
+        first.name = Some("Kept".into());
+        let mut second = saved("example.com", 2345, Some(1), "alice");
+        second.name = Some("Dropped".into());
+        second.pfp = Some("pfp".into());
+        second.folder = Some("Work".into());
+        second.hide_images = true;
+        let (merged, removed) = merge_duplicate_servers(vec![first, second]);
+        assert_eq!(removed, 1);
+        let [ref merged] = merged[..] else {
+            panic!("expected one server, got {}", merged.len());
+        };
+        assert_eq!(merged.uuid, Some(1));
+        assert_eq!(merged.name.as_deref(), Some("Kept"));
+        assert_eq!(merged.pfp.as_deref(), Some("pfp"));
+        assert_eq!(merged.folder.as_deref(), Some("Work"));
+        assert!(merged.hide_images);
     }
 }
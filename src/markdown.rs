@@ -0,0 +1,405 @@
+use crate::drawing::ThemedArea;
+use crate::highlight::{Highlighter, Token};
+use crate::styled::Span;
+
+/// The escape codes for each kind of text in a message, taken from the theme
+#[derive(Clone, PartialEq, Debug)]
+pub struct MessageStyles {
+    pub text: String,
+    pub code: String,
+    pub quote: String,
+    pub link: String,
+    pub edited: String,
+    pub reply: String,
+    syntax: Vec<String>, // in the same order as `Token::ALL`
+}
+
+impl MessageStyles {
+    pub fn new(area: &ThemedArea) -> Self {
+        Self {
+            text: area.text.to_string(),
+            code: area.code.to_string(),
+            quote: area.quote.to_string(),
+            link: area.link.to_string(),
+            edited: area.edited.to_string(),
+            reply: area.reply.to_string(),
+            syntax: area
+                .syntax
+                .iter()
+                .map(|colour| colour.to_string())
+                .collect(),
+        }
+    }
+
+    /// The style for a token in a code block, on top of the code block's own
+    fn token(&self, token: Option<Token>) -> String {
+        let index = token.and_then(|token| Token::ALL.iter().position(|t| *t == token));
+        match index {
+            Some(index) => format!("{}{}", self.code, self.syntax[index]),
+            None => self.code.clone(),
+        }
+    }
+}
+
+/// One line of the message source, before it's wrapped to fit the screen
+#[derive(Default)]
+pub struct MarkdownLine {
+    pub prefix: Vec<Span>, // goes before the first row, like a bullet point
+    pub indent: Vec<Span>, // goes before every row the line wraps onto
+    pub text: Vec<Span>,
+    pub fill: Option<String>, // style to fill the rest of each row with, for code blocks
+}
+
+/// Inline delimiters, longest first so that `**` isn't mistaken for two `*`s
+const DELIMITERS: [&str; 6] = ["**", "__", "~~", "*", "_", "`"];
+
+/// Show the source exactly as it was typed, apart from making links clickable
+pub fn raw(content: &str, styles: &MessageStyles) -> Vec<MarkdownLine> {
+    content
+        .lines()
+        .map(|line| {
+            let mut text = Vec::new();
+            plain(&expand_tabs(line), &styles.text, styles, &mut text);
+            MarkdownLine {
+                text,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Style a message written in markdown. Each line of the source is its own line,
+/// since people don't expect single newlines to be joined up in a chat message.
+pub fn render(content: &str, styles: &MessageStyles) -> Vec<MarkdownLine> {
+    let mut lines = Vec::new();
+    let mut code_block = None; // Some if we're in one, with a highlighter if we know the language
+    for line in content.lines() {
+        let line = expand_tabs(line);
+        if let Some(tag) = line.trim_start().strip_prefix("```") {
+            code_block = match code_block {
+                Some(_) => None,
+                None => Some(Highlighter::new(tag.trim())),
+            };
+            continue;
+        }
+        match code_block {
+            Some(ref mut highlighter) => {
+                let text = match highlighter {
+                    Some(highlighter) => highlighter
+                        .line(&line)
+                        .into_iter()
+                        .map(|(token, text)| Span::new(&styles.token(token), text))
+                        .collect(),
+                    None => vec![Span::new(&styles.code, &line)],
+                };
+                lines.push(MarkdownLine {
+                    text,
+                    fill: Some(styles.code.clone()),
+                    ..Default::default()
+                });
+            }
+            None => lines.push(block(&line, &styles.text, styles)),
+        }
+    }
+    lines
+}
+
+/// Tabs would be drawn as nothing at all, so line things up with spaces instead
+fn expand_tabs(line: &str) -> String {
+    line.replace('\t', "    ")
+}
+
+/// A line outside of a code block, which might be quoted or part of a list
+fn block(line: &str, style: &str, styles: &MessageStyles) -> MarkdownLine {
+    if let Some(rest) = line.strip_prefix('>') {
+        let rest = rest.strip_prefix(' ').unwrap_or(rest);
+        let mut quoted = block(rest, &styles.quote, styles);
+        let bar = Span::new(&styles.quote, "│ ");
+        quoted.prefix.insert(0, bar.clone());
+        quoted.indent.insert(0, bar);
+        return quoted;
+    }
+
+    let rest = line.trim_start_matches(' ');
+    let nesting = " ".repeat(line.len() - rest.len());
+    let (marker, text) = match list_marker(rest) {
+        Some((marker, text)) => (Some(marker), text),
+        None => (None, line),
+    };
+    let mut out = MarkdownLine::default();
+    if let Some(marker) = marker {
+        let bullet = format!("{}{} ", nesting, marker);
+        out.indent.push(Span::new(
+            style,
+            &" ".repeat(crate::unicode::width(&bullet)),
+        ));
+        out.prefix.push(Span::new(style, &bullet));
+    }
+    inline(text, style, styles, &mut out.text);
+    out
+}
+
+/// The marker at the start of a list item and the text after it, like "-" and "item" for "- item".
+/// Unordered lists all get the same bullet no matter which character they were written with.
+fn list_marker(line: &str) -> Option<(String, &str)> {
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(text) = line.strip_prefix(bullet) {
+            return Some(("•".to_owned(), text));
+        }
+    }
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let after = &line[digits..];
+    if digits > 0 && (after.starts_with(". ") || after.starts_with(") ")) {
+        return Some((line[..digits + 1].to_owned(), &after[2..]));
+    }
+    None
+}
+
+/// Parse bold, italic, strikethrough and code spans, adding the styled text to `out`
+fn inline(text: &str, style: &str, styles: &MessageStyles, out: &mut Vec<Span>) {
+    let mut before = String::new(); // text that's waiting to be added, once we know it's not styled
+    let mut prev = None; // the character before `rest`
+    let mut rest = text;
+    'outer: while let Some(ch) = rest.chars().next() {
+        if let Some((len, span)) = link(rest, prev, style, styles) {
+            // the URL is taken as it is, so that underscores and asterisks in it aren't styling
+            plain(&before, style, styles, out);
+            before.clear();
+            out.push(span);
+            prev = rest[..len].chars().next_back();
+            rest = &rest[len..];
+            continue;
+        }
+        if ch == '\\' {
+            if let Some(escaped) = rest[1..].chars().next().filter(char::is_ascii_punctuation) {
+                before.push(escaped);
+                prev = Some(escaped);
+                rest = &rest[1 + escaped.len_utf8()..];
+                continue;
+            }
+        }
+        for delim in DELIMITERS {
+            if !rest.starts_with(delim) {
+                continue;
+            }
+            let Some(len) = closing(&rest[delim.len()..], delim, prev) else {
+                continue;
+            };
+            let inner = &rest[delim.len()..delim.len() + len];
+            plain(&before, style, styles, out);
+            before.clear();
+            match delim {
+                "`" => out.push(Span::new(&format!("{}{}", style, styles.code), inner)),
+                "**" | "__" => inline(
+                    inner,
+                    &format!("{}{}", style, termion::style::Bold),
+                    styles,
+                    out,
+                ),
+                "~~" => inline(
+                    inner,
+                    &format!("{}{}", style, termion::style::CrossedOut),
+                    styles,
+                    out,
+                ),
+                _ => inline(
+                    inner,
+                    &format!("{}{}", style, termion::style::Italic),
+                    styles,
+                    out,
+                ),
+            }
+            rest = &rest[2 * delim.len() + len..];
+            prev = delim.chars().last();
+            continue 'outer;
+        }
+        before.push(ch);
+        prev = Some(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+    plain(&before, style, styles, out);
+}
+
+/// Add text with no markdown in it to `out`, picking out any URLs so they can be clicked on
+fn plain(text: &str, style: &str, styles: &MessageStyles, out: &mut Vec<Span>) {
+    let mut start = 0; // of the text since the last URL
+    let mut prev = None;
+    for (idx, ch) in text.char_indices() {
+        if idx < start {
+            continue; // still inside the last URL
+        }
+        if let Some((len, span)) = link(&text[idx..], prev, style, styles) {
+            if start < idx {
+                out.push(Span::new(style, &text[start..idx]));
+            }
+            out.push(span);
+            start = idx + len;
+        }
+        prev = Some(ch);
+    }
+    if start < text.len() {
+        out.push(Span::new(style, &text[start..]));
+    }
+}
+
+/// The link at the start of `text`, if there is one, and how long it is in the source.
+/// Images sent as data URIs would be a screenful of base64, so they're shortened to a label.
+fn link(
+    text: &str,
+    prev: Option<char>,
+    style: &str,
+    styles: &MessageStyles,
+) -> Option<(usize, Span)> {
+    if prev.is_some_and(char::is_alphanumeric) {
+        return None; // in the middle of a word
+    }
+    let style = format!("{}{}", style, styles.link);
+    if text.starts_with("data:image/") {
+        let len = text.find(char::is_whitespace).unwrap_or(text.len());
+        let span = Span {
+            link: Some(text[..len].to_owned()),
+            ..Span::new(&style, "[image]")
+        };
+        return Some((len, span));
+    }
+    let len = url_len(text)?;
+    Some((len, Span::link(&style, &text[..len])))
+}
+
+/// How long the URL at the start of `text` is, if there is one. Punctuation at the end is left off
+/// since it's more likely to be the end of a sentence, and so are closing brackets that weren't opened
+/// in the URL, like in "(see https://example.com)".
+fn url_len(text: &str) -> Option<usize> {
+    let scheme = ["https://", "http://", "file://"]
+        .iter()
+        .find(|scheme| text.starts_with(**scheme))?;
+    let mut len = text
+        .find(|c: char| c.is_whitespace() || c.is_control() || "<>\"`".contains(c))
+        .unwrap_or(text.len());
+    loop {
+        let url = &text[..len];
+        let Some(last) = url.chars().next_back() else {
+            break;
+        };
+        let unbalanced = |open: char| url.matches(open).count() < url.matches(last).count();
+        let trim = match last {
+            '.' | ',' | ':' | ';' | '!' | '?' | '\'' | '*' | '_' | '~' => true,
+            ')' => unbalanced('('),
+            ']' => unbalanced('['),
+            _ => false,
+        };
+        if !trim {
+            break;
+        }
+        len -= last.len_utf8();
+    }
+    (len > scheme.len()).then_some(len)
+}
+
+/// Where the delimiter that closes `delim` is in `text`, which starts just after the opening one.
+/// Like in CommonMark, the text inside can't start or end with a space, and underscores
+/// have to be at the edges of words so that snake_case names are left alone.
+fn closing(text: &str, delim: &str, before_open: Option<char>) -> Option<usize> {
+    if delim == "`" {
+        return text.find('`').filter(|&len| len > 0);
+    }
+    let underscore = delim.starts_with('_');
+    let single = delim.len() == 1;
+    if text.starts_with(char::is_whitespace)
+        || (single && text.starts_with(delim))
+        || (underscore && before_open.is_some_and(char::is_alphanumeric))
+    {
+        return None;
+    }
+    let delim_char = delim.chars().next();
+    text.match_indices(delim).map(|(idx, _)| idx).find(|&idx| {
+        let before = text[..idx].chars().next_back();
+        let after = text[idx + delim.len()..].chars().next();
+        let touching_same = single && (before == delim_char || after == delim_char);
+        let ends_word = !underscore || !after.is_some_and(char::is_alphanumeric);
+        before.is_some_and(|c| !c.is_whitespace()) && !touching_same && ends_word
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use termion::style::{Bold, Italic};
+
+    fn styles() -> MessageStyles {
+        MessageStyles {
+            text: String::new(),
+            code: "<code>".into(),
+            quote: "<quote>".into(),
+            link: "<link>".into(),
+            edited: "<edited>".into(),
+            reply: "<reply>".into(),
+            syntax: Token::ALL
+                .iter()
+                .map(|t| format!("<{}>", t.name()))
+                .collect(),
+        }
+    }
+
+    /// The text of each span on one line, with its style
+    fn spans(line: &str) -> Vec<(String, String)> {
+        let mut out = Vec::new();
+        inline(line, "", &styles(), &mut out);
+        out.into_iter()
+            .map(|span| (span.style, span.text))
+            .collect()
+    }
+
+    fn span(style: &str, text: &str) -> (String, String) {
+        (style.to_owned(), text.to_owned())
+    }
+
+    #[test]
+    fn nested_emphasis() {
+        let bold = Bold.to_string();
+        let both = format!("{}{}", Bold, Italic);
+        assert_eq!(
+            spans("**bold *both* bold**"),
+            vec![
+                span(&bold, "bold "),
+                span(&both, "both"),
+                span(&bold, " bold"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_emphasis_is_left_alone() {
+        assert_eq!(spans("**not closed"), vec![span("", "**not closed")]);
+        assert_eq!(spans("a * b * c"), vec![span("", "a * b * c")]);
+        assert_eq!(spans("snake_case_name"), vec![span("", "snake_case_name")]);
+    }
+
+    #[test]
+    fn code_spans_are_not_styled_inside() {
+        assert_eq!(
+            spans("`*not italic*` and"),
+            vec![span("<code>", "*not italic*"), span("", " and")]
+        );
+        assert_eq!(spans("`unterminated"), vec![span("", "`unterminated")]);
+    }
+
+    #[test]
+    fn code_fences() {
+        let lines = render("```\n*code*\n```\n*after*", &styles());
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].fill.as_deref(), Some("<code>"));
+        assert_eq!(lines[0].text[0].text, "*code*");
+        assert_eq!(lines[1].fill, None);
+        assert_eq!(lines[1].text[0].text, "after");
+    }
+
+    #[test]
+    fn unterminated_code_fence_runs_to_the_end() {
+        let lines = render("before\n```rust\nlet x = 1;\n\n**still code**", &styles());
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].fill, None);
+        assert!(lines[1..].iter().all(|line| line.fill.is_some()));
+        assert_eq!(lines[3].text[0].text, "**still code**");
+    }
+}
@@ -0,0 +1,116 @@
+use crate::gui::Gui;
+
+/// Where Tab last completed a name in the input box, so pressing it again can swap in the next one
+pub struct Completion {
+    start: usize,       // byte index of the @
+    names: Vec<String>, // everyone whose name starts with what was typed, in order
+    index: usize,
+    inserted: String, // to tell if the input box has been changed since
+}
+
+fn is_name_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '-'
+}
+
+/// Whether `content` mentions `name` with an @, in any case, as long as it isn't just
+/// the start of a longer name or part of an email address
+pub fn mentions(content: &str, name: &str) -> bool {
+    if name.is_empty() {
+        return false;
+    }
+    let (content, mention) = (content.to_lowercase(), format!("@{}", name.to_lowercase()));
+    content.match_indices(&mention).any(|(idx, _)| {
+        let before = content[..idx].chars().next_back();
+        let after = content[idx + mention.len()..].chars().next();
+        !before.is_some_and(is_name_char) && !after.is_some_and(is_name_char)
+    })
+}
+
+impl Gui {
+    /// Complete the @name before the cursor from the people in the current server, or if one was
+    /// just completed, swap it for the next. Returns false if there's nothing to complete.
+    pub fn complete_mention(&mut self) -> bool {
+        if let Some(ref mut completion) = self.completion {
+            let end = completion.start + completion.inserted.len();
+            let unchanged = self.buffer.edit_position == end
+                && self.buffer.data.get(completion.start..end) == Some(&completion.inserted);
+            if unchanged {
+                completion.index = (completion.index + 1) % completion.names.len();
+                let text = format!("@{} ", completion.names[completion.index]);
+                self.buffer.data.replace_range(completion.start..end, &text);
+                self.buffer.edit_position = completion.start + text.len();
+                completion.inserted = text;
+                return true;
+            }
+        }
+        self.completion = None;
+
+        let before = &self.buffer.data[..self.buffer.edit_position];
+        let Some(start) = before.rfind('@') else {
+            return false;
+        };
+        let typed = before[start + 1..].to_lowercase();
+        let starts_word = !before[..start]
+            .chars()
+            .next_back()
+            .is_some_and(is_name_char);
+        if !starts_word || typed.contains(char::is_whitespace) {
+            return false;
+        }
+        let Some(Ok(net)) = self.curr_server.map(|idx| &self.servers[idx].network) else {
+            return false;
+        };
+        let mut names: Vec<String> = net
+            .peers
+            .values()
+            .filter(|peer| peer.name.to_lowercase().starts_with(&typed))
+            .map(|peer| peer.name.clone())
+            .collect();
+        if names.is_empty() {
+            return false;
+        }
+        names.sort_by_key(|name| name.to_lowercase());
+        names.dedup();
+
+        let text = format!("@{} ", names[0]);
+        let end = self.buffer.edit_position;
+        self.buffer.data.replace_range(start..end, &text);
+        self.buffer.edit_position = start + text.len();
+        self.completion = Some(Completion {
+            start,
+            names,
+            index: 0,
+            inserted: text,
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_in_any_case() {
+        assert!(mentions("hi @Alice", "alice"));
+        assert!(mentions("HI @ALICE!", "Alice"));
+        assert!(mentions("@Ärger, look", "ärger"));
+    }
+
+    #[test]
+    fn mentions_only_whole_names() {
+        assert!(mentions("@alice: hi", "alice"));
+        assert!(mentions("(@alice)", "alice"));
+        assert!(!mentions("hi @alice_b", "alice"));
+        assert!(!mentions("hi @alice-b", "alice"));
+        assert!(!mentions("hi @alices", "alice"));
+        assert!(!mentions("mail alice@alice.com", "alice"));
+        assert!(!mentions("hi alice", "alice"));
+        assert!(mentions("mail me@x then @alice", "alice"));
+    }
+
+    #[test]
+    fn empty_name_mentions_nothing() {
+        assert!(!mentions("@ hello", ""));
+    }
+}
@@ -1,5 +1,7 @@
 use crate::drawing::Theme;
+use crate::unicode;
 use termion::event::{Event, Key};
+use unicode_segmentation::UnicodeSegmentation;
 
 enum Selection {
     Field(usize),
@@ -8,7 +10,7 @@ enum Selection {
 
 pub struct EditBuffer {
     pub data: String,
-    pub edit_position: usize,
+    pub edit_position: usize, // in bytes, always at the start of a grapheme
 }
 
 impl EditBuffer {
@@ -19,48 +21,121 @@ impl EditBuffer {
         }
     }
     pub fn push(&mut self, c: char) {
-        let i = if self.edit_position == 0 {
-            0
-        } else if self.edit_position + 1 < self.data.chars().count() {
-            self.data
-                .char_indices()
-                .nth(self.edit_position)
-                .unwrap()
-                .0 // edit position should always be in range...
-        } else {
-            self.data.bytes().len()
-        };
-
-        self.data.insert(i, c);
-        self.edit_position += 1;
+        self.data.insert(self.edit_position, c);
+        self.edit_position += c.len_utf8();
     }
     pub fn pop(&mut self) {
-        if self.edit_position > 0 {
-            let (i, _) = self
-                .data
-                .char_indices()
-                .nth(self.edit_position - 1)
-                .unwrap(); // edit position should always be in range...
-            self.data.remove(i);
-            self.edit_position -= 1;
+        if let Some((i, _)) = self.data[..self.edit_position]
+            .grapheme_indices(true)
+            .next_back()
+        {
+            self.data.replace_range(i..self.edit_position, "");
+            self.edit_position = i;
         }
     }
 
+    /// Delete back to the start of the word before the cursor, along with any whitespace after it.
+    /// If that leaves only whitespace on the line, that goes too.
     pub fn pop_word(&mut self) {
-        self.pop();
+        let mut start = self.edit_position;
+        let mut in_word = false;
+        for (i, grapheme) in self.data[..self.edit_position].grapheme_indices(true).rev() {
+            let space = grapheme.chars().all(char::is_whitespace);
+            if space && in_word {
+                break;
+            }
+            in_word |= !space;
+            start = i;
+        }
+        let line_start = self.data[..start].rfind('\n').map_or(0, |i| i + 1);
+        if self.data[line_start..start].trim().is_empty() {
+            start = line_start;
+        }
+        self.data.replace_range(start..self.edit_position, "");
+        self.edit_position = start;
     }
     
     pub fn left(&mut self) {
-        if self.edit_position > 0 {
-            self.edit_position -= 1;
+        if let Some((i, _)) = self.data[..self.edit_position]
+            .grapheme_indices(true)
+            .next_back()
+        {
+            self.edit_position = i;
         }
     }
 
     pub fn right(&mut self) {
-        if self.edit_position < self.data.len() {
-            self.edit_position += 1;
+        if let Some(grapheme) = self.data[self.edit_position..].graphemes(true).next() {
+            self.edit_position += grapheme.len();
         }
     }
+
+    /// Move the cursor to the row above, as near to the same column as it can.
+    /// Returns false if it's already on the first row.
+    pub fn up(&mut self, width: usize) -> bool {
+        self.move_row(width, false)
+    }
+
+    /// Move the cursor to the row below, see `up`
+    pub fn down(&mut self, width: usize) -> bool {
+        self.move_row(width, true)
+    }
+
+    fn move_row(&mut self, width: usize, down: bool) -> bool {
+        let rows = self.rows(width);
+        let row = rows
+            .iter()
+            .rposition(|(start, _)| *start <= self.edit_position)
+            .unwrap_or(0);
+        let col = unicode::width(&self.data[rows[row].0..self.edit_position]);
+        let target = if down { row + 1 } else { row.wrapping_sub(1) };
+        let Some((start, text)) = rows.get(target) else {
+            return false;
+        };
+        let mut position = *start;
+        let mut target_col = 0;
+        for grapheme in text.graphemes(true) {
+            target_col += unicode::grapheme_width(grapheme);
+            if target_col > col {
+                break;
+            }
+            position += grapheme.len();
+        }
+        self.edit_position = position;
+        true
+    }
+
+    /// Rows of at most `width` columns that the buffer is drawn in, see `wrap_rows`
+    pub fn rows(&self, width: usize) -> Vec<(usize, String)> {
+        wrap_rows(&self.data, width)
+    }
+
+    /// Row and column of the cursor when drawn `width` columns wide.
+    /// A cursor just past the end of a full row goes at the start of the next one.
+    pub fn cursor(&self, width: usize) -> (usize, usize) {
+        let rows = self.rows(width);
+        let row = rows
+            .iter()
+            .rposition(|(start, _)| *start <= self.edit_position)
+            .unwrap_or(0);
+        let col = unicode::width(&self.data[rows[row].0..self.edit_position]);
+        if col >= width {
+            (row + 1, 0)
+        } else {
+            (row, col)
+        }
+    }
+
+    /// How many rows are needed to draw the buffer `width` columns wide,
+    /// leaving room for the cursor after the last character
+    pub fn height(&self, width: usize) -> usize {
+        let rows = self.rows(width);
+        let last_full = rows
+            .last()
+            .map(|(_, text)| unicode::width(text) >= width)
+            .unwrap_or(false);
+        rows.len() + last_full as usize
+    }
 }
 
 pub struct Prompt {
@@ -68,7 +143,9 @@ pub struct Prompt {
     fields: Vec<PromptField>,
     buttons: Vec<&'static str>,
     buffers: Vec<EditBuffer>,
+    errors: Vec<Option<String>>,
     selected: Selection,
+    revealed: bool, // whether the selected password field is shown in clear text
 }
 
 pub enum PromptEvent {
@@ -79,6 +156,55 @@ pub enum PromptEvent {
 pub enum FieldError {
     WrongType,
     NoSuchField,
+    Invalid,
+}
+
+/// Split the contents of a field into display rows of at most `width` columns,
+/// returning the byte index each row starts at along with its text. Rows are broken between
+/// words where they can be, and anywhere in words too long to fit on a row of their own.
+fn wrap_rows(data: &str, width: usize) -> Vec<(usize, String)> {
+    let mut rows = vec![(0, String::new())];
+    let mut col = 0;
+    // where the last row could be broken: just after its last space, if there's been
+    // anything other than spaces before it, so that indentation isn't left on its own
+    let mut split = None;
+    let mut seen_text = false;
+    for (i, grapheme) in data.grapheme_indices(true) {
+        if grapheme == "\n" || grapheme == "\r\n" {
+            rows.push((i + grapheme.len(), String::new()));
+            (col, split, seen_text) = (0, None, false);
+            continue;
+        }
+        let grapheme_width = unicode::grapheme_width(grapheme);
+        let space = grapheme.chars().all(char::is_whitespace);
+        if col > 0 && col + grapheme_width > width {
+            if space {
+                // it would only be at the end of the row, so the next row starts after it
+                rows.push((i + grapheme.len(), String::new()));
+                (col, split, seen_text) = (0, None, false);
+                continue;
+            }
+            if let Some(at) = split.take() {
+                let row = rows.last_mut().unwrap(); // unwrap: we start with one row
+                let word = row.1.split_off(at - row.0);
+                col = unicode::width(&word);
+                seen_text = !word.is_empty();
+                rows.push((at, word));
+            }
+            if col > 0 && col + grapheme_width > width {
+                rows.push((i, String::new()));
+                (col, seen_text) = (0, false);
+            }
+        }
+        if !space {
+            seen_text = true;
+        } else if seen_text {
+            split = Some(i + grapheme.len());
+        }
+        rows.last_mut().unwrap().1.push_str(grapheme); // unwrap: we start with one row
+        col += grapheme_width;
+    }
+    rows
 }
 
 impl Prompt {
@@ -87,16 +213,20 @@ impl Prompt {
             .iter()
             .map(|field| EditBuffer::new(field.default_string()))
             .collect();
+        let errors = fields.iter().map(|_| None).collect();
         Self {
             name,
             fields,
             buttons,
             buffers,
+            errors,
             selected: Selection::Field(0),
+            revealed: false,
         }
     }
 
     fn increment_selection(&mut self) {
+        self.revealed = false;
         match self.selected {
             Selection::Field(idx) => {
                 if idx + 1 < self.fields.len() {
@@ -113,6 +243,7 @@ impl Prompt {
         }
     }
     fn decrement_selection(&mut self) {
+        self.revealed = false;
         match self.selected {
             Selection::Button(idx) => {
                 if idx > 0 {
@@ -129,50 +260,169 @@ impl Prompt {
         }
     }
 
-    fn validate_field(&self, idx: usize) -> bool {
+    /// Check for input that can never become valid by typing more, e.g. letters in a number.
+    /// Keystrokes that fail this check are rejected outright.
+    fn check_input(&self, idx: usize) -> Result<(), String> {
+        let data = &self.buffers[idx].data;
+        match self.fields[idx] {
+            PromptField::String { max_width, .. } | PromptField::Password { max_width, .. } => {
+                if unicode::width(data) > max_width {
+                    return Err(format!("Must be at most {} characters wide", max_width));
+                }
+            }
+
+            PromptField::U16 { .. } => {
+                if !data.chars().all(|c| c.is_ascii_digit()) {
+                    return Err("Must be a whole number".to_owned());
+                }
+                if !data.is_empty() && data.parse::<u16>().is_err() {
+                    return Err(format!("Must be between 0 and {}", u16::MAX));
+                }
+            }
+            PromptField::I64 { .. } => {
+                let digits = data.strip_prefix('-').unwrap_or(data);
+                if !digits.chars().all(|c| c.is_ascii_digit()) {
+                    return Err("Must be a whole number".to_owned());
+                }
+                if !digits.is_empty() && data.parse::<i64>().is_err() {
+                    return Err("Number is too large".to_owned());
+                }
+            }
+            PromptField::MultiLine { .. }
+            | PromptField::Bool { .. }
+            | PromptField::Choice { .. } => (),
+        }
+        Ok(())
+    }
+
+    /// Check that the field holds a complete, usable value
+    fn validate_field(&self, idx: usize) -> Result<(), String> {
+        self.check_input(idx)?;
         let data = &self.buffers[idx].data;
-        let max_width = 48; // just so it isn't fucking stupid
-                            // TODO make this variable
         match self.fields[idx] {
-            PromptField::String { .. } => data.len() < max_width,
-            PromptField::U16 { .. } => data.parse::<u16>().is_ok() && data.len() < max_width,
-            // PromptField::I64 { .. } => data.parse::<i64>().is_ok(),
+            PromptField::U16 { name, .. } | PromptField::I64 { name, .. } if data.is_empty() => {
+                Err(format!("{} is required", name))
+            }
+            PromptField::I64 { .. } if data.parse::<i64>().is_err() => {
+                Err("Must be a whole number".to_owned())
+            }
+            PromptField::Choice { ref options, .. } if !options.contains(data) => {
+                Err("Pick one of the options".to_owned())
+            }
+            _ => Ok(()),
         }
     }
 
+    /// Validate every field, showing a message under each one that is invalid.
+    /// Returns true if all of the fields can be read.
+    pub fn validate(&mut self) -> bool {
+        for idx in 0..self.fields.len() {
+            self.errors[idx] = self.validate_field(idx).err();
+        }
+        self.errors.iter().all(|e| e.is_none())
+    }
+
+    /// Show (or clear) a message under a field, for checks the prompt can't do itself
+    pub fn set_error(&mut self, key: &str, error: Option<String>) {
+        if let Some(idx) = self.index_from_str(key) {
+            self.errors[idx] = error;
+        }
+    }
+
+    fn cycle_choice(&mut self, idx: usize, forwards: bool) {
+        let PromptField::Choice { ref options, .. } = self.fields[idx] else {
+            return;
+        };
+        if options.is_empty() {
+            return;
+        }
+        let curr = options
+            .iter()
+            .position(|opt| *opt == self.buffers[idx].data)
+            .unwrap_or(0);
+        let next = if forwards {
+            (curr + 1) % options.len()
+        } else {
+            (curr + options.len() - 1) % options.len()
+        };
+        self.buffers[idx] = EditBuffer::new(options[next].clone());
+    }
+
+    fn toggle_bool(&mut self, idx: usize) {
+        let checked = self.buffers[idx].data == "true";
+        self.buffers[idx] = EditBuffer::new((!checked).to_string());
+    }
+
     pub fn handle_event(&mut self, event: Event) -> Option<PromptEvent> {
         match event.clone() {
             Event::Key(Key::Char('\n')) => match self.selected {
                 Selection::Button(idx) => {
                     return Some(PromptEvent::ButtonPressed(self.buttons[idx]))
                 }
-                Selection::Field(_) => self.increment_selection(),
+                Selection::Field(idx) => {
+                    if let PromptField::MultiLine { .. } = self.fields[idx] {
+                        self.type_char(idx, '\n');
+                    } else {
+                        self.increment_selection();
+                    }
+                }
             },
-            Event::Key(Key::Down) | Event::Key(Key::Char('\t')) => self.increment_selection(),
-            Event::Key(Key::Up) | Event::Key(Key::BackTab) => self.decrement_selection(),
+            Event::Key(Key::Down) => {
+                let moved = self.move_row(true);
+                if !moved {
+                    self.increment_selection();
+                }
+            }
+            Event::Key(Key::Up) => {
+                let moved = self.move_row(false);
+                if !moved {
+                    self.decrement_selection();
+                }
+            }
+            Event::Key(Key::Char('\t')) => self.increment_selection(),
+            Event::Key(Key::BackTab) => self.decrement_selection(),
 
             Event::Key(Key::Right) => match self.selected {
-                Selection::Field(idx) => self.buffers[idx].right(),
+                Selection::Field(idx) => match self.fields[idx] {
+                    PromptField::Choice { .. } => self.cycle_choice(idx, true),
+                    PromptField::Bool { .. } => (),
+                    _ => self.buffers[idx].right(),
+                },
                 Selection::Button(_) => self.increment_selection(),
             },
             Event::Key(Key::Left) => match self.selected {
-                Selection::Field(idx) => self.buffers[idx].left(),
+                Selection::Field(idx) => match self.fields[idx] {
+                    PromptField::Choice { .. } => self.cycle_choice(idx, false),
+                    PromptField::Bool { .. } => (),
+                    _ => self.buffers[idx].left(),
+                },
                 Selection::Button(_) => self.decrement_selection(),
             },
 
+            Event::Key(Key::Ctrl('r')) => {
+                if let Selection::Field(idx) = self.selected {
+                    if let PromptField::Password { .. } = self.fields[idx] {
+                        self.revealed = !self.revealed;
+                    }
+                }
+            }
+
             Event::Key(Key::Backspace) => {
                 if let Selection::Field(idx) = self.selected {
-                    self.buffers[idx].pop();
+                    if self.fields[idx].is_text() {
+                        self.buffers[idx].pop();
+                        self.errors[idx] = self.check_input(idx).err();
+                    }
                 }
             }
 
             Event::Key(Key::Char(c)) => {
                 if let Selection::Field(idx) = self.selected {
-                    self.buffers[idx].push(c);
-
-                    // if the user inputted something wrong, it's best to check after adding and just remove the lsat char if necessary
-                    if !self.validate_field(idx) {
-                        self.buffers[idx].pop();
+                    match self.fields[idx] {
+                        PromptField::Bool { .. } if c == ' ' => self.toggle_bool(idx),
+                        PromptField::Choice { .. } if c == ' ' => self.cycle_choice(idx, true),
+                        PromptField::Bool { .. } | PromptField::Choice { .. } => (),
+                        _ => self.type_char(idx, c),
                     }
                 }
             }
@@ -182,105 +432,224 @@ impl Prompt {
         None
     }
 
+    /// Move between the rows of a multi-line field, so that up and down only leave it from the
+    /// first and last rows. Returns false if the cursor didn't move.
+    fn move_row(&mut self, down: bool) -> bool {
+        let Selection::Field(idx) = self.selected else {
+            return false;
+        };
+        let PromptField::MultiLine { max_width, .. } = self.fields[idx] else {
+            return false;
+        };
+        if down {
+            self.buffers[idx].down(max_width)
+        } else {
+            self.buffers[idx].up(max_width)
+        }
+    }
+
+    fn type_char(&mut self, idx: usize, c: char) {
+        self.buffers[idx].push(c);
+
+        // if the user inputted something wrong, it's best to check after adding and just remove the lsat char if necessary
+        self.errors[idx] = self.check_input(idx).err();
+        if self.errors[idx].is_some() {
+            self.buffers[idx].pop();
+        }
+    }
+
+    /// Height of the whole prompt, including space for any validation messages.
+    /// This doesn't change as messages come and go, so the prompt can be positioned once.
     pub fn height(&self) -> u16 {
-        self.fields.len() as u16 + 2 // + 1 for buttons, + 1 for title
+        self.fields
+            .iter()
+            .map(|f| f.rows() + f.message_rows())
+            .sum::<u16>()
+            + 2 // + 1 for buttons, + 1 for title
     }
 
-    pub fn draw<W: std::io::Write>(&self, screen: &mut W, x: u16, y: u16, theme: &Theme) {
-        let align = self
-            .fields
+    fn align(&self) -> usize {
+        self.fields
             .iter()
             .map(|field| field.name().len())
             .max()
-            .unwrap(); // unwrap: we must have at least one field
+            .unwrap() // unwrap: we must have at least one field
+    }
+
+    pub fn width(&self) -> u16 {
+        let fields_width = self.align()
+            + 2
+            + self
+                .fields
+                .iter()
+                .map(|field| field.value_width())
+                .max()
+                .unwrap_or(0);
+        let buttons_width = self.buttons.iter().map(|b| b.len() + 3).sum::<usize>();
+        fields_width.max(buttons_width).max(self.name.len()) as u16
+    }
+
+    /// The text shown for a field's value, one string per row
+    fn display_rows(&self, idx: usize) -> Vec<String> {
+        let data = &self.buffers[idx].data;
+        match self.fields[idx] {
+            PromptField::Password { .. } => {
+                let revealed =
+                    self.revealed && matches!(self.selected, Selection::Field(i) if i == idx);
+                if revealed {
+                    vec![data.clone()]
+                } else {
+                    vec!["*".repeat(data.graphemes(true).count())]
+                }
+            }
+            PromptField::Bool { .. } => {
+                vec![if data == "true" { "[x]" } else { "[ ]" }.to_owned()]
+            }
+            PromptField::Choice { .. } => vec![format!("< {} >", data)],
+            PromptField::MultiLine {
+                max_width, height, ..
+            } => {
+                // scrolled so that the cursor's row is showing
+                let (cursor_row, _) = self.buffers[idx].cursor(max_width);
+                let first = cursor_row.saturating_sub(height.saturating_sub(1) as usize);
+                self.buffers[idx]
+                    .rows(max_width)
+                    .into_iter()
+                    .map(|(_, text)| text)
+                    .chain(std::iter::repeat(String::new()))
+                    .skip(first)
+                    .take(height as usize)
+                    .collect()
+            }
+            PromptField::String { .. } | PromptField::U16 { .. } | PromptField::I64 { .. } => {
+                vec![data.clone()]
+            }
+        }
+    }
+
+    /// Column of the cursor within a single line field
+    fn cursor_col(&self, idx: usize) -> usize {
+        let buffer = &self.buffers[idx];
+        let before = &buffer.data[..buffer.edit_position];
+        let revealed = self.revealed && matches!(self.selected, Selection::Field(i) if i == idx);
+        match self.fields[idx] {
+            PromptField::Password { .. } if !revealed => before.graphemes(true).count(),
+            _ => unicode::width(before),
+        }
+    }
+
+    pub fn draw<W: std::io::Write>(&self, screen: &mut W, x: u16, y: u16, theme: &Theme) {
+        let align = self.align();
+        let value_x = x + align as u16 + 2;
+        let value_width = self.width() as usize - align - 2;
 
         // draw the title at the top
         write!(screen, "{}{}", termion::cursor::Goto(x, y), self.name).unwrap();
 
         // draw each field, along with its associated buffer`
-        let mut idx = 0;
-        for (field, buffer) in std::iter::zip(self.fields.iter(), self.buffers.iter()) {
-            // calculate the selected index
-            let idx2 = if let Selection::Field(idx2) = self.selected {
-                idx2
-            } else {
-                usize::MAX // I hope this will never equal idx
-            };
+        let mut row = y + 1;
+        let mut cursor = termion::cursor::Goto(1, 1);
+        for (idx, field) in self.fields.iter().enumerate() {
+            let selected = matches!(self.selected, Selection::Field(i) if i == idx);
 
             // if our index is the selected index, draw with colour
-            if idx2 == idx {
+            if selected {
                 write!(
                     screen,
-                    "{}{}{}{}{}{}: {} ",
-                    termion::cursor::Goto(x, y + idx as u16 + 1),
+                    "{}{}{}{}{}: ",
+                    termion::cursor::Goto(x, row),
                     theme.servers.selected_text,
                     field.name(),
                     " ".repeat(align - field.name().len()),
-                    termion::color::Fg(termion::color::Reset),
-                    termion::color::Bg(termion::color::Reset),
-                    buffer.data,
+                    termion::style::Reset,
                 )
                 .unwrap();
             } else {
                 write!(
                     screen,
-                    "{}{}{}: {} ",
-                    termion::cursor::Goto(x, y + idx as u16 + 1),
+                    "{}{}{}: ",
+                    termion::cursor::Goto(x, row),
                     field.name(),
                     " ".repeat(align - field.name().len()),
-                    buffer.data,
                 )
                 .unwrap();
             }
-            idx += 1;
+
+            for (i, text) in self.display_rows(idx).iter().enumerate() {
+                // pad with spaces to make sure nothing is left behind from the last draw
+                write!(
+                    screen,
+                    "{}{}{}",
+                    termion::cursor::Goto(value_x, row + i as u16),
+                    text,
+                    " ".repeat(value_width.saturating_sub(unicode::width(text))),
+                )
+                .unwrap();
+            }
+
+            // Work out where the cursor goes if this field is selected
+            if selected {
+                cursor = match field {
+                    PromptField::MultiLine {
+                        max_width, height, ..
+                    } => {
+                        let (cursor_row, cursor_col) = self.buffers[idx].cursor(*max_width);
+                        let visible_row = cursor_row.min(height.saturating_sub(1) as usize);
+                        termion::cursor::Goto(value_x + cursor_col as u16, row + visible_row as u16)
+                    }
+                    PromptField::Bool { .. } => termion::cursor::Goto(value_x + 1, row),
+                    PromptField::Choice { .. } => termion::cursor::Goto(value_x, row),
+                    _ => termion::cursor::Goto(value_x + self.cursor_col(idx) as u16, row),
+                };
+            }
+            row += field.rows();
+
+            if field.message_rows() > 0 {
+                let hint = if selected && matches!(field, PromptField::Password { .. }) {
+                    "Ctrl+R to show/hide"
+                } else {
+                    ""
+                };
+                let (colour, message) = match self.errors[idx] {
+                    Some(ref err) => (&theme.servers.error_text, err.as_str()),
+                    None => (&theme.servers.text, hint),
+                };
+                write!(
+                    screen,
+                    "{}{}{}{}{}",
+                    termion::cursor::Goto(value_x, row),
+                    colour,
+                    message,
+                    termion::style::Reset,
+                    " ".repeat(value_width.saturating_sub(unicode::width(message))),
+                )
+                .unwrap();
+                row += 1;
+            }
         }
 
         // move cursor to draw the buttons all on one line
-        write!(
-            screen,
-            "{}",
-            termion::cursor::Goto(x, y + self.fields.len() as u16 + 1)
-        )
-        .unwrap();
+        write!(screen, "{}", termion::cursor::Goto(x, row)).unwrap();
 
         // similar code for drawing the buttons
-        idx = 0;
-        for button in &self.buttons {
-            let idx2 = if let Selection::Button(idx2) = self.selected {
-                idx2
-            } else {
-                usize::MAX // I hope this will never equal idx
-            };
-            if idx2 == idx {
+        for (idx, button) in self.buttons.iter().enumerate() {
+            if matches!(self.selected, Selection::Button(i) if i == idx) {
                 write!(
                     screen,
-                    "[{}{}{}{}] ",
+                    "[{}{}{}] ",
                     theme.servers.selected_text,
                     button,
-                    termion::color::Fg(termion::color::Reset),
-                    termion::color::Bg(termion::color::Reset),
+                    termion::style::Reset,
                 )
                 .unwrap();
             } else {
                 write!(screen, "[{}] ", button).unwrap();
             }
-            idx += 1;
         }
 
         // Move the cursor to the edit position of the selected buffer,
         // or else to some arbitrary location if none is selected
-        write!(
-            screen,
-            "{}",
-            if let Selection::Field(idx) = self.selected {
-                let sel_x = x + align as u16 + 2 + self.buffers[idx].edit_position as u16;
-                let sel_y = y + idx as u16 + 1;
-                termion::cursor::Goto(sel_x, sel_y)
-            } else {
-                termion::cursor::Goto(1, 1)
-            }
-        )
-        .unwrap();
+        write!(screen, "{}", cursor).unwrap();
     }
 
     fn index_from_str(&self, val: &str) -> Option<usize> {
@@ -289,58 +658,335 @@ impl Prompt {
 
     pub fn get_str(&self, key: &str) -> Result<&str, FieldError> {
         let idx = self.index_from_str(key).ok_or(FieldError::NoSuchField)?;
-        if let PromptField::String { .. } = self.fields[idx] {
+        match self.fields[idx] {
+            PromptField::String { .. }
+            | PromptField::Password { .. }
+            | PromptField::MultiLine { .. } => Ok(&self.buffers[idx].data),
+            _ => Err(FieldError::WrongType),
+        }
+    }
+    pub fn get_u16(&self, key: &str) -> Result<u16, FieldError> {
+        let idx = self.index_from_str(key).ok_or(FieldError::NoSuchField)?;
+        if let PromptField::U16 { .. } = self.fields[idx] {
+            self.buffers[idx]
+                .data
+                .parse()
+                .map_err(|_| FieldError::Invalid)
+        } else {
+            Err(FieldError::WrongType)
+        }
+    }
+    pub fn get_i64(&self, key: &str) -> Result<i64, FieldError> {
+        let idx = self.index_from_str(key).ok_or(FieldError::NoSuchField)?;
+        if let PromptField::I64 { .. } = self.fields[idx] {
+            self.buffers[idx]
+                .data
+                .parse()
+                .map_err(|_| FieldError::Invalid)
+        } else {
+            Err(FieldError::WrongType)
+        }
+    }
+    pub fn get_bool(&self, key: &str) -> Result<bool, FieldError> {
+        let idx = self.index_from_str(key).ok_or(FieldError::NoSuchField)?;
+        if let PromptField::Bool { .. } = self.fields[idx] {
+            Ok(self.buffers[idx].data == "true")
+        } else {
+            Err(FieldError::WrongType)
+        }
+    }
+    pub fn get_choice(&self, key: &str) -> Result<&str, FieldError> {
+        let idx = self.index_from_str(key).ok_or(FieldError::NoSuchField)?;
+        if let PromptField::Choice { .. } = self.fields[idx] {
             Ok(&self.buffers[idx].data)
         } else {
             Err(FieldError::WrongType)
         }
     }
-    pub fn get_u16(&self, key: &str) -> Result<u16, FieldError> {
+    /// Which of the options is chosen, counting from 0
+    pub fn get_choice_index(&self, key: &str) -> Result<usize, FieldError> {
         let idx = self.index_from_str(key).ok_or(FieldError::NoSuchField)?;
-        if let PromptField::U16 { .. } = self.fields[idx] {
-            Ok(self.buffers[idx].data.parse().unwrap()) // This unwrap **SHOULD** be okay, as we should have prevented anything invalid from being entered in the first place
+        if let PromptField::Choice { ref options, .. } = self.fields[idx] {
+            options
+                .iter()
+                .position(|option| *option == self.buffers[idx].data)
+                .ok_or(FieldError::Invalid)
         } else {
             Err(FieldError::WrongType)
         }
     }
-    // pub fn get_i64(&self, key: &str) -> Result<i64, FieldError> {
-    //     let idx = self.index_from_str(key).ok_or(FieldError::NoSuchField)?;
-    //     if let PromptField::I64 { .. } = self.fields[idx] {
-    //         Ok(self.buffers[idx].data.parse().unwrap()) // Likewise
-    //     } else {
-    //         Err(FieldError::WrongType)
-    //     }
-    // }
 }
 
 pub enum PromptField {
     String {
         name: &'static str,
         default: Option<String>,
+        max_width: usize,
+    },
+    /// Like `String`, but shown as asterisks unless revealed with ctrl+r
+    Password {
+        name: &'static str,
+        default: Option<String>,
+        max_width: usize,
+    },
+    /// Free text that wraps at `max_width` and shows `height` rows at a time.
+    /// Enter inserts a newline rather than moving to the next field.
+    #[allow(dead_code)] // no prompt needs one yet
+    MultiLine {
+        name: &'static str,
+        default: Option<String>,
+        max_width: usize,
+        height: u16,
     },
     U16 {
         name: &'static str,
         default: Option<u16>,
     },
-    // I64 {
-    //     name: &'static str,
-    //     default: Option<i64>,
-    // },
+    I64 {
+        name: &'static str,
+        default: Option<i64>,
+    },
+    /// A checkbox, toggled with space
+    Bool { name: &'static str, default: bool },
+    /// One of a fixed set of options, cycled with left/right or space
+    Choice {
+        name: &'static str,
+        options: Vec<String>,
+        default: usize,
+    },
 }
 
 impl PromptField {
     #[rustfmt::skip]
     fn default_string(&self) -> String {
         match self {
-            Self::String { default: Some(d), .. } => d.clone(),
+            Self::String { default: Some(d), .. }
+            | Self::Password { default: Some(d), .. }
+            | Self::MultiLine { default: Some(d), .. } => d.clone(),
             Self::U16 { default: Some(d), .. } => d.to_string(),
-            // Self::I64 { default: Some(d), .. } => d.to_string(),
+            Self::I64 { default: Some(d), .. } => d.to_string(),
+            Self::Bool { default, .. } => default.to_string(),
+            Self::Choice { options, default, .. } => options.get(*default).cloned().unwrap_or_default(),
             _ => "".to_owned(),
         }
     }
+    #[rustfmt::skip]
     fn name(&self) -> &str {
         match self {
-            Self::String { name, .. } | Self::U16 { name, .. } /* | Self::I64 { name, .. } */ => name,
+            Self::String { name, .. } | Self::Password { name, .. } | Self::MultiLine { name, .. }
+            | Self::U16 { name, .. } | Self::I64 { name, .. }
+            | Self::Bool { name, .. } | Self::Choice { name, .. } => name,
+        }
+    }
+
+    /// Whether the field is edited by typing into its buffer
+    fn is_text(&self) -> bool {
+        !matches!(self, Self::Bool { .. } | Self::Choice { .. })
+    }
+
+    /// Number of rows the value takes up on screen
+    fn rows(&self) -> u16 {
+        match self {
+            Self::MultiLine { height, .. } => *height,
+            _ => 1,
+        }
+    }
+
+    /// Number of rows reserved underneath the value for validation messages
+    fn message_rows(&self) -> u16 {
+        match self {
+            Self::Bool { .. } | Self::Choice { .. } => 0,
+            _ => 1,
+        }
+    }
+
+    /// Number of columns needed to draw the value, plus one for the cursor
+    fn value_width(&self) -> usize {
+        let width = match self {
+            Self::String { max_width, .. }
+            | Self::Password { max_width, .. }
+            | Self::MultiLine { max_width, .. } => *max_width,
+            Self::U16 { .. } => 5,
+            Self::I64 { .. } => 20,
+            Self::Bool { .. } => 3,
+            Self::Choice { options, .. } => {
+                options.iter().map(|o| unicode::width(o)).max().unwrap_or(0) + 4
+            }
+        };
+        // validation messages need to fit too
+        width.max(30) + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(field: PromptField) -> Prompt {
+        Prompt::new("Test", vec![field], vec!["OK"])
+    }
+
+    /// Type `text` into the only field, returning the error under it, if any
+    fn typed(field: PromptField, text: &str) -> (String, Option<String>) {
+        let mut prompt = prompt(field);
+        for ch in text.chars() {
+            prompt.handle_event(Event::Key(Key::Char(ch)));
+        }
+        (prompt.buffers[0].data.clone(), prompt.errors[0].clone())
+    }
+
+    fn u16_field(default: Option<u16>) -> PromptField {
+        PromptField::U16 {
+            name: "Port",
+            default,
+        }
+    }
+
+    #[test]
+    fn numbers_are_required() {
+        let mut empty = prompt(u16_field(None));
+        assert!(!empty.validate());
+        assert_eq!(empty.errors[0].as_deref(), Some("Port is required"));
+        let mut empty = prompt(PromptField::I64 {
+            name: "Width",
+            default: None,
+        });
+        assert!(!empty.validate());
+        assert_eq!(empty.errors[0].as_deref(), Some("Width is required"));
+        assert!(prompt(u16_field(Some(2345))).validate());
+    }
+
+    #[test]
+    fn bad_integers_are_rejected_as_typed() {
+        let (data, error) = typed(u16_field(None), "12a");
+        assert_eq!(data, "12");
+        assert_eq!(error.as_deref(), Some("Must be a whole number"));
+        let i64_field = || PromptField::I64 {
+            name: "Width",
+            default: None,
+        };
+        assert_eq!(typed(i64_field(), "-42"), ("-42".to_owned(), None));
+        assert_eq!(typed(i64_field(), "4-2").0, "42");
+
+        // a lone minus sign can still be finished off, but isn't a number yet
+        let mut minus = prompt(i64_field());
+        minus.handle_event(Event::Key(Key::Char('-')));
+        assert_eq!(minus.errors[0], None);
+        assert!(!minus.validate());
+        assert_eq!(minus.errors[0].as_deref(), Some("Must be a whole number"));
+    }
+
+    #[test]
+    fn u16_out_of_range() {
+        let (data, error) = typed(u16_field(None), "65536");
+        assert_eq!(data, "6553");
+        assert_eq!(error, Some(format!("Must be between 0 and {}", u16::MAX)));
+        assert_eq!(typed(u16_field(None), "65535"), ("65535".to_owned(), None));
+    }
+
+    #[test]
+    fn choice_must_be_an_option() {
+        let choice = |options: &[&str]| PromptField::Choice {
+            name: "Theme",
+            options: options.iter().map(|o| o.to_string()).collect(),
+            default: 0,
+        };
+        assert!(prompt(choice(&["a", "b"])).validate());
+        let mut none = prompt(choice(&[]));
+        assert!(!none.validate());
+        assert_eq!(none.errors[0].as_deref(), Some("Pick one of the options"));
+        let mut changed = prompt(choice(&["a", "b"]));
+        changed.buffers[0] = EditBuffer::new("c".into());
+        assert!(!changed.validate());
+        assert_eq!(changed.get_choice_index("Theme").ok(), None);
+    }
+
+    #[test]
+    fn multi_line_fields() {
+        let mut prompt = prompt(PromptField::MultiLine {
+            name: "Bio",
+            default: None,
+            max_width: 10,
+            height: 3,
+        });
+        for ch in "ab\ncdef".chars() {
+            prompt.handle_event(Event::Key(Key::Char(ch)));
         }
+        assert_eq!(prompt.get_str("Bio").unwrap(), "ab\ncdef");
+        assert_eq!(prompt.height(), 3 + 1 + 2);
+        assert_eq!(prompt.display_rows(0), ["ab", "cdef", ""]);
+
+        // up moves within the field, to the nearest column, until it's on the first row
+        prompt.handle_event(Event::Key(Key::Up));
+        assert_eq!(prompt.buffers[0].edit_position, 2);
+        prompt.handle_event(Event::Key(Key::Char('X')));
+        assert_eq!(prompt.buffers[0].data, "abX\ncdef");
+        prompt.handle_event(Event::Key(Key::Down));
+        assert_eq!(prompt.buffers[0].edit_position, 7);
+        prompt.handle_event(Event::Key(Key::Down));
+        assert!(matches!(prompt.selected, Selection::Button(0)));
+    }
+
+    fn popped(data: &str) -> String {
+        let mut buffer = EditBuffer::new(data.to_owned());
+        buffer.pop_word();
+        assert_eq!(buffer.edit_position, buffer.data.len());
+        buffer.data
+    }
+
+    fn rows(rows: &[(usize, &str)]) -> Vec<(usize, String)> {
+        rows.iter()
+            .map(|(i, text)| (*i, text.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn wrap_rows_breaks_between_words() {
+        assert_eq!(
+            wrap_rows("hello there world", 11),
+            rows(&[(0, "hello there"), (12, "world")])
+        );
+        assert_eq!(wrap_rows("a\nb", 10), rows(&[(0, "a"), (2, "b")]));
+    }
+
+    #[test]
+    fn wrap_rows_breaks_long_words() {
+        assert_eq!(
+            wrap_rows("abcdefghij", 4),
+            rows(&[(0, "abcd"), (4, "efgh"), (8, "ij")])
+        );
+        assert_eq!(
+            wrap_rows("hi abcdef", 4),
+            rows(&[(0, "hi "), (3, "abcd"), (7, "ef")])
+        );
+    }
+
+    #[test]
+    fn wrap_rows_measures_wide_graphemes() {
+        // each is two columns, so only two fit in five
+        assert_eq!(wrap_rows("日本語", 5), rows(&[(0, "日本"), (6, "語")]));
+        assert_eq!(wrap_rows("a👨‍👩‍👧b", 3), rows(&[(0, "a👨‍👩‍👧"), (19, "b")]));
+        // too wide for a row at all, so it goes on its own rather than looping forever
+        assert_eq!(wrap_rows("日本", 1), rows(&[(0, "日"), (3, "本")]));
+    }
+
+    #[test]
+    fn pop_word_stops_at_word_boundary() {
+        assert_eq!(popped("hello there"), "hello ");
+        assert_eq!(popped("hello there  "), "hello ");
+        assert_eq!(popped("one"), "");
+        assert_eq!(popped(""), "");
+    }
+
+    #[test]
+    fn pop_word_takes_whole_graphemes() {
+        assert_eq!(popped("hi 👨‍👩‍👧 e\u{301}"), "hi 👨‍👩‍👧 ");
+        assert_eq!(popped("hi 日本語"), "hi ");
+    }
+
+    #[test]
+    fn pop_word_leaves_no_blank_line() {
+        assert_eq!(popped("first\n   second"), "first\n");
+        assert_eq!(popped("first\n"), "");
     }
 }
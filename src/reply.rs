@@ -0,0 +1,187 @@
+use crate::api;
+use crate::drawing::truncate;
+use crate::gui::{CommandError, Gui};
+use crate::prompt::EditBuffer;
+use crate::server::HistoryRequest;
+use crate::Mode;
+
+/// How much of the message being replied to goes in the reference to it
+const SNIPPET_WIDTH: usize = 40;
+/// How many earlier messages to load at a time when looking for one to jump to
+pub const JUMP_BATCH: u32 = 100;
+/// How many batches to load before giving up on finding it
+pub const MAX_JUMP_BATCHES: u32 = 10;
+
+/// A reply, worked out from the first line of a message, which looks like ">>1234 Start of the
+/// original…". It's plain text, so it still makes sense to people using other clients.
+pub struct Reply<'a> {
+    pub original: i64, // uuid of the message it's replying to
+    pub snippet: &'a str,
+    pub body: &'a str, // everything after the first line
+}
+
+pub fn parse(content: &str) -> Option<Reply<'_>> {
+    let (first, body) = content.split_once('\n').unwrap_or((content, ""));
+    let rest = first.strip_prefix(">>")?;
+    let (uuid, snippet) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    // parse() would also take a sign, but ">>+1" and ">>-1" are more likely to be text
+    if !uuid.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(Reply {
+        original: uuid.parse().ok()?,
+        snippet: snippet.trim_end(),
+        body,
+    })
+}
+
+/// The first line of a message with something in it, cut short to `width` columns,
+/// leaving out what it's replying to if it's a reply itself
+pub fn snippet(content: &str, width: usize) -> String {
+    let body = parse(content).map(|reply| reply.body).unwrap_or(content);
+    let line = body.lines().find(|line| !line.trim().is_empty());
+    truncate(line.unwrap_or("").trim(), width)
+}
+
+/// The line a reply to `message` starts with
+pub fn reference(message: &api::Message) -> String {
+    format!(
+        ">>{} {}\n",
+        message.uuid,
+        snippet(&message.content, SNIPPET_WIDTH)
+    )
+}
+
+impl Gui {
+    /// Start a reply to the selected message, by putting a reference to it at the start of the input box
+    pub fn reply(&mut self) {
+        if let Err(CommandError(e)) = self.try_reply() {
+            self.send_system(&e);
+        }
+    }
+
+    fn try_reply(&mut self) -> Result<(), CommandError> {
+        if self.mode == Mode::EditMessage {
+            return Err(CommandError("Finish editing the message first".into()));
+        }
+        if self.selected_message.is_none() {
+            return Err(CommandError("No message selected".into()));
+        }
+        let reference = reference(&self.get_selected_message()?.message);
+        // anything already typed is kept, but not a reference to some other message
+        let body = parse(&self.buffer.data)
+            .map(|reply| reply.body)
+            .unwrap_or(&self.buffer.data);
+        self.buffer = EditBuffer::new(format!("{}{}", reference, body));
+        self.selected_message = None;
+        Ok(())
+    }
+
+    /// Select the message the selected reply is replying to, loading earlier messages until it's
+    /// found if it's from before the ones that are loaded
+    pub async fn jump_to_original(&mut self) {
+        if let Err(CommandError(e)) = self.try_jump_to_original().await {
+            self.send_system(&e);
+        }
+    }
+
+    async fn try_jump_to_original(&mut self) -> Result<(), CommandError> {
+        if self.selected_message.is_none() {
+            return Err(CommandError("No message selected".into()));
+        }
+        let message = &self.get_selected_message()?.message;
+        let Some(reply) = parse(&message.content) else {
+            return Err(CommandError("The selected message isn't a reply".into()));
+        };
+        let (original, channel) = (reply.original, message.channel_uuid);
+        let net = self.servers[self.curr_server.unwrap()] // unwrap: checked by get_selected_message
+            .network
+            .as_mut()
+            .unwrap(); // unwrap: so is this
+        if net.quoted.get(&original).is_some_and(Option::is_none) {
+            return Err(CommandError(
+                "The message being replied to couldn't be found".into(),
+            ));
+        }
+        let loaded = net
+            .loaded_messages
+            .iter()
+            .any(|m| m.message.uuid == original);
+        let oldest = net.loaded_messages.first().map(|m| m.message.uuid);
+        // everything since the oldest loaded message is loaded, so it would have been found
+        if !loaded && oldest.is_some_and(|oldest| oldest < original) {
+            return Err(CommandError(
+                "The message being replied to isn't in this channel".into(),
+            ));
+        }
+        net.jump_to = Some((original, 1));
+        if !loaded {
+            net.request_history(HistoryRequest::Messages, JUMP_BATCH, channel, oldest)
+                .await
+                .map_err(|e| CommandError(format!("Couldn't load earlier messages: {}", e)))?;
+        }
+        self.finish_jump();
+        Ok(())
+    }
+
+    /// Select the message being jumped to, if it's been loaded by now
+    pub fn finish_jump(&mut self) {
+        let Some(Ok(net)) = self.curr_server.map(|idx| &mut self.servers[idx].network) else {
+            return;
+        };
+        let Some((target, _)) = net.jump_to else {
+            return;
+        };
+        let Some(idx) = net
+            .loaded_messages
+            .iter()
+            .position(|m| m.message.uuid == target)
+        else {
+            return;
+        };
+        net.jump_to = None;
+        let from_bottom = net.loaded_messages.len() - idx;
+        self.selected_message = Some(from_bottom);
+        // scrolled so it's the last message on screen, drawing stops it going past the top
+        self.scroll = 1 - from_bottom as isize;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(content: &str) -> Option<(i64, &str, &str)> {
+        parse(content).map(|reply| (reply.original, reply.snippet, reply.body))
+    }
+
+    #[test]
+    fn parses_references() {
+        assert_eq!(parsed(">>42 hello…\nbody"), Some((42, "hello…", "body")));
+        assert_eq!(parsed(">>42"), Some((42, "", "")));
+        assert_eq!(
+            parsed(">>42 snippet \r\nbody"),
+            Some((42, "snippet", "body"))
+        );
+        assert_eq!(parsed(">>42\r\nbody"), Some((42, "", "body")));
+    }
+
+    #[test]
+    fn ignores_malformed_headers() {
+        assert_eq!(parsed(">>"), None);
+        assert_eq!(parsed(">> 42"), None);
+        assert_eq!(parsed(">>abc text"), None);
+        assert_eq!(parsed(">>42abc text"), None);
+        assert_eq!(parsed(">>+42"), None);
+        assert_eq!(parsed(">>-42"), None);
+        assert_eq!(parsed(">>99999999999999999999"), None);
+        assert_eq!(parsed("> >42"), None);
+        assert_eq!(parsed("text\n>>42"), None);
+    }
+
+    #[test]
+    fn snippet_skips_the_reference() {
+        assert_eq!(snippet(">>1 old\n\n  new line  \nmore", 40), "new line");
+        assert_eq!(snippet("a long first line", 6), "a lon…");
+    }
+}
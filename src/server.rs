@@ -1,13 +1,22 @@
 use crate::api::{self, Channel, Request, Response, User};
-use crate::LocalMessage;
+use crate::drawing::truncate;
+use crate::images::{ImageCache, Preview};
+use crate::markdown::{self, MarkdownLine, MessageStyles};
+use crate::mentions::mentions;
+use crate::reply;
+use crate::styled::{Span, StyledLine};
+use crate::template::{Template, Values};
+use crate::unicode::{self, fmt_width};
+use crate::{LocalMessage, NotificationRule};
 use base64::prelude::*;
-use fmtstring::{FmtChar, FmtString};
+use fmtstring::FmtString;
 use native_tls::TlsConnector;
 use notify_rust::{Notification, Timeout};
 use serde::ser::{Serialize, SerializeStruct, Serializer};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io::Write;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
 use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
@@ -15,6 +24,7 @@ use tokio::io::{ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::broadcast::Receiver;
 use tokio_native_tls::TlsStream;
+use unicode_segmentation::UnicodeSegmentation;
 
 type SocketStream = TlsStream<TcpStream>;
 
@@ -52,30 +62,143 @@ pub struct Peer {
 impl Peer {
     fn from_user(user: User) -> Self {
         let pfp_bytes = BASE64_STANDARD.decode(user.pfp).unwrap();
-        let img = image::load_from_memory(&pfp_bytes)
-            .unwrap()
-            .resize_exact(14, 16, image::imageops::FilterType::Triangle)
-            .into_rgb8();
-
-        let pfp = dct_tiv::textify_dct(
-            &img,
-            &dct_tiv::DEFAULT_DCT_MATRICIES,
-            &dct_tiv::DEFAULT_PALETTE,
-        )
-        .into_iter()
-        .next()
-        .unwrap();
         Self {
             uuid: user.uuid,
             name: user.name,
-            pfp, // TODO assert len == 1
+            pfp: pfp_from_image(&pfp_bytes).unwrap(),
         }
     }
 }
 
+/// Turn the bytes of an image file into the small block-character avatar shown next to messages
+pub fn pfp_from_image(bytes: &[u8]) -> Option<FmtString> {
+    let img = image::load_from_memory(bytes)
+        .ok()?
+        .resize_exact(14, 16, image::imageops::FilterType::Triangle)
+        .into_rgb8();
+
+    dct_tiv::textify_dct(
+        &img,
+        &dct_tiv::DEFAULT_DCT_MATRICIES,
+        &dct_tiv::DEFAULT_PALETTE,
+    )
+    .into_iter()
+    .next() // TODO assert len == 1
+}
+
+/// Whether a strftime-style format can be used for message timestamps.
+/// chrono panics when displaying an invalid format, so check before using one.
+pub fn is_valid_time_format(format: &str) -> bool {
+    !chrono::format::StrftimeItems::new(format).any(|item| item == chrono::format::Item::Error)
+}
+
+/// When a message was sent, in the user's own timezone
+pub fn local_time(date: i32) -> Option<chrono::DateTime<chrono::Local>> {
+    chrono::DateTime::from_timestamp(date as i64, 0).map(|dt| dt.with_timezone(&chrono::Local))
+}
+
+/// Most earlier versions of a message to remember, dropping the oldest past that
+const MAX_EDIT_VERSIONS: usize = 20;
+
+/// How far back messages say how long ago they were sent instead of the time
+const RELATIVE_SECS: i64 = 60 * 60;
+
+/// How long ago a message was sent, for messages from the last hour, or None for anything older
+fn relative_time(date: i32, now: i64) -> Option<String> {
+    // a little in the future is fine since the clocks might not quite agree, but any more and
+    // the actual time is more use
+    match now - date as i64 {
+        -59..=59 => Some("just now".to_owned()),
+        secs @ 60..RELATIVE_SECS => Some(format!("{} min ago", secs / 60)),
+        _ => None,
+    }
+}
+
+/// Whether `msg` should share a header with `prev`, the message before it: they're from the same
+/// person, close enough together, and on the same day, so there's no date separator between them,
+/// and `msg` isn't a reply
+fn continues(prev: &api::Message, msg: &api::Message, window: i64) -> bool {
+    let gap = msg.date as i64 - prev.date as i64;
+    window > 0
+        && prev.author_uuid == msg.author_uuid
+        && reply::parse(&msg.content).is_none() // the quote above it would split it off anyway
+        && (0..=window).contains(&gap)
+        && local_time(prev.date).map(|dt| dt.date_naive())
+            == local_time(msg.date).map(|dt| dt.date_naive())
+}
+
+/// Everything from the settings that affects how a message is laid out
+#[derive(Clone, PartialEq)]
+pub struct MessageFormat {
+    pub width: usize,
+    pub time_format: String,
+    pub relative_to: Option<i64>, // the current minute, if recent messages say how long ago they were sent
+    pub group_window: i64,        // in seconds, for messages from one person sharing a header
+    pub raw_markdown: bool,       // show the source instead of styling it
+    pub template: Template,
+    pub styles: MessageStyles,
+    pub images: Option<ImageCache>, // None if previews are turned off
+    pub own_uuid: Option<i64>, // who we are in the server, for picking out messages that mention us
+    pub own_name: Option<String>,
+}
+
+/// Whether two logins are the same account, given the uuid and username of each if they're known.
+/// Uuids are compared when both have one, and usernames otherwise.
+pub fn same_account(a: (Option<i64>, Option<&str>), b: (Option<i64>, Option<&str>)) -> bool {
+    match (a, b) {
+        ((Some(x), _), (Some(y), _)) => x == y,
+        ((_, Some(x)), (_, Some(y))) => x == y,
+        _ => false,
+    }
+}
+
+/// Stable handle for a server, used to route packets from its network thread.
+/// Unlike the remote address this is unique even if two entries point at the same host.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ServerId(usize);
+
+impl ServerId {
+    fn next() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        ServerId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// The content of a message after markdown and syntax highlighting. It doesn't depend on the
+/// width, so it's kept around to make rebuilding the lines after a resize cheap.
+struct StyledContent {
+    source: String,
+    raw_markdown: bool,
+    styles: MessageStyles,
+    lines: Vec<MarkdownLine>,
+    links: Vec<String>, // the URLs in it, in the order they first appear
+}
+
 pub struct LoadedMessage {
-    pub lines: Vec<FmtString>,
+    pub lines: Vec<StyledLine>,
     pub message: api::Message,
+    pub grouped: bool, // carries on from the message before, so it has no avatar or name
+    pub quote: Option<Quote>,
+    pub mentions_us: bool, // someone else's message with our name in it
+    styled: Option<StyledContent>,
+}
+
+/// What a reply shows of the message it's replying to
+#[derive(Clone, PartialEq)]
+pub struct Quote {
+    pub name: String,
+    pub snippet: String,
+}
+
+/// Why a History request was sent. History responses don't say which request they're for, so
+/// this relies on the server answering every request, errors included, in the order they were
+/// sent: each response goes with the oldest request still waiting. If one ever went unanswered,
+/// the responses after it would be taken for the wrong thing, which is kept harmless by checking
+/// them: originals are looked for by uuid, and messages from another channel are dropped.
+#[derive(Clone, Copy, PartialEq)]
+pub enum HistoryRequest {
+    Messages,      // messages to show in the channel, older than any that are loaded
+    Original(i64), // the message with this uuid, which a reply is replying to
 }
 
 pub struct OnlineServer {
@@ -83,17 +206,25 @@ pub struct OnlineServer {
     pub channels: Vec<Channel>,
     pub curr_channel: Option<usize>,
     pub peers: HashMap<i64, Peer>,
+    pub edit_history: HashMap<i64, Vec<String>>, // earlier contents of messages by uuid, oldest first
+    pub quoted: HashMap<i64, Option<api::Message>>, // messages replied to that aren't loaded, None if they couldn't be found
+    pub history_requests: VecDeque<HistoryRequest>, // sent but not answered yet, oldest first
+    pub jump_to: Option<(i64, u32)>, // message to select once it's loaded, and how many batches have been loaded looking for it
+    pub nick: Option<String>, // what we're called here right now, which `Server::uname` might not be
     pub write_half: WriteHalf<SocketStream>,
-    pub remote_addr: SocketAddr,
 }
 
 pub struct Server {
+    pub id: ServerId,
     pub ip: String,
     pub port: u16,
     pub name: Option<String>,
     pub uuid: Option<i64>,
     pub uname: Option<String>,
     pub passwd: String,
+    pub folder: Option<String>,
+    pub hide_images: bool, // don't show previews of images in messages
+    pub unread: usize,     // messages received since this server was last selected
     pub network: Result<OnlineServer, String>,
 }
 
@@ -101,70 +232,301 @@ impl LoadedMessage {
     pub fn from_message(
         message: api::Message,
         peers: &HashMap<i64, Peer>,
-        width: usize,
+        format: &MessageFormat,
     ) -> LoadedMessage {
         let mut this = LoadedMessage {
             lines: Vec::new(),
             message,
+            grouped: false,
+            quote: None,
+            mentions_us: false,
+            styled: None,
         };
-        this.rebuild(peers, width);
+        this.rebuild(peers, format);
         this
     }
 
-    pub fn rebuild(&mut self, peers: &HashMap<i64, Peer>, width: usize) {
-        let uname_str = peers
-            .get(&self.message.author_uuid)
-            .map(|x| x.name.as_str())
-            .unwrap_or("Unknown User");
-        let formatted = FmtString::from_str(&format!(" {}: {}", uname_str, self.message.content));
-        let datetime = chrono::DateTime::from_timestamp(self.message.date as i64, 0);
-        let date = FmtString::from_str(
-            &datetime
-                .map(|dt| dt.format("%H:%M").to_string())
-                .unwrap_or("??:??".to_string()),
-        );
-
-        let pfp = peers
-            .get(&self.message.author_uuid)
+    /// The URLs in the message, in the order they first appear
+    pub fn links(&self) -> &[String] {
+        self.styled.as_ref().map_or(&[], |styled| &styled.links)
+    }
+
+    /// Add a thumbnail under the message text for each image it links to
+    fn push_previews(
+        &mut self,
+        images: &ImageCache,
+        styles: &MessageStyles,
+        left_margin: &str,
+        line_width: usize,
+    ) {
+        let margin = unicode::width(left_margin);
+        for url in self.links().to_vec() {
+            let note = match images.get(&url) {
+                Some(Preview::Loaded(image)) => {
+                    let fits = image
+                        .thumbnail
+                        .iter()
+                        .all(|row| margin + fmt_width(row) <= line_width);
+                    if fits {
+                        for row in &image.thumbnail {
+                            let mut line = StyledLine::default();
+                            line.push_str("", left_margin);
+                            line.push_fmt(row);
+                            self.lines.push(line);
+                        }
+                        continue;
+                    }
+                    "[image too wide to preview, alt+i to view]".to_owned()
+                }
+                Some(Preview::Loading) => "[loading image…]".to_owned(),
+                Some(Preview::Failed(e)) => format!("[couldn't load image: {}]", e),
+                None => continue,
+            };
+            let mut line = StyledLine::default();
+            line.push_str("", left_margin);
+            line.push_str(
+                &styles.quote,
+                unicode::take_width(&note, line_width.saturating_sub(margin)),
+            );
+            self.lines.push(line);
+        }
+    }
+
+    pub fn rebuild(&mut self, peers: &HashMap<i64, Peer>, format: &MessageFormat) {
+        let width = format.width;
+        let styles = &format.styles;
+        let time_format = if is_valid_time_format(&format.time_format) {
+            format.time_format.as_str()
+        } else {
+            "%H:%M"
+        };
+        let up_to_date = self.styled.as_ref().is_some_and(|styled| {
+            styled.source == self.message.content
+                && styled.raw_markdown == format.raw_markdown
+                && styled.styles == *styles
+        });
+        if !up_to_date {
+            // a reply's first line is drawn as the quote above it instead
+            let content = &self.message.content;
+            let body = reply::parse(content).map_or(content.as_str(), |reply| reply.body);
+            let mut lines = if format.raw_markdown {
+                markdown::raw(body, styles)
+            } else {
+                markdown::render(body, styles)
+            };
+            if lines.is_empty() {
+                lines.push(MarkdownLine::default());
+            }
+            let mut links: Vec<String> = Vec::new();
+            let spans = lines.iter().flat_map(|line| &line.text);
+            for url in spans.filter_map(|span| span.link.as_deref()) {
+                if !links.iter().any(|link| link == url) {
+                    links.push(url.to_owned());
+                }
+            }
+            self.styled = Some(StyledContent {
+                source: self.message.content.clone(),
+                raw_markdown: format.raw_markdown,
+                styles: styles.clone(),
+                lines,
+                links,
+            });
+        }
+        self.mentions_us = format.own_uuid != Some(self.message.author_uuid)
+            && format
+                .own_name
+                .as_deref()
+                .is_some_and(|name| mentions(&self.message.content, name));
+        let content = &self.styled.as_ref().unwrap().lines; // unwrap: just set above if it wasn't already
+        let relative = format
+            .relative_to
+            .and_then(|now| relative_time(self.message.date, now));
+        let time = relative.unwrap_or_else(|| {
+            local_time(self.message.date)
+                .map(|dt| dt.format(time_format).to_string())
+                .unwrap_or("??:??".to_string())
+        });
+        let peer = peers.get(&self.message.author_uuid);
+        let pfp = peer
             .map(|x| x.pfp.clone())
             .unwrap_or(FmtString::from_str("  "));
+        let values = Values {
+            time: &time,
+            avatar: &pfp,
+            nick: peer.map(|x| x.name.as_str()).unwrap_or("Unknown User"),
+            edited: self.message.edited,
+        };
 
-        let left_margin = pfp.len() + 1;
-        self.lines = vec![pfp];
-        for c in formatted.into_iter() {
-            // unwraps are ok because we start with at least 1 element
-            let curr = self.lines.last().unwrap();
-            if c.ch == '\n' || curr.len() >= width - 1 - date.len() {
-                self.lines
-                    .push(FmtString::from_str(&" ".repeat(left_margin)))
+        let template = &format.template;
+        let right = template.right(&values, styles);
+        let after = template.after(&values, styles);
+        let line_width = match right.width() {
+            0 => width,
+            right_width => width.saturating_sub(1 + right_width),
+        };
+        let first_line = template.header(&values, styles, self.grouped);
+        // wrapped rows line up under the content, unless that would leave it too squashed
+        let left_margin = " ".repeat(first_line.width().min(line_width / 2));
+        self.lines = vec![first_line];
+        for (idx, line) in content.iter().enumerate() {
+            if idx > 0 {
+                let mut row = StyledLine::default();
+                row.push_str("", &left_margin);
+                self.lines.push(row);
             }
-            let curr = self.lines.last_mut().unwrap();
-            if c.ch != '\n' {
-                curr.push(c);
+            let after: &[Span] = if idx == content.len() - 1 {
+                &after
+            } else {
+                &[]
+            };
+            Wrapper::new(&mut self.lines, line, &left_margin, line_width).push_line(after);
+        }
+        if let Some(ref images) = format.images {
+            self.push_previews(images, styles, &left_margin, line_width);
+        }
+        if right.width() > 0 {
+            self.lines[0].pad("", width.saturating_sub(right.width()));
+            self.lines[0].extend(right);
+        }
+        if let Some(reply) = reply::parse(&self.message.content) {
+            // until the original is loaded, the start of it from the reply itself will do
+            let quote = match self.quote {
+                Some(ref quote) => format!("↱ {}: {}", quote.name, quote.snippet),
+                None => format!("↱ {}", reply.snippet),
+            };
+            let mut row = StyledLine::default();
+            row.push_str("", &left_margin);
+            let room = line_width.saturating_sub(row.width());
+            row.push_str(&styles.reply, &truncate(&quote, room));
+            self.lines.insert(0, row);
+        }
+    }
+}
+
+/// Lays out one line of a message's content in rows, breaking them between words where it can
+struct Wrapper<'a> {
+    rows: &'a mut Vec<StyledLine>, // starting with the row the line goes on the end of
+    line: &'a MarkdownLine,
+    margin: &'a str, // what the rows the line wraps onto start with, before its indent
+    width: usize,
+    start: usize,    // how wide the last row was before any of the line went on it
+    first_row: bool, // whether it's still on the row it started on
+}
+
+impl<'a> Wrapper<'a> {
+    /// Start laying out `line` on the end of the last of `rows`, which there has to be at least one of
+    fn new(
+        rows: &'a mut Vec<StyledLine>,
+        line: &'a MarkdownLine,
+        margin: &'a str,
+        width: usize,
+    ) -> Self {
+        Wrapper {
+            start: rows.last().map(StyledLine::width).unwrap_or(0),
+            first_row: true,
+            rows,
+            line,
+            margin,
+            width,
+        }
+    }
+
+    fn push_line(mut self, after: &[Span]) {
+        // the prefix goes on with the first word, so a bullet point is never left on its own
+        let mut word: Vec<(&Span, &str)> = self
+            .line
+            .prefix
+            .iter()
+            .flat_map(|span| span.text.graphemes(true).map(move |g| (span, g)))
+            .collect();
+        for span in self.line.text.iter().chain(after) {
+            for grapheme in span.text.graphemes(true) {
+                if grapheme.chars().all(char::is_whitespace) {
+                    self.push_word(&mut word);
+                    self.push_space(span, grapheme);
+                } else {
+                    word.push((span, grapheme));
+                }
             }
         }
-        while self.lines[0].len() < width - date.len() {
-            self.lines[0].push(FmtChar {
-                ch: ' ',
-                fg: fmtstring::Colour::None,
-                bg: fmtstring::Colour::None,
-            });
+        self.push_word(&mut word);
+        self.pad();
+    }
+
+    fn last(&self) -> &StyledLine {
+        self.rows.last().unwrap() // unwrap: there's always at least one, see new()
+    }
+
+    fn last_mut(&mut self) -> &mut StyledLine {
+        self.rows.last_mut().unwrap() // unwrap: same as above
+    }
+
+    fn fits(&self, width: usize) -> bool {
+        self.last().width() + width <= self.width
+    }
+
+    /// Fill the rest of the last row, for code blocks
+    fn pad(&mut self) {
+        if let Some(ref fill) = self.line.fill {
+            let width = self.width;
+            self.last_mut().pad(fill, width);
+        }
+    }
+
+    fn new_row(&mut self) {
+        self.pad();
+        let mut row = StyledLine::default();
+        row.push_str("", self.margin);
+        for indent in &self.line.indent {
+            row.push_str(&indent.style, &indent.text);
+        }
+        self.start = row.width();
+        self.first_row = false;
+        self.rows.push(row);
+    }
+
+    /// Put a word on the last row, or on a new one if it doesn't fit. Words too long for a row of
+    /// their own, like URLs, are broken wherever they reach the end.
+    fn push_word(&mut self, word: &mut Vec<(&Span, &str)>) {
+        let width: usize = word.iter().map(|(_, g)| unicode::grapheme_width(g)).sum();
+        let row_is_empty = self.last().width() <= self.start;
+        if !row_is_empty && !self.fits(width) {
+            self.new_row();
+        }
+        for (span, grapheme) in word.drain(..) {
+            // one wider than a whole row goes where it is, rather than after an empty row
+            let row_is_empty = self.last().width() <= self.start;
+            if !row_is_empty && !self.fits(unicode::grapheme_width(grapheme)) {
+                self.new_row();
+            }
+            self.last_mut()
+                .push_link(&span.style, grapheme, span.link.as_deref());
         }
-        for c in date.into_iter() {
-            self.lines[0].push(c);
+    }
+
+    /// Spaces go on the end of the row, or end it if there's no room. They're left off the start
+    /// of the rows the line wraps onto, but not the first, so indented code keeps its indentation.
+    fn push_space(&mut self, span: &Span, grapheme: &str) {
+        let row_is_empty = self.last().width() <= self.start;
+        if !self.fits(unicode::grapheme_width(grapheme)) {
+            self.new_row();
+        } else if self.first_row || !row_is_empty {
+            self.last_mut().push_str(&span.style, grapheme);
         }
     }
 }
 
 impl Serialize for Server {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut state = serializer.serialize_struct("Server", 6)?;
+        let mut state = serializer.serialize_struct("Server", 8)?;
         state.serialize_field("name", &self.name)?;
         state.serialize_field("ip", &self.ip)?;
         state.serialize_field("port", &self.port)?;
         state.serialize_field("uuid", &self.uuid)?;
         state.serialize_field("uname", &self.uname)?;
         state.serialize_field("idx", &0)?; // TODO temp
+        state.serialize_field("folder", &self.folder)?;
+        state.serialize_field("hide_images", &self.hide_images)?;
         state.end()
     }
 }
@@ -220,21 +582,128 @@ impl OnlineServer {
         self.write_half.write_request(request).await
     }
 
+    /// Work out again which messages share a header with the one before, after messages have been
+    /// added or removed, rebuilding any that have changed
+    pub fn regroup(&mut self, format: &MessageFormat) {
+        for idx in 0..self.loaded_messages.len() {
+            let grouped = idx > 0
+                && continues(
+                    &self.loaded_messages[idx - 1].message,
+                    &self.loaded_messages[idx].message,
+                    format.group_window,
+                );
+            let message = &mut self.loaded_messages[idx];
+            if message.grouped != grouped {
+                message.grouped = grouped;
+                message.rebuild(&self.peers, format);
+            }
+        }
+    }
+
+    /// Rebuild the messages that might show a relative time when measured from `since` or
+    /// later, which are all near the end since they're in order
+    pub fn rebuild_recent(&mut self, since: i64, format: &MessageFormat) {
+        for message in self.loaded_messages.iter_mut().rev() {
+            if since - message.message.date as i64 >= RELATIVE_SECS {
+                break;
+            }
+            message.rebuild(&self.peers, format);
+        }
+    }
+
+    /// Ask for up to `num` messages from `channel` from before `before_message`, or the latest
+    /// ones if it's None, saying why so that the response can be dealt with properly
+    pub async fn request_history(
+        &mut self,
+        purpose: HistoryRequest,
+        num: u32,
+        channel: i64,
+        before_message: Option<i64>,
+    ) -> Result<usize, std::io::Error> {
+        self.history_requests.push_back(purpose);
+        self.write(Request::History {
+            num,
+            channel,
+            before_message,
+        })
+        .await
+    }
+
+    /// Work out again what each reply shows of the message it's replying to, rebuilding any that change
+    pub fn resolve_replies(&mut self, format: &MessageFormat) {
+        let by_uuid: HashMap<i64, usize> = self
+            .loaded_messages
+            .iter()
+            .enumerate()
+            .map(|(idx, loaded)| (loaded.message.uuid, idx))
+            .collect();
+        for idx in 0..self.loaded_messages.len() {
+            let Some(reply) = reply::parse(&self.loaded_messages[idx].message.content) else {
+                continue;
+            };
+            let original = match by_uuid.get(&reply.original) {
+                Some(&original) => Some(&self.loaded_messages[original].message),
+                None => self.quoted.get(&reply.original).and_then(Option::as_ref),
+            };
+            let quote = original.map(|original| Quote {
+                name: self
+                    .peers
+                    .get(&original.author_uuid)
+                    .map(|peer| peer.name.clone())
+                    .unwrap_or("Unknown User".into()),
+                snippet: reply::snippet(&original.content, usize::MAX),
+            });
+            let message = &mut self.loaded_messages[idx];
+            if message.quote != quote {
+                message.quote = quote;
+                message.rebuild(&self.peers, format);
+            }
+        }
+    }
+
+    /// Ask for the messages that replies are replying to, if they aren't loaded and haven't been
+    /// asked for already. Messages before one with a uuid one higher should start with it, even if
+    /// there isn't one with that uuid.
+    async fn fetch_originals(&mut self) -> Result<(), std::io::Error> {
+        let mut wanted = Vec::new();
+        for loaded in &self.loaded_messages {
+            let Some(reply) = reply::parse(&loaded.message.content) else {
+                continue;
+            };
+            let original = reply.original;
+            let known = self.quoted.contains_key(&original)
+                || wanted.iter().any(|(uuid, _)| *uuid == original)
+                || self
+                    .history_requests
+                    .contains(&HistoryRequest::Original(original))
+                || self
+                    .loaded_messages
+                    .iter()
+                    .any(|m| m.message.uuid == original);
+            if !known {
+                wanted.push((original, loaded.message.channel_uuid));
+            }
+        }
+        for (uuid, channel) in wanted {
+            let purpose = HistoryRequest::Original(uuid);
+            self.request_history(purpose, 1, channel, Some(uuid + 1))
+                .await?;
+        }
+        Ok(())
+    }
+
     pub fn get_channel(&self, uuid: i64) -> Option<&Channel> {
         self.channels.iter().find(|c| c.uuid == uuid)
     }
 
     pub async fn switch_channel(&mut self, idx: usize) {
         self.loaded_messages.clear();
+        self.edit_history.clear(); // only kept for loaded messages
         self.curr_channel = Some(idx);
+        self.jump_to = None;
         let channel = self.channels[idx].uuid;
         let res = self
-            .write_half
-            .write_request(api::Request::History {
-                num: 100,
-                channel,
-                before_message: None,
-            })
+            .request_history(HistoryRequest::Messages, 100, channel, None)
             .await;
         if let Err(_) = res {
             // *s = (*s).to_offline(e.to_string());
@@ -252,6 +721,7 @@ impl Server {
         tx: Sender<LocalMessage>,
         mut cancel: Receiver<()>,
     ) -> Self {
+        let server_id = ServerId::next();
         let network = match TcpStream::connect((ip.as_str(), port)).await {
             Ok(socket) => {
                 let addr = socket.peer_addr().unwrap(); // TODO figure out if this unwrap is ever gonna cause a problem
@@ -269,7 +739,7 @@ impl Server {
                         let net_tx = tx.clone();
                         tokio::spawn(async move {
                             tokio::select! {
-                                _ = Self::run_network(net_tx, read_half, addr) => {},
+                                _ = Self::run_network(net_tx, read_half, server_id, addr) => {},
                                 _ = cancel.recv() => {}, // we need to shut down the connection rn
                             }
                         });
@@ -278,8 +748,12 @@ impl Server {
                             channels: Vec::new(),
                             curr_channel: None,
                             peers: HashMap::new(),
+                            edit_history: HashMap::new(),
+                            quoted: HashMap::new(),
+                            history_requests: VecDeque::new(),
+                            jump_to: None,
+                            nick: None,
                             write_half,
-                            remote_addr: addr,
                         })
                     }
                     Err(e) => Err(format!("Failed to init TLS encryption: {:?}", e)),
@@ -294,16 +768,42 @@ impl Server {
         };
 
         Self {
+            id: server_id,
             ip,
             port,
             name: None,
             uuid,
             uname,
             passwd,
+            folder: None,
+            hide_images: false,
+            unread: 0,
             network,
         }
     }
 
+    /// How to lay out this server's messages, which leaves out image previews if they're turned off for it
+    pub fn message_format(&self, format: &MessageFormat) -> MessageFormat {
+        let mut format = format.clone();
+        if self.hide_images {
+            format.images = None;
+        }
+        format.own_uuid = self.uuid;
+        format.own_name = self.nick().map(str::to_owned);
+        format
+    }
+
+    /// What we're called in this server, as far as we know
+    pub fn nick(&self) -> Option<&str> {
+        match self.network {
+            Ok(OnlineServer {
+                nick: Some(ref nick),
+                ..
+            }) => Some(nick),
+            _ => self.uname.as_deref(),
+        }
+    }
+
     pub fn to_offline(&mut self, offline_reason: String) {
         self.network = Err(offline_reason);
     }
@@ -312,21 +812,17 @@ impl Server {
         self.network.is_ok()
     }
 
-    // pub fn add_message(&mut self, content: FmtString, author: u64) {
-    //     //todo compile regex once and use it mulyiple times, this is slow as fuck
-    //     //let url_regex = r#"^https?://(www\.)?[-a-zA-Z0-9@:%._\+~#=]{1,256}\.[a-zA-Z0-9()]{1,6}\b([-a-zA-Z0-9()@:%_\+.~#?&//=]*)$"#;
-    //     //let compiled_regex = regex::Regex::new(url_regex).unwrap();
-    //     //if compiled_regex.is_match(&content) {
-    //     //this is gonna be fuckin rough... brace yourselves
-    //     //}
-
-    //     match self {
-    //         Self::Online {
-    //             loaded_messages, ..
-    //         } => loaded_messages.push(DisplayMessage::User { content, author }),
-    //         Self::Offline { .. } => panic!("Try to add a message to an offline server!"),
-    //     }
-    // }
+    /// Whether this entry refers to the same account on the same server as the given details.
+    /// Hostnames are compared case-insensitively, and accounts as in `same_account`.
+    pub fn is_same_server(&self, ip: &str, port: u16, id: &Identification) -> bool {
+        let (uuid, uname) = match id {
+            Identification::Uuid(uuid) => (Some(*uuid), None),
+            Identification::Username(uname) => (None, Some(uname.as_str())),
+        };
+        self.ip.eq_ignore_ascii_case(ip)
+            && self.port == port
+            && same_account((self.uuid, self.uname.as_deref()), (uuid, uname))
+    }
 
     // pub async fn update_metadata(&mut self, meta: User) -> std::result::Result<(), std::io::Error> {
     //     self.write(Request::NickRequest { nick: meta.name }).await?;
@@ -338,11 +834,10 @@ impl Server {
     async fn run_network(
         tx: std::sync::mpsc::Sender<LocalMessage>,
         stream: ReadHalf<SocketStream>,
-        // addr: (String, u16),
+        id: ServerId,
         addr: SocketAddr,
     ) {
         let mut reader = tokio::io::BufReader::new(stream);
-        // let addr_hash = addr.hash();
 
         loop {
             let mut result: String = "".to_string();
@@ -355,7 +850,7 @@ impl Server {
                         .unwrap();
                         return;
                     }
-                    tx.send(LocalMessage::Network(result, addr)).unwrap();
+                    tx.send(LocalMessage::Network(result, id)).unwrap();
                 }
                 Err(e) => {
                     tx.send(LocalMessage::NetError(format!(
@@ -372,36 +867,58 @@ impl Server {
     fn format_message(
         msg: api::Message,
         peers: &HashMap<i64, Peer>,
-        message_width: usize,
+        format: &MessageFormat,
     ) -> LoadedMessage {
-        LoadedMessage::from_message(msg, peers, message_width)
+        LoadedMessage::from_message(msg, peers, format)
     }
 
     pub async fn handle_network_packet(
         &mut self,
         response: Response,
-        message_width: usize,
+        format: &MessageFormat,
+        notifications: NotificationRule,
         inactivity_time: Duration,
         we_are_the_selected_server: bool,
     ) -> Result<(), String> {
         use api::Status::{self, *};
         use Response::*;
+        let format = &self.message_format(format);
         let net = self
             .network
             .as_mut()
             .expect("Network packet recv'd for offline server??");
+        // every History response answers the oldest request that's still waiting, whatever its status
+        let history_request = match response {
+            History { .. } => net.history_requests.pop_front(),
+            _ => None,
+        };
         match response {
             GetMetadata { data, .. } => {
+                let mut renamed = false;
                 for elem in data.unwrap() {
                     let peer = Peer::from_user(elem);
-                    if self.uuid.is_some_and(|uuid| uuid == peer.uuid) {
+                    if self.uuid == Some(peer.uuid) {
                         // info about ourselves that we may not know yet!
                         if self.uname.is_none() {
                             self.uname = Some(peer.name.clone());
                         }
+                        // or a new name from another client, which mentions have to use
+                        if net.nick.as_ref() != Some(&peer.name) {
+                            net.nick = Some(peer.name.clone());
+                            renamed = true;
+                        }
                     }
                     net.peers.insert(peer.uuid, peer);
                 }
+                if renamed {
+                    let format = MessageFormat {
+                        own_name: net.nick.clone(),
+                        ..format.clone()
+                    };
+                    for message in &mut net.loaded_messages {
+                        message.rebuild(&net.peers, &format);
+                    }
+                }
             }
             Register {
                 uuid: new_uuid,
@@ -457,12 +974,65 @@ impl Server {
             GetName { data, status: Ok } => self.name = Some(data.unwrap()),
             ListChannels { data, status: Ok } => net.channels = data.unwrap(),
             History { data, status: Ok } => {
-                let new_msgs = data
-                    .unwrap()
-                    .into_iter()
-                    .map(|message| Self::format_message(message, &net.peers, message_width))
-                    .collect::<Vec<_>>(); // TODO get rid of this collect: borrow checker complains, tho
-                net.loaded_messages.extend(new_msgs);
+                let messages = data.unwrap();
+                if let Some(HistoryRequest::Original(uuid)) = history_request {
+                    let original = messages.into_iter().find(|m| m.uuid == uuid);
+                    net.quoted.insert(uuid, original);
+                    net.resolve_replies(format);
+                } else {
+                    let found_none = messages.is_empty();
+                    // a batch for a channel that was switched away from while it was on its way
+                    let current = net.curr_channel.map(|idx| net.channels[idx].uuid);
+                    let elsewhere = messages.iter().any(|m| Some(m.channel_uuid) != current);
+                    let new_msgs = messages
+                        .into_iter()
+                        .filter(|_| !elsewhere)
+                        .map(|message| Self::format_message(message, &net.peers, format))
+                        .collect::<Vec<_>>(); // TODO get rid of this collect: borrow checker complains, tho
+
+                    // older messages, loaded to find one being jumped to, go before the ones there already
+                    let older = new_msgs.last().is_some_and(|last| {
+                        net.loaded_messages
+                            .first()
+                            .is_some_and(|first| last.message.uuid < first.message.uuid)
+                    });
+                    if older {
+                        net.loaded_messages.splice(0..0, new_msgs);
+                    } else {
+                        net.loaded_messages.extend(new_msgs);
+                    }
+                    net.regroup(format);
+                    net.resolve_replies(format);
+                    net.fetch_originals().await.map_err(|e| e.to_string())?;
+
+                    // Gui::finish_jump selects the message once it's loaded, until then keep going back
+                    if let Some((target, batches)) = net.jump_to {
+                        let found = net.loaded_messages.iter().any(|m| m.message.uuid == target);
+                        // uuids go up over time, so once the loaded messages start before it, it's
+                        // not in this channel at all, and a reply can claim any uuid it likes
+                        let passed = net
+                            .loaded_messages
+                            .first()
+                            .is_some_and(|oldest| oldest.message.uuid < target);
+                        if !found && !elsewhere {
+                            if found_none || passed || batches >= reply::MAX_JUMP_BATCHES {
+                                net.jump_to = None;
+                                return Err("Couldn't find the message being replied to".into());
+                            }
+                            net.jump_to = Some((target, batches + 1));
+                            let oldest = &net.loaded_messages[0].message; // there's at least one, since some were just loaded
+                            let (channel, before) = (oldest.channel_uuid, Some(oldest.uuid));
+                            net.request_history(
+                                HistoryRequest::Messages,
+                                reply::JUMP_BATCH,
+                                channel,
+                                before,
+                            )
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        }
+                    }
+                }
             }
             Content { message, .. } => {
                 let in_current_channel = net
@@ -472,13 +1042,30 @@ impl Server {
                     net.loaded_messages.push(Self::format_message(
                         message.clone(),
                         &net.peers,
-                        message_width,
+                        format,
                     ));
+                    net.regroup(format);
+                    net.resolve_replies(format);
+                    net.fetch_originals().await.map_err(|e| e.to_string())?;
+                }
+                if !we_are_the_selected_server || !in_current_channel {
+                    self.unread += 1;
                 }
-                if !we_are_the_selected_server
+                let unfocussed = !we_are_the_selected_server
                     || !in_current_channel
-                    || inactivity_time > Duration::from_secs(10)
-                {
+                    || inactivity_time > Duration::from_secs(10);
+                let mentions_us = self.uuid != Some(message.author_uuid)
+                    && self
+                        .uname
+                        .as_deref()
+                        .is_some_and(|name| mentions(&message.content, name));
+                let should_notify = match notifications {
+                    NotificationRule::Always => true,
+                    NotificationRule::Unfocussed => unfocussed,
+                    NotificationRule::Mentions => unfocussed && mentions_us,
+                    NotificationRule::Never => false,
+                };
+                if should_notify {
                     // ignore errors, for now
                     let _ = Notification::new()
                         .summary(&format!(
@@ -508,12 +1095,21 @@ impl Server {
             } => {
                 for msg in &mut net.loaded_messages {
                     if msg.message.uuid == message {
-                        msg.message.content = new_content;
+                        let old = std::mem::replace(&mut msg.message.content, new_content);
+                        let earlier = net.edit_history.entry(message).or_default();
+                        if earlier.len() >= MAX_EDIT_VERSIONS {
+                            earlier.remove(0);
+                        }
+                        earlier.push(old);
                         msg.message.edited = true;
-                        msg.rebuild(&net.peers, message_width);
+                        msg.rebuild(&net.peers, format);
                         break;
                     }
                 }
+                // it might have become a reply, or stopped being one, or be quoted by one
+                net.regroup(format);
+                net.resolve_replies(format);
+                net.fetch_originals().await.map_err(|e| e.to_string())?;
             }
 
             MessageDeleted {
@@ -522,6 +1118,9 @@ impl Server {
             } => {
                 net.loaded_messages
                     .retain(|msg| msg.message.uuid != message);
+                net.edit_history.remove(&message);
+                net.regroup(format);
+                net.resolve_replies(format);
             }
 
             _ => {
@@ -539,3 +1138,42 @@ impl Server {
         Result::Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of each row `text` is wrapped onto, starting on a row that already has `start` in it
+    fn wrap(start: &str, text: &str, width: usize) -> Vec<String> {
+        let mut rows = vec![StyledLine::default()];
+        rows[0].push_str("", start);
+        let line = MarkdownLine {
+            text: vec![Span::new("", text)],
+            ..Default::default()
+        };
+        Wrapper::new(&mut rows, &line, "", width).push_line(&[]);
+        rows.iter()
+            .map(|row| row.spans().iter().map(|span| span.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn breaks_between_words() {
+        assert_eq!(wrap("", "one two three", 8), ["one two ", "three"]);
+        assert_eq!(wrap("> ", "one two", 6), ["> one ", "two"]);
+    }
+
+    #[test]
+    fn breaks_over_long_words() {
+        assert_eq!(wrap("", "abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("", "hi abcdefgh", 4), ["hi ", "abcd", "efgh"]);
+    }
+
+    #[test]
+    fn measures_wide_graphemes() {
+        assert_eq!(wrap("", "日本語", 5), ["日本", "語"]);
+        assert_eq!(wrap("", "ab 👨‍👩‍👧", 4), ["ab ", "👨‍👩‍👧"]);
+        // wider than a whole row, so it overflows rather than being split or looping
+        assert_eq!(wrap("", "日本", 1), ["日", "本"]);
+    }
+}
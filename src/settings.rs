@@ -0,0 +1,359 @@
+use crate::drawing::{join_theme_errors, Theme};
+use crate::gui::{Gui, MIN_SIDEBAR_WIDTH};
+use crate::prompt::{Prompt, PromptEvent, PromptField};
+use crate::server::{is_valid_time_format, pfp_from_image};
+use crate::template::Template;
+use crate::{LocalMessage, Mode, NotificationRule, Settings};
+use base64::prelude::*;
+use fmtstring::FmtString;
+use termion::event::Event;
+
+/// A day, past which grouping messages together would hide more than it tidies up
+pub const MAX_GROUP_MINUTES: u16 = 24 * 60;
+
+/// Everything the settings screen needs besides the prompt itself
+pub struct SettingsScreen {
+    saved: Settings, // what to go back to if the changes are cancelled
+    nick: String,    // what we were called in the current server when the screen was opened
+    avatar_path: String,
+    avatar_error: Option<String>,
+    new_pfp: Option<(String, FmtString)>, // base64 like Settings::pfp, and how it looks
+    saved_pfp: Option<FmtString>,
+}
+
+impl SettingsScreen {
+    /// The avatar to show in the preview
+    pub fn pfp(&self) -> Option<&FmtString> {
+        self.new_pfp
+            .as_ref()
+            .map(|(_, pfp)| pfp)
+            .or(self.saved_pfp.as_ref())
+    }
+}
+
+impl Gui {
+    pub fn open_settings(&mut self) {
+        let themes = Theme::available();
+        let notification_names = NotificationRule::ALL
+            .iter()
+            .map(|rule| rule.name().to_owned())
+            .collect();
+        let settings = &self.settings;
+        // the nick is per server, unlike the username that logs in to the sync server
+        let nick = self
+            .curr_server
+            .and_then(|idx| self.servers[idx].nick())
+            .unwrap_or(&settings.uname)
+            .to_owned();
+        let prompt = Prompt::new(
+            "Settings",
+            vec![
+                PromptField::String {
+                    name: "Nickname",
+                    default: Some(nick.clone()),
+                    max_width: 48,
+                },
+                PromptField::String {
+                    name: "Avatar image",
+                    default: None,
+                    max_width: 48,
+                },
+                PromptField::Choice {
+                    name: "Theme",
+                    default: themes
+                        .iter()
+                        .position(|name| *name == settings.theme)
+                        .unwrap_or(0),
+                    options: themes,
+                },
+                PromptField::I64 {
+                    name: "Sidebar width",
+                    default: Some(settings.sidebar_width as i64),
+                },
+                PromptField::Choice {
+                    name: "Notifications",
+                    options: notification_names,
+                    default: NotificationRule::ALL
+                        .iter()
+                        .position(|rule| *rule == settings.notifications)
+                        .unwrap_or(0),
+                },
+                PromptField::String {
+                    name: "Time format",
+                    default: Some(settings.time_format.clone()),
+                    max_width: 48,
+                },
+                PromptField::String {
+                    name: "Message template",
+                    default: Some(settings.message_template.clone()),
+                    max_width: 48,
+                },
+                PromptField::Bool {
+                    name: "Relative times",
+                    default: settings.relative_times,
+                },
+                PromptField::I64 {
+                    name: "Group within (min)",
+                    default: Some(settings.group_minutes as i64),
+                },
+                PromptField::Bool {
+                    name: "Raw markdown",
+                    default: settings.raw_markdown,
+                },
+                PromptField::String {
+                    name: "Sync server IP",
+                    default: Some(settings.sync_ip.clone()),
+                    max_width: 48,
+                },
+                PromptField::U16 {
+                    name: "Sync server port",
+                    default: Some(settings.sync_port),
+                },
+            ],
+            vec!["Save", "Cancel"],
+        );
+
+        let saved_pfp = BASE64_STANDARD
+            .decode(&settings.pfp)
+            .ok()
+            .and_then(|bytes| pfp_from_image(&bytes));
+        self.settings_screen = Some(SettingsScreen {
+            saved: settings.clone(),
+            nick,
+            avatar_path: String::new(),
+            avatar_error: None,
+            new_pfp: None,
+            saved_pfp,
+        });
+        self.prompt = Some(prompt);
+        self.mode = Mode::Settings;
+    }
+
+    pub async fn settings_event(&mut self, event: Event) {
+        let Some(mut prompt) = self.prompt.take() else {
+            return;
+        };
+        match prompt.handle_event(event) {
+            Some(PromptEvent::ButtonPressed("Save")) => {
+                let valid = prompt.validate();
+                if self.preview_settings(&mut prompt) && valid {
+                    self.save_settings(&prompt).await;
+                    return;
+                }
+            }
+            Some(PromptEvent::ButtonPressed("Cancel")) => {
+                self.cancel_settings();
+                return;
+            }
+            Some(PromptEvent::ButtonPressed(_)) => unreachable!(),
+            None => {
+                self.preview_settings(&mut prompt);
+            }
+        }
+        self.prompt = Some(prompt);
+    }
+
+    /// Apply what's in the prompt straight away so the user can see what it looks like.
+    /// Returns false if any of it is unusable, in which case the field says why.
+    fn preview_settings(&mut self, prompt: &mut Prompt) -> bool {
+        let mut ok = true;
+
+        let theme = prompt.get_choice("Theme").unwrap().to_owned();
+        if theme != self.settings.theme {
+            match Theme::new(&theme, self.theme.sidebar_width) {
+                Ok((new_theme, errors)) => {
+                    if !errors.is_empty() {
+                        self.send_system(&format!(
+                            "Problems with theme '{}': {}",
+                            theme,
+                            join_theme_errors(&errors)
+                        ));
+                    }
+                    self.theme = new_theme;
+                    self.settings.theme = theme;
+                }
+                Err(e) => {
+                    self.send_system(&format!("Couldn't load theme '{}': {}", theme, e));
+                    ok = false;
+                }
+            }
+        }
+
+        match prompt.get_i64("Sidebar width") {
+            Ok(width) if (MIN_SIDEBAR_WIDTH as i64..=u16::MAX as i64).contains(&width) => {
+                if width as usize != self.settings.sidebar_width {
+                    self.set_sidebar_width(width as usize);
+                }
+            }
+            Ok(_) => {
+                let error = format!("Must be from {} to {}", MIN_SIDEBAR_WIDTH, u16::MAX);
+                prompt.set_error("Sidebar width", Some(error));
+                ok = false;
+            }
+            Err(_) => {
+                let error = "Must be a whole number".to_owned();
+                prompt.set_error("Sidebar width", Some(error));
+                ok = false;
+            }
+        }
+
+        let notifications = prompt.get_choice("Notifications").unwrap();
+        if let Some(rule) = NotificationRule::ALL
+            .iter()
+            .copied()
+            .find(|rule| rule.name() == notifications)
+        {
+            self.settings.notifications = rule;
+        }
+
+        let time_format = prompt.get_str("Time format").unwrap();
+        if !time_format.is_empty() && is_valid_time_format(time_format) {
+            time_format.clone_into(&mut self.settings.time_format);
+        } else {
+            let error = "Not a valid time format, try %H:%M or %-I:%M %p".to_owned();
+            prompt.set_error("Time format", Some(error));
+            ok = false;
+        }
+
+        let template = prompt.get_str("Message template").unwrap();
+        match Template::parse(template) {
+            Ok(parsed) => {
+                template.clone_into(&mut self.settings.message_template);
+                self.template = parsed;
+            }
+            Err(e) => {
+                prompt.set_error("Message template", Some(e));
+                ok = false;
+            }
+        }
+
+        self.settings.relative_times = prompt.get_bool("Relative times").unwrap();
+        match prompt.get_i64("Group within (min)") {
+            Ok(minutes) if (0..=MAX_GROUP_MINUTES as i64).contains(&minutes) => {
+                self.settings.group_minutes = minutes as u16;
+            }
+            _ => {
+                let error = format!("Must be from 0 (off) to {}", MAX_GROUP_MINUTES);
+                prompt.set_error("Group within (min)", Some(error));
+                ok = false;
+            }
+        }
+        self.settings.raw_markdown = prompt.get_bool("Raw markdown").unwrap();
+
+        if prompt.get_str("Nickname").unwrap().is_empty() {
+            prompt.set_error("Nickname", Some("A nickname is required".to_owned()));
+            ok = false;
+        }
+        if prompt.get_str("Sync server IP").unwrap().is_empty() {
+            prompt.set_error("Sync server IP", Some("An IP is required".to_owned()));
+            ok = false;
+        }
+
+        // only bother reading the image again if the path has changed
+        let avatar_path = prompt.get_str("Avatar image").unwrap();
+        let screen = self.settings_screen.as_mut().unwrap();
+        if avatar_path != screen.avatar_path {
+            avatar_path.clone_into(&mut screen.avatar_path);
+            screen.new_pfp = None;
+            screen.avatar_error = None;
+            if !avatar_path.is_empty() {
+                match std::fs::read(avatar_path) {
+                    Ok(bytes) => match pfp_from_image(&bytes) {
+                        Some(pfp) => screen.new_pfp = Some((BASE64_STANDARD.encode(bytes), pfp)),
+                        None => {
+                            screen.avatar_error = Some("Not an image that can be loaded".into())
+                        }
+                    },
+                    Err(e) => screen.avatar_error = Some(e.to_string()),
+                }
+            }
+        }
+        if screen.avatar_error.is_some() {
+            prompt.set_error("Avatar image", screen.avatar_error.clone());
+            ok = false;
+        }
+        ok
+    }
+
+    /// Send the settings that are kept on the sync server there, in the background
+    pub fn upload_sync_data(&mut self) {
+        // the sync server connection blocks, so don't hold up the UI while it happens
+        let tx = self.tx.clone();
+        let settings = self.settings.clone();
+        self.uploaded_folders.clone_from(&settings.folders);
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = crate::save_sync_data(
+                &settings.sync_ip,
+                settings.sync_port,
+                &settings.uname,
+                &settings.passwd,
+                &settings.pfp,
+                settings.sync_folders.then_some(&settings.folders),
+            ) {
+                let _ = tx.send(LocalMessage::NetError(format!(
+                    "Couldn't save settings to the sync server: {}",
+                    e
+                )));
+            }
+        });
+    }
+
+    /// Folders are collapsed and expanded too often to upload every time, so any changes that
+    /// haven't been sent yet go when the client quits. This blocks, since it's the last thing
+    /// that happens, and there's nowhere left to show an error.
+    pub fn upload_folders_on_quit(&self) {
+        let settings = &self.settings;
+        if settings.sync_folders && settings.folders != self.uploaded_folders {
+            let _ = crate::save_sync_data(
+                &settings.sync_ip,
+                settings.sync_port,
+                &settings.uname,
+                &settings.passwd,
+                &settings.pfp,
+                Some(&settings.folders),
+            );
+        }
+    }
+
+    async fn save_settings(&mut self, prompt: &Prompt) {
+        let screen = self.settings_screen.take().unwrap();
+        let nick = prompt.get_str("Nickname").unwrap();
+        if nick != screen.nick {
+            self.set_nick(nick).await;
+        }
+        if let Some((pfp, _)) = screen.new_pfp {
+            for server in &mut self.servers {
+                if let Ok(ref mut net) = server.network {
+                    // ignore errors, the server will just keep showing the old pfp
+                    let _ = net
+                        .write(crate::api::Request::Pfp { data: pfp.clone() })
+                        .await;
+                }
+            }
+            self.settings.pfp = pfp;
+        }
+        prompt
+            .get_str("Sync server IP")
+            .unwrap()
+            .clone_into(&mut self.settings.sync_ip);
+        self.settings.sync_port = prompt.get_u16("Sync server port").unwrap();
+        self.save_config();
+        self.upload_sync_data();
+
+        self.mode = Mode::Messages;
+        self.send_system("Settings saved");
+    }
+
+    fn cancel_settings(&mut self) {
+        let screen = self.settings_screen.take().unwrap();
+        if screen.saved.theme != self.settings.theme {
+            if let Ok((theme, _)) = Theme::new(&screen.saved.theme, self.theme.sidebar_width) {
+                self.theme = theme;
+            }
+        }
+        self.settings = screen.saved;
+        self.template = Template::parse(&self.settings.message_template).unwrap_or_default();
+        self.fit_sidebar();
+        self.mode = Mode::Messages;
+    }
+}
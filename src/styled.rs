@@ -0,0 +1,121 @@
+use crate::colour::downsample_escapes;
+use crate::unicode::{self, fmt_width};
+use fmtstring::FmtString;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A run of text that all looks the same. `style` is the escape codes that switch to it from plain text.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Span {
+    pub style: String,
+    pub text: String,
+    pub link: Option<String>, // the URL it's a hyperlink to
+}
+
+impl Span {
+    pub fn new(style: &str, text: &str) -> Self {
+        Self {
+            style: style.to_owned(),
+            text: text.to_owned(),
+            link: None,
+        }
+    }
+
+    pub fn link(style: &str, url: &str) -> Self {
+        Self {
+            link: Some(url.to_owned()),
+            ..Self::new(style, url)
+        }
+    }
+}
+
+/// One row of a message, ready to be drawn
+#[derive(Clone, Default, Debug)]
+pub struct StyledLine {
+    spans: Vec<Span>,
+    width: usize,
+}
+
+impl StyledLine {
+    /// How many columns the line takes up
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    pub fn push_str(&mut self, style: &str, text: &str) {
+        self.push_link(style, text, None);
+    }
+
+    /// Add text that's part of a hyperlink to `link`, or not part of one if it's None
+    pub fn push_link(&mut self, style: &str, text: &str, link: Option<&str>) {
+        if text.is_empty() {
+            return;
+        }
+        self.width += unicode::width(text);
+        match self.spans.last_mut() {
+            Some(last) if last.style == style && last.link.as_deref() == link => {
+                last.text.push_str(text)
+            }
+            _ => self.spans.push(Span {
+                link: link.map(str::to_owned),
+                ..Span::new(style, text)
+            }),
+        }
+    }
+
+    /// Add text that's already been coloured by something else, like an avatar from dct-tiv
+    pub fn push_fmt(&mut self, text: &FmtString) {
+        self.width += fmt_width(text);
+        self.spans
+            .push(Span::new("", &downsample_escapes(text.to_str())));
+    }
+
+    /// Add everything from `other` on the end
+    pub fn extend(&mut self, other: StyledLine) {
+        for span in other.spans {
+            self.push_link(&span.style, &span.text, span.link.as_deref());
+        }
+    }
+
+    /// Fill the line with spaces in `style` until it's `width` columns wide
+    pub fn pad(&mut self, style: &str, width: usize) {
+        if self.width < width {
+            self.push_str(style, &" ".repeat(width - self.width));
+        }
+    }
+
+    /// The escape codes and text to draw the line, with `extra` added on top of every style,
+    /// e.g. bold for the selected message
+    pub fn render(&self, extra: &str) -> String {
+        let mut out = String::new();
+        for span in &self.spans {
+            out.push_str(termion::style::Reset.as_ref());
+            out.push_str(&span.style);
+            out.push_str(extra);
+            match span.link {
+                // there's nothing useful a terminal can do with a data URI
+                Some(ref url) if !url.starts_with("data:") => {
+                    out.push_str(&hyperlink(url));
+                    out.push_str(&span.text);
+                    out.push_str("\x1b]8;;\x1b\\");
+                }
+                _ => out.push_str(&span.text),
+            }
+        }
+        out.push_str(termion::style::Reset.as_ref());
+        out
+    }
+}
+
+/// The OSC 8 escape code that makes the text after it a hyperlink to `url`.
+/// A URL that wraps onto more than one row gets the same id on each of them,
+/// so the terminal knows they're all one link.
+fn hyperlink(url: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    format!("\x1b]8;id={:x};{}\x1b\\", hasher.finish(), url)
+}
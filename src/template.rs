@@ -0,0 +1,298 @@
+use crate::drawing::truncate;
+use crate::markdown::MessageStyles;
+use crate::styled::{Span, StyledLine};
+use crate::unicode::{self, fmt_width};
+use fmtstring::FmtString;
+
+/// How messages look unless the user says otherwise: avatar and name, then the message,
+/// with the time at the right-hand end of the first row
+pub const DEFAULT_TEMPLATE: &str = "{avatar} {nick}: {content}{edited}{>}{time}";
+
+/// Something that's filled in from the message
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Field {
+    Time,
+    Avatar,
+    Nick,
+    Edited, // " (edited)", or nothing if it hasn't been
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Align {
+    Left,
+    Right,
+    Centre,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Piece {
+    Text(String),
+    Field(Field, Option<(Align, usize)>), // padded or cut short to that many columns
+}
+
+/// The layout of a message, parsed from something like "[{time}] <{nick:>10}> {content}{edited}".
+/// Whatever comes before `{content}` starts the first row, and the rows the content wraps onto
+/// are indented to line up under it. Whatever comes after it follows on from the end of the
+/// content, and anything after `{>}` goes at the right-hand end of the first row.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Template {
+    header: Vec<Piece>,
+    after: Vec<Piece>,
+    right: Vec<Piece>,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template::parse(DEFAULT_TEMPLATE).unwrap() // unwrap: it's a valid template
+    }
+}
+
+/// What the fields of a template are filled in with for one message
+pub struct Values<'a> {
+    pub time: &'a str,
+    pub avatar: &'a FmtString,
+    pub nick: &'a str,
+    pub edited: bool,
+}
+
+/// Parse `name` or `name:>10` from inside a pair of braces
+fn parse_field(inside: &str) -> Result<Piece, String> {
+    let (name, spec) = match inside.split_once(':') {
+        Some((name, spec)) => (name, Some(spec)),
+        None => (inside, None),
+    };
+    let field = match name {
+        "time" => Field::Time,
+        "avatar" => Field::Avatar,
+        "nick" => Field::Nick,
+        "edited" => Field::Edited,
+        _ => {
+            return Err(format!(
+                "{{{}}} isn't something that can be filled in",
+                name
+            ))
+        }
+    };
+    let Some(spec) = spec else {
+        return Ok(Piece::Field(field, None));
+    };
+    let (align, digits) = match spec.chars().next() {
+        Some('<') => (Align::Left, &spec[1..]),
+        Some('>') => (Align::Right, &spec[1..]),
+        Some('^') => (Align::Centre, &spec[1..]),
+        _ => (Align::Left, spec),
+    };
+    let width = digits
+        .parse()
+        .map_err(|_| format!("{{{}}} needs a width like :>10", inside))?;
+    Ok(Piece::Field(field, Some((align, width))))
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, String> {
+        // split into pieces, with None where {content} and {>} are
+        let mut pieces: Vec<Option<Piece>> = Vec::new();
+        let mut markers = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut inside = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(ch) => inside.push(ch),
+                            None => return Err("There's a { without a } to close it".into()),
+                        }
+                    }
+                    if !text.is_empty() {
+                        pieces.push(Some(Piece::Text(std::mem::take(&mut text))));
+                    }
+                    if inside == "content" || inside == ">" {
+                        if markers.contains(&inside) {
+                            return Err(format!("{{{}}} can only be used once", inside));
+                        }
+                        markers.push(inside);
+                        pieces.push(None);
+                    } else {
+                        pieces.push(Some(parse_field(&inside)?));
+                    }
+                }
+                '}' => return Err("Write }} for a } on its own".into()),
+                _ => text.push(ch),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Some(Piece::Text(text)));
+        }
+
+        let mut parts: Vec<Vec<Piece>> = pieces
+            .split(Option::is_none)
+            .map(|part| part.iter().flatten().cloned().collect())
+            .collect();
+        match markers.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["content"] => parts.push(Vec::new()), // nothing on the right
+            ["content", ">"] => (),
+            [">", "content"] => return Err("{content} has to come before {>}".into()),
+            _ => return Err("There has to be a {content}".into()),
+        }
+        // unwraps: there are three parts now, one either side of each marker
+        let right = parts.pop().unwrap();
+        let after = parts.pop().unwrap();
+        let header = parts.pop().unwrap();
+        if after
+            .iter()
+            .any(|piece| matches!(piece, Piece::Field(Field::Avatar, _)))
+        {
+            return Err("{avatar} can only go before {content} or after {>}".into());
+        }
+        Ok(Template {
+            header,
+            after,
+            right,
+        })
+    }
+
+    /// Start the first row of a message. Messages grouped under the one before have spaces
+    /// instead of everything from the avatar or name onwards, so their content still lines up.
+    pub fn header(&self, values: &Values, styles: &MessageStyles, grouped: bool) -> StyledLine {
+        let mut line = StyledLine::default();
+        let mut blank = false;
+        for piece in &self.header {
+            blank |= grouped && matches!(piece, Piece::Field(Field::Avatar | Field::Nick, _));
+            if blank {
+                let mut filler = StyledLine::default();
+                push_piece(&mut filler, piece, values, styles);
+                line.push_str("", &" ".repeat(filler.width()));
+            } else {
+                push_piece(&mut line, piece, values, styles);
+            }
+        }
+        line
+    }
+
+    /// What goes on the end of the content, to be wrapped along with it
+    pub fn after(&self, values: &Values, styles: &MessageStyles) -> Vec<Span> {
+        let mut line = StyledLine::default();
+        for piece in &self.after {
+            push_piece(&mut line, piece, values, styles);
+        }
+        line.spans().to_vec()
+    }
+
+    /// What goes at the right-hand end of the first row
+    pub fn right(&self, values: &Values, styles: &MessageStyles) -> StyledLine {
+        let mut line = StyledLine::default();
+        for piece in &self.right {
+            push_piece(&mut line, piece, values, styles);
+        }
+        line
+    }
+}
+
+fn push_piece(line: &mut StyledLine, piece: &Piece, values: &Values, styles: &MessageStyles) {
+    let (field, size) = match piece {
+        Piece::Text(text) => return line.push_str(&styles.text, text),
+        Piece::Field(field, size) => (*field, *size),
+    };
+    let (style, text) = match field {
+        Field::Avatar => {
+            let (before, after) = padding(fmt_width(values.avatar), size);
+            line.push_str("", &before);
+            line.push_fmt(values.avatar);
+            line.push_str("", &after);
+            return;
+        }
+        Field::Time => ("", values.time),
+        Field::Nick => (styles.text.as_str(), values.nick),
+        Field::Edited if values.edited => (styles.edited.as_str(), " (edited)"),
+        Field::Edited => (styles.edited.as_str(), ""),
+    };
+    let text = match size {
+        Some((_, width)) => truncate(text, width),
+        None => text.to_owned(),
+    };
+    let (before, after) = padding(unicode::width(&text), size);
+    line.push_str("", &before);
+    line.push_str(style, &text);
+    line.push_str("", &after);
+}
+
+/// The spaces to go either side of something `width` columns wide to line it up as asked
+fn padding(width: usize, size: Option<(Align, usize)>) -> (String, String) {
+    let Some((align, size)) = size else {
+        return (String::new(), String::new());
+    };
+    let spare = size.saturating_sub(width);
+    let before = match align {
+        Align::Left => 0,
+        Align::Right => spare,
+        Align::Centre => spare / 2,
+    };
+    (" ".repeat(before), " ".repeat(spare - before))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawing::Theme;
+
+    /// The text of a template's first row, without any styling
+    fn header(template: &str, nick: &str) -> String {
+        let avatar = FmtString::from_str("AV");
+        let values = Values {
+            time: "12:34",
+            avatar: &avatar,
+            nick,
+            edited: false,
+        };
+        let styles = MessageStyles::new(&Theme::fallback(0).messages);
+        Template::parse(template)
+            .unwrap()
+            .header(&values, &styles, false)
+            .spans()
+            .iter()
+            .map(|span| span.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn alignment_specs() {
+        assert_eq!(
+            Template::parse("{nick:>8}{content}").unwrap().header,
+            vec![Piece::Field(Field::Nick, Some((Align::Right, 8)))]
+        );
+        assert_eq!(
+            Template::parse("{nick:8}{content}").unwrap().header,
+            vec![Piece::Field(Field::Nick, Some((Align::Left, 8)))]
+        );
+        assert_eq!(header("<{nick:<6}>{content}", "bob"), "<bob   >");
+        assert_eq!(header("<{nick:>6}>{content}", "bob"), "<   bob>");
+        assert_eq!(header("<{nick:^6}>{content}", "bob"), "< bob  >");
+        assert_eq!(header("<{avatar:>4}>{content}", "bob"), "<  AV>");
+    }
+
+    #[test]
+    fn too_long_fields_are_cut_short() {
+        assert_eq!(header("{nick:>4}{content}", "someone"), "som…");
+        assert_eq!(header("{nick:^4}{content}", "日本語"), "日… "); // a wide character doesn't fit
+        assert_eq!(header("{nick:0}{content}", "bob"), "");
+    }
+
+    #[test]
+    fn bad_specs() {
+        assert!(Template::parse("{nick:>}{content}").is_err());
+        assert!(Template::parse("{nick:>x}{content}").is_err());
+        assert!(Template::parse("{nick:-3}{content}").is_err());
+        assert!(Template::parse("{colour:>3}{content}").is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use fmtstring::FmtString;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+const EMOJI_PRESENTATION: char = '\u{fe0f}';
+const REGIONAL_INDICATORS: std::ops::RangeInclusive<char> = '\u{1f1e6}'..='\u{1f1ff}';
+
+/// How many columns a grapheme cluster takes up on the terminal.
+/// Emoji sequences like 👩‍💻 or 👍🏽 are drawn as one emoji, not as each of their parts.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    let Some(first) = grapheme.chars().next() else {
+        return 0;
+    };
+    if grapheme.contains(EMOJI_PRESENTATION) || REGIONAL_INDICATORS.contains(&first) {
+        return 2; // e.g. ❤️, which is narrow without the selector, or a flag
+    }
+    match first.width() {
+        Some(width) if width > 0 => width,
+        _ => grapheme.width(), // a combining mark with nothing to combine with
+    }
+}
+
+/// How many columns `text` takes up on the terminal
+pub fn width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+/// The longest start of `text` that fits in `max` columns, without splitting a grapheme
+pub fn take_width(text: &str, max: usize) -> &str {
+    let mut used = 0;
+    for (idx, grapheme) in text.grapheme_indices(true) {
+        used += grapheme_width(grapheme);
+        if used > max {
+            return &text[..idx];
+        }
+    }
+    text
+}
+
+/// How many columns a formatted string takes up, ignoring its colours
+pub fn fmt_width(text: &FmtString) -> usize {
+    width(&text.clone().into_iter().map(|c| c.ch).collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grapheme_widths() {
+        assert_eq!(grapheme_width(""), 0);
+        assert_eq!(grapheme_width("a"), 1);
+        assert_eq!(grapheme_width("日"), 2);
+        assert_eq!(grapheme_width("e\u{301}"), 1);
+        // a ZWJ sequence is drawn as one emoji
+        assert_eq!(grapheme_width("👩\u{200d}💻"), 2);
+        assert_eq!(grapheme_width("👍🏽"), 2);
+        // VS16 makes a narrow symbol into a wide emoji
+        assert_eq!(grapheme_width("❤"), 1);
+        assert_eq!(grapheme_width("❤\u{fe0f}"), 2);
+        assert_eq!(grapheme_width("🇬🇧"), 2);
+        // a lone regional indicator still takes up two columns
+        assert_eq!(grapheme_width("🇬"), 2);
+        // a combining mark with nothing before it
+        assert_eq!(grapheme_width("\u{301}"), 0);
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(width("hello"), 5);
+        assert_eq!(width("日本語"), 6);
+        assert_eq!(width("a👩\u{200d}💻b"), 4);
+        assert_eq!(width("🇬🇧🇫🇷"), 4);
+        assert_eq!(width("cafe\u{301}"), 4);
+    }
+
+    #[test]
+    fn takes_whole_graphemes() {
+        assert_eq!(take_width("hello", 3), "hel");
+        assert_eq!(take_width("hello", 10), "hello");
+        assert_eq!(take_width("日本語", 3), "日");
+        assert_eq!(take_width("日本語", 4), "日本");
+        assert_eq!(take_width("a👩\u{200d}💻", 2), "a");
+        assert_eq!(take_width("a👩\u{200d}💻", 3), "a👩\u{200d}💻");
+        assert_eq!(take_width("🇬🇧🇫🇷", 3), "🇬🇧");
+        assert_eq!(take_width("e\u{301}x", 1), "e\u{301}");
+        assert_eq!(take_width("❤\u{fe0f}", 1), "");
+        assert_eq!(take_width("日", 0), "");
+    }
+}